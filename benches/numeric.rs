@@ -0,0 +1,48 @@
+//! Straight-line arithmetic on globals, the closest thing to a numeric loop until Lox here gets
+//! `while`. Only interpretation is timed, compiling is done up front.
+//!
+//! Run with `cargo bench --bench numeric`.
+
+use std::time::{Duration, Instant};
+
+use rlox::{
+    compile::Source,
+    vm::{InterpretMode, InterpretResult, VM},
+};
+
+const STATEMENTS: usize = 10_000;
+const RUNS: usize = 50;
+
+fn program() -> String {
+    let mut source = String::from("var x = 0; var y = 1;\n");
+    for _ in 0..STATEMENTS {
+        source.push_str("x = x + y * 2 - (y / 4 + -1) * 3;\n");
+    }
+    source
+}
+
+fn main() {
+    let source = program();
+    let mut vm = VM::new();
+    let mut total = Duration::ZERO;
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let chunk = Source(source.clone())
+            .compile("numeric", InterpretMode::Release, &mut vm.heap)
+            .expect("benchmark program compiles");
+        let start = Instant::now();
+        let result = vm.interpret(chunk, InterpretMode::Release);
+        let elapsed = start.elapsed();
+        assert!(matches!(result, InterpretResult::Ok));
+        total += elapsed;
+        best = best.min(elapsed);
+    }
+    let instructions = STATEMENTS * 16;
+    println!(
+        "numeric: {} runs, best {:?}, mean {:?}, {:.1} ns/instruction",
+        RUNS,
+        best,
+        total / RUNS as u32,
+        best.as_nanos() as f64 / instructions as f64
+    );
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use crate::tokens::Span;
+
+/// A name as written in the source, wherever it's declared, read or assigned to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+    pub line: i32,
+    pub column: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+    Nil,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    /// `+a`, which does nothing but is accepted anyway.
+    Plus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "!",
+            UnaryOp::Plus => "+",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    /// The line the expression's own instructions are attributed to: its operator's, or the
+    /// token's for a literal or a variable.
+    pub line: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Literal(Literal),
+    Variable(Identifier),
+    Assign {
+        name: Identifier,
+        value: Box<Expr>,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Grouping(Box<Expr>),
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+    /// `[a, b, c]`.
+    List(Vec<Expr>),
+    /// `{key: value, ...}`.
+    Map(Vec<(Expr, Expr)>),
+    /// `object[index]`.
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `object[index] = value`.
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    /// `object.name`, a member of a module imported with `as` or a method of a value.
+    Get {
+        object: Box<Expr>,
+        name: Identifier,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Print(Expr),
+    Expression(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    pub kind: DeclKind,
+    pub span: Span,
+    /// The line of the closing `;`, where defining, printing or popping happens.
+    pub line: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclKind {
+    Var {
+        name: Identifier,
+        /// The type after `name:`, which only `rlox check` looks at.
+        annotation: Option<Identifier>,
+        initializer: Option<Expr>,
+    },
+    /// `import "path";` brings what the module declares into scope, `import "path" as name;`
+    /// keeps it behind `name.`.
+    Import {
+        path: String,
+        alias: Option<Identifier>,
+    },
+    Stmt(Stmt),
+}
+
+// The S-expression dump behind `--dump-ast`: every node that isn't a leaf is `(head children...)`.
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Number(n) => write!(f, "{}", n),
+            Literal::Str(s) => write!(f, "{:?}", s),
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Literal(literal) => write!(f, "{}", literal),
+            ExprKind::Variable(name) => write!(f, "{}", name.name),
+            ExprKind::Assign { name, value } => write!(f, "(= {} {})", name.name, value),
+            ExprKind::Unary { op, operand } => write!(f, "({} {})", op.symbol(), operand),
+            ExprKind::Binary { op, left, right } => {
+                write!(f, "({} {} {})", op.symbol(), left, right)
+            }
+            ExprKind::Grouping(expr) => write!(f, "(group {})", expr),
+            ExprKind::Call { callee, arguments } => {
+                write!(f, "(call {}", callee)?;
+                for argument in arguments {
+                    write!(f, " {}", argument)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::List(items) => {
+                write!(f, "(list")?;
+                for item in items {
+                    write!(f, " {}", item)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Map(entries) => {
+                write!(f, "(map")?;
+                for (key, value) in entries {
+                    write!(f, " ({} {})", key, value)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Index { object, index } => write!(f, "(index {} {})", object, index),
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => write!(f, "(= (index {} {}) {})", object, index, value),
+            ExprKind::Get { object, name } => write!(f, "(. {} {})", object, name.name),
+        }
+    }
+}
+
+impl fmt::Display for Decl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            DeclKind::Var {
+                name,
+                annotation,
+                initializer,
+            } => {
+                match annotation {
+                    Some(annotation) => write!(f, "(var (: {} {})", name.name, annotation.name)?,
+                    None => write!(f, "(var {}", name.name)?,
+                }
+                if let Some(initializer) = initializer {
+                    write!(f, " {}", initializer)?;
+                }
+                write!(f, ")")
+            }
+            DeclKind::Import { path, alias } => match alias {
+                Some(alias) => write!(f, "(import {:?} as {})", path, alias.name),
+                None => write!(f, "(import {:?})", path),
+            },
+            DeclKind::Stmt(Stmt::Print(expr)) => write!(f, "(print {})", expr),
+            DeclKind::Stmt(Stmt::Expression(expr)) => write!(f, "(expr {})", expr),
+        }
+    }
+}
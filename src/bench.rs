@@ -0,0 +1,226 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use rlox::{
+    compile::{Session, Source},
+    json::Json,
+    vm::{InterpretMode, InterpretResult, VM},
+};
+
+const USAGE: &str = "Usage: rlox bench [--clox path/to/clox] [file.lox | dir]...";
+
+/// The book's benchmarks, which the suite should run, and what the language still lacks for each.
+/// The default report lists them so they don't go missing without a trace.
+const BLOCKED: &[(&str, &str)] = &[
+    ("fib", "functions and if"),
+    ("binary_trees", "classes, functions and loops"),
+    ("equality", "loops"),
+    ("string_concatenation", "loops"),
+    ("method_call", "classes and loops"),
+    ("zoo", "classes and loops"),
+    ("instantiation", "classes and loops"),
+    ("trees", "classes and loops"),
+];
+
+/// `rlox bench`: runs every benchmark script and prints one JSON report to stdout. Directories are
+/// searched for `.lox` files, and with no paths at all the `benches` directory is used, and the
+/// report also lists the benchmarks that are blocked. Output of the scripts themselves is
+/// discarded.
+pub fn bench(args: &[String]) -> Result<(), String> {
+    let mut clox = None;
+    let mut paths = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--clox" => match args.next() {
+                Some(path) => clox = Some(path.clone()),
+                None => return Err(USAGE.into()),
+            },
+            _ if arg.starts_with('-') => return Err(USAGE.into()),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let default_suite = paths.is_empty();
+    if default_suite {
+        paths.push(PathBuf::from("benches"));
+    }
+    let files = benchmark_files(&paths).map_err(|error| error.to_string())?;
+    let results = files
+        .iter()
+        .map(|file| {
+            let mut result = run_benchmark(file);
+            if let (Some(clox), Json::Object(fields)) = (&clox, &mut result) {
+                fields.push(("clox".into(), run_clox(clox, file)));
+            }
+            result
+        })
+        .collect::<Vec<_>>();
+    let mut report = vec![("benchmarks", Json::Array(results))];
+    if default_suite {
+        report.push(("blocked", blocked()));
+    }
+    println!("{}", Json::object(report));
+    Ok(())
+}
+
+fn benchmark_files(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut scripts = vec![];
+            for entry in fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry.extension().is_some_and(|ext| ext == "lox") {
+                    scripts.push(entry);
+                }
+            }
+            scripts.sort();
+            files.append(&mut scripts);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn blocked() -> Json {
+    let blocked = BLOCKED
+        .iter()
+        .map(|(name, needs)| {
+            Json::object([("name", Json::from(*name)), ("needs", Json::from(*needs))])
+        })
+        .collect();
+    Json::Array(blocked)
+}
+
+fn milliseconds(duration: Duration) -> Json {
+    Json::from(duration.as_secs_f64() * 1000.0)
+}
+
+/// Compiles and runs one script on a fresh VM. Only running is timed, and allocations made by the
+/// compiler for constants aren't counted.
+fn run_benchmark(path: &Path) -> Json {
+    let name = path
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().into());
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            return Json::object([
+                ("name", Json::from(name)),
+                ("status", Json::from("io_error")),
+                ("error", Json::from(error.to_string())),
+            ])
+        }
+    };
+    let mut vm = VM::new();
+    vm.output = Box::new(io::sink());
+    let mut session = Session::new(&vm, &[]);
+    let chunk =
+        match Source(source).try_compile(&path.to_string_lossy(), &mut vm.heap, &mut session) {
+            Ok(chunk) => chunk,
+            Err(errors) => {
+                return Json::object([
+                    ("name", Json::from(name)),
+                    ("status", Json::from("compile_error")),
+                    ("errors", Json::from(errors)),
+                ])
+            }
+        };
+    let allocations = vm.heap.allocations();
+    let instructions = vm.instructions;
+    let start = Instant::now();
+    let result = vm.interpret(chunk, InterpretMode::Release);
+    let elapsed = start.elapsed();
+    let status = match result {
+        InterpretResult::Ok => "ok",
+        InterpretResult::RuntimeError(_) => "runtime_error",
+        InterpretResult::Stopped => "stopped",
+    };
+    Json::object([
+        ("name", Json::from(name)),
+        ("status", Json::from(status)),
+        ("wall_time_ms", milliseconds(elapsed)),
+        ("instructions", Json::from(vm.instructions - instructions)),
+        (
+            "allocations",
+            Json::from(vm.heap.allocations() - allocations),
+        ),
+    ])
+}
+
+/// Wall time of the same script under another Lox implementation, for comparison.
+fn run_clox(clox: &str, path: &Path) -> Json {
+    let start = Instant::now();
+    let status = Command::new(clox)
+        .arg(path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let elapsed = start.elapsed();
+    match status {
+        Ok(status) => Json::object([
+            (
+                "status",
+                Json::from(if status.success() { "ok" } else { "error" }),
+            ),
+            ("wall_time_ms", milliseconds(elapsed)),
+        ]),
+        Err(error) => Json::object([
+            ("status", Json::from("io_error")),
+            ("error", Json::from(error.to_string())),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod test_bench {
+    use super::*;
+    use crate::test_util::script;
+
+    fn field<'a>(json: &'a Json, key: &str) -> Option<&'a Json> {
+        match json {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reports_instructions_and_allocations() {
+        let path = script("ok", "var a = \"x\"; print a + \"y\";");
+        let result = run_benchmark(&path);
+        assert_eq!(field(&result, "status"), Some(&Json::from("ok")));
+        // GetGlobal, Constant, Add, Print and Return, after Constant and DefineGlobal
+        assert_eq!(field(&result, "instructions"), Some(&Json::from(7usize)));
+        assert_eq!(field(&result, "allocations"), Some(&Json::from(1usize)));
+    }
+
+    #[test]
+    fn shipped_benchmarks_run() {
+        let files = benchmark_files(&[PathBuf::from("benches")]).unwrap();
+        assert!(!files.is_empty());
+        for path in files {
+            let result = run_benchmark(&path);
+            assert_eq!(
+                field(&result, "status"),
+                Some(&Json::from("ok")),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn reports_compile_errors() {
+        let path = script("broken", "print (1;");
+        let result = run_benchmark(&path);
+        assert_eq!(field(&result, "status"), Some(&Json::from("compile_error")));
+        assert!(
+            matches!(field(&result, "errors"), Some(Json::Array(errors)) if !errors.is_empty())
+        );
+    }
+}
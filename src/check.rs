@@ -0,0 +1,406 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    ast::{BinaryOp, Decl, DeclKind, Expr, ExprKind, Identifier, Literal, Stmt, UnaryOp},
+    compile::Source,
+    diagnostic::Diagnostic,
+    tokens::Span,
+};
+
+/// The static type of an expression. `Any` is what nothing is known about, and it's compatible
+/// with everything in both directions, which is what keeps unannotated code valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Any,
+    Number,
+    Str,
+    Bool,
+    Nil,
+    Function,
+    List,
+    Map,
+}
+
+impl Type {
+    /// The type an annotation names.
+    pub fn named(name: &str) -> Option<Type> {
+        match name {
+            "any" => Some(Type::Any),
+            "number" => Some(Type::Number),
+            "string" => Some(Type::Str),
+            "boolean" => Some(Type::Bool),
+            "nil" => Some(Type::Nil),
+            "function" => Some(Type::Function),
+            "list" => Some(Type::List),
+            "map" => Some(Type::Map),
+            _ => None,
+        }
+    }
+
+    fn accepts(self, other: Type) -> bool {
+        self == Type::Any || other == Type::Any || self == other
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Any => "any",
+            Type::Number => "number",
+            Type::Str => "string",
+            Type::Bool => "boolean",
+            Type::Nil => "nil",
+            Type::Function => "function",
+            Type::List => "list",
+            Type::Map => "map",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+struct Checker<'a> {
+    source: &'a str,
+    /// The type of every global seen so far. An annotated global keeps the type it's declared
+    /// with. One without an annotation has the type of the last value it was given, which is exact
+    /// as long as there's no control flow. Anything used before its declaration is `Any`.
+    globals: HashMap<String, Type>,
+    /// The globals declared with an annotation, which can only be assigned their type.
+    annotated: HashSet<String>,
+    /// How many arguments each native takes, until a declaration hides it.
+    arities: HashMap<String, usize>,
+    /// Mismatches between the types things have and the types they're used as.
+    errors: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, span: Span, message: String) {
+        let (line, column) = span.position(self.source);
+        self.errors.push(Diagnostic::error(message, line, column));
+    }
+
+    fn annotation(&mut self, annotation: &Identifier) -> Type {
+        match Type::named(&annotation.name) {
+            Some(ty) => ty,
+            None => {
+                let message = format!("Unknown type '{}'", annotation.name);
+                self.error(annotation.span, message);
+                Type::Any
+            }
+        }
+    }
+
+    fn declaration(&mut self, decl: &Decl) {
+        match &decl.kind {
+            DeclKind::Var {
+                name,
+                annotation,
+                initializer,
+            } => {
+                let value = match initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => Type::Nil,
+                };
+                let ty = match annotation {
+                    Some(annotation) => {
+                        self.annotated.insert(name.name.clone());
+                        self.annotation(annotation)
+                    }
+                    None => {
+                        self.annotated.remove(&name.name);
+                        value
+                    }
+                };
+                if !ty.accepts(value) {
+                    let message = format!(
+                        "'{}' is declared {} but starts out as {}",
+                        name.name, ty, value
+                    );
+                    self.error(name.span, message);
+                }
+                self.globals.insert(name.name.clone(), ty);
+                self.arities.remove(&name.name);
+            }
+            DeclKind::Import { .. } => {}
+            DeclKind::Stmt(Stmt::Print(expr) | Stmt::Expression(expr)) => {
+                self.expression(expr);
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Literal(Literal::Number(_)) => Type::Number,
+            ExprKind::Literal(Literal::Str(_)) => Type::Str,
+            ExprKind::Literal(Literal::Bool(_)) => Type::Bool,
+            ExprKind::Literal(Literal::Nil) => Type::Nil,
+            ExprKind::Variable(name) => self.global(name),
+            ExprKind::Assign { name, value } => {
+                let value = self.expression(value);
+                let ty = self.global(name);
+                if !self.annotated.contains(&name.name) {
+                    // Only known globals, since anything else could be defined later
+                    if self.globals.contains_key(&name.name) {
+                        self.globals.insert(name.name.clone(), value);
+                        self.arities.remove(&name.name);
+                    }
+                } else if !ty.accepts(value) {
+                    let message =
+                        format!("'{}' is {}, it can't be assigned {}", name.name, ty, value);
+                    self.error(expr.span, message);
+                }
+                value
+            }
+            ExprKind::Unary { op, operand } => {
+                let operand = self.expression(operand);
+                match op {
+                    UnaryOp::Negate if !Type::Number.accepts(operand) => {
+                        let message = format!("Operand of - must be a number, got {}", operand);
+                        self.error(expr.span, message);
+                        Type::Any
+                    }
+                    UnaryOp::Negate => Type::Number,
+                    UnaryOp::Not if matches!(operand, Type::Str | Type::Function) => {
+                        let message = format!("Operand of ! can't be a {}", operand);
+                        self.error(expr.span, message);
+                        Type::Bool
+                    }
+                    UnaryOp::Not => Type::Bool,
+                    UnaryOp::Plus => operand,
+                }
+            }
+            ExprKind::Binary { op, left, right } => {
+                let (left, right) = (self.expression(left), self.expression(right));
+                self.binary(expr, *op, left, right)
+            }
+            ExprKind::Grouping(inner) => self.expression(inner),
+            ExprKind::Call { callee, arguments } => {
+                let ty = self.expression(callee);
+                if !Type::Function.accepts(ty) {
+                    self.error(expr.span, format!("Can't call a {}", ty));
+                }
+                let arity = match &callee.kind {
+                    ExprKind::Variable(name) => self.arities.get(&name.name).copied(),
+                    _ => None,
+                };
+                if let Some(arity) = arity.filter(|arity| *arity != arguments.len()) {
+                    let message =
+                        format!("Expected {} arguments but got {}", arity, arguments.len());
+                    self.error(expr.span, message);
+                }
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                Type::Any
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+                Type::List
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    let key = self.expression(key);
+                    if matches!(key, Type::Function | Type::List | Type::Map) {
+                        self.error(expr.span, key_error(key));
+                    }
+                    self.expression(value);
+                }
+                Type::Map
+            }
+            ExprKind::Index { object, index } => {
+                self.index(expr, object, index);
+                Type::Any
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.index(expr, object, index);
+                self.expression(value)
+            }
+            // Modules are checked on their own, so what they export isn't known here
+            ExprKind::Get { .. } => Type::Any,
+        }
+    }
+
+    // What's in a list or a map isn't tracked, only what it can be indexed by
+    fn index(&mut self, expr: &Expr, object: &Expr, index: &Expr) {
+        let (object, index) = (self.expression(object), self.expression(index));
+        match object {
+            Type::List if !Type::Number.accepts(index) => self.error(
+                expr.span,
+                format!("List index must be a number, got {}", index),
+            ),
+            Type::Map if matches!(index, Type::Function | Type::List | Type::Map) => {
+                self.error(expr.span, key_error(index))
+            }
+            Type::Any | Type::List | Type::Map => {}
+            _ => self.error(expr.span, format!("Can't index a {}", object)),
+        }
+    }
+
+    fn global(&self, name: &Identifier) -> Type {
+        self.globals.get(&name.name).copied().unwrap_or(Type::Any)
+    }
+
+    fn binary(&mut self, expr: &Expr, op: BinaryOp, left: Type, right: Type) -> Type {
+        let numbers = Type::Number.accepts(left) && Type::Number.accepts(right);
+        match op {
+            BinaryOp::Equal | BinaryOp::NotEqual => Type::Bool,
+            // The VM concatenates two strings for any arithmetic operator, but only `+` means it
+            BinaryOp::Add => {
+                let strings = Type::Str.accepts(left) && Type::Str.accepts(right);
+                match (left, right) {
+                    (Type::Number, Type::Number) => Type::Number,
+                    (Type::Str, Type::Str) => Type::Str,
+                    _ if numbers || strings => Type::Any,
+                    _ => {
+                        let message = format!(
+                            "Operands of + must be two numbers or two strings, got {} and {}",
+                            left, right
+                        );
+                        self.error(expr.span, message);
+                        Type::Any
+                    }
+                }
+            }
+            _ => {
+                if !numbers {
+                    let message = format!(
+                        "Operands of {} must be numbers, got {} and {}",
+                        op.symbol(),
+                        left,
+                        right
+                    );
+                    self.error(expr.span, message);
+                    // Reported once here, not again wherever the result is used
+                    return Type::Any;
+                }
+                match op {
+                    BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide => Type::Number,
+                    _ => Type::Bool,
+                }
+            }
+        }
+    }
+}
+
+/// Infers the type of everything in a file and reports where they don't fit together. `natives`
+/// are the functions that exist before the file runs, with how many arguments they take.
+///
+/// Only `var` declarations take annotations. Parameter and return types, as in
+/// `fun f(a: string) -> boolean`, wait on `fun` itself, which the parser doesn't know yet, so there
+/// is no `->` token either.
+pub fn check(source: &str, natives: &[(&str, usize)]) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let program = Source(source.into()).parse()?;
+    let mut checker = Checker {
+        source,
+        globals: natives
+            .iter()
+            .map(|(name, _)| (name.to_string(), Type::Function))
+            .collect(),
+        annotated: HashSet::new(),
+        arities: natives
+            .iter()
+            .map(|(name, arity)| (name.to_string(), *arity))
+            .collect(),
+        errors: vec![],
+    };
+    for decl in &program {
+        checker.declaration(decl);
+    }
+    Ok(checker.errors)
+}
+
+fn key_error(key: Type) -> String {
+    format!(
+        "Map keys must be strings, numbers, booleans or nil, got {}",
+        key
+    )
+}
+
+#[cfg(test)]
+mod test_check {
+    use super::*;
+
+    fn errors(source: &str) -> Vec<String> {
+        check(source, &[("clock", 0)])
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn unannotated_globals_take_the_type_of_their_value() {
+        assert!(errors("var a = 1; a = \"now a string\"; print a + \"!\"; b(); c = 1;").is_empty());
+        let source = "var f = 3;\nf();\nvar s = \"a\";\nprint s - 1;\ns = 2;\nprint s - 1;\nvar clock = 1;\nclock();";
+        assert_eq!(
+            errors(source),
+            vec![
+                "Error at line 2, Can't call a number",
+                "Error at line 4, Operands of - must be numbers, got string and number",
+                "Error at line 8, Can't call a number",
+            ]
+        );
+    }
+
+    #[test]
+    fn native_calls_need_the_right_number_of_arguments() {
+        assert_eq!(
+            errors("print clock(1);\nprint clock();"),
+            vec!["Error at line 1, Expected 0 arguments but got 1"]
+        );
+    }
+
+    #[test]
+    fn finds_misuse_of_literals_and_annotations() {
+        let source = "var n: number = 1;\nvar s: string = \"s\" - \"t\";\nn();\nprint -s;\nn = s + 1;\nvar t = clock() + 1;\nvar u: nil;\nvar w: number;\nvar v: thing = !clock;";
+        assert_eq!(
+            errors(source),
+            vec![
+                "Error at line 2, Operands of - must be numbers, got string and string",
+                "Error at line 3, Can't call a number",
+                "Error at line 4, Operand of - must be a number, got string",
+                "Error at line 5, Operands of + must be two numbers or two strings, got string and number",
+                "Error at line 8, 'w' is declared number but starts out as nil",
+                "Error at line 9, Operand of ! can't be a function",
+                "Error at line 9, Unknown type 'thing'",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_are_indexed_by_numbers_and_maps_by_keys() {
+        let source = "var l: list = [1, \"a\"];\nprint l[0] + l[1];\nl[\"x\"] = 1;\nprint clock[0];\nvar n: number = [];\nvar m: map = {\"a\": 1, nil: l};\nm[l] = m[\"a\"];\nvar k = {[]: 1};";
+        assert_eq!(
+            errors(source),
+            vec![
+                "Error at line 3, List index must be a number, got string",
+                "Error at line 4, Can't index a function",
+                "Error at line 5, 'n' is declared number but starts out as list",
+                "Error at line 7, Map keys must be strings, numbers, booleans or nil, got list",
+                "Error at line 8, Map keys must be strings, numbers, booleans or nil, got list",
+            ]
+        );
+    }
+
+    #[test]
+    fn assignments_keep_the_declared_type() {
+        let source = "var flag: boolean = true;\nflag = 1 < 2;\nflag = 1 + 2;";
+        let errors = check(source, &[]).unwrap();
+        assert_eq!(
+            errors,
+            vec![Diagnostic::error(
+                "'flag' is boolean, it can't be assigned number",
+                3,
+                0
+            )]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs;
+
+use rlox::{check::check as check_source, common::Obj, debug::globals, vm::VM};
+
+const USAGE: &str = "Usage: rlox check file.lox...";
+
+/// `rlox check`: reports type errors in each file without running it.
+pub fn check(args: &[String]) -> Result<(), String> {
+    if args.is_empty() || args.iter().any(|arg| arg.starts_with('-')) {
+        return Err(USAGE.into());
+    }
+    let vm = VM::new();
+    let natives: Vec<(&str, usize)> = globals(&vm)
+        .into_iter()
+        .filter_map(|(name, value)| match vm.heap.get(value.as_obj()?) {
+            Obj::NativeFn(native) => Some((name, native.arity)),
+            _ => None,
+        })
+        .collect();
+    let mut found = 0;
+    for path in args {
+        let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        match check_source(&source, &natives) {
+            Ok(errors) => {
+                for error in &errors {
+                    println!("{}: {}", path, error);
+                }
+                found += errors.len();
+            }
+            Err(errors) => {
+                for error in errors {
+                    println!("{}: {}", path, error);
+                }
+                return Err(format!("{} doesn't parse", path));
+            }
+        }
+    }
+    match found {
+        0 => Ok(()),
+        1 => Err("1 type error found".into()),
+        n => Err(format!("{} type errors found", n)),
+    }
+}
+
+#[cfg(test)]
+mod test_checker {
+    use super::*;
+    use crate::test_util::script;
+
+    #[test]
+    fn counts_type_errors_across_files() {
+        let good = script("good", "var x: number = clock();\nprint x * 2;\n");
+        let good = good.to_string_lossy().to_string();
+        let bad = script("bad", "var s: string = 1;\nprint -\"s\";\n");
+        let bad = bad.to_string_lossy().to_string();
+        assert_eq!(check(std::slice::from_ref(&good)), Ok(()));
+        assert_eq!(check(&[good, bad]), Err("2 type errors found".into()));
+    }
+}
@@ -0,0 +1,501 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{BinaryOp, Decl, DeclKind, Expr, ExprKind, Literal, Stmt, UnaryOp},
+    common::{OpCode, Value},
+    gc::{Heap, ObjRef},
+    resolve::{Binding, Resolution},
+    tokens::Span,
+};
+
+pub type Code = Vec<(OpCode, i32)>;
+
+pub struct Codegen<'a> {
+    heap: &'a mut Heap,
+    resolution: &'a Resolution,
+    /// In the REPL expression statements print their value instead of discarding it.
+    repl: bool,
+    code: Code,
+}
+
+impl<'a> Codegen<'a> {
+    /// String constants and global names are interned on `heap` as they're emitted.
+    pub fn new(heap: &'a mut Heap, resolution: &'a Resolution, repl: bool) -> Self {
+        Codegen {
+            heap,
+            resolution,
+            repl,
+            code: vec![],
+        }
+    }
+
+    fn emit(&mut self, op: OpCode, line: i32) {
+        self.code.push((op, line));
+    }
+
+    fn global(&mut self, name: &str, span: Span) -> ObjRef {
+        match self.resolution.bindings.get(&span) {
+            Some(Binding::Global(global)) => self.heap.intern(global),
+            None => panic!("{} wasn't resolved. This should never happen", name),
+        }
+    }
+
+    pub fn declaration(&mut self, decl: &Decl) {
+        match &decl.kind {
+            DeclKind::Var {
+                name, initializer, ..
+            } => {
+                match initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.emit(OpCode::Constant(Value::NIL), name.line),
+                }
+                let global = self.global(&name.name, name.span);
+                self.emit(OpCode::DefineGlobal(global), decl.line);
+            }
+            DeclKind::Import { path, .. } => match self.resolution.imports.get(&decl.span) {
+                Some(module) => self.emit(OpCode::Import(Rc::clone(module)), decl.line),
+                None => panic!("{} wasn't loaded. This should never happen", path),
+            },
+            DeclKind::Stmt(Stmt::Print(expr)) => {
+                self.expression(expr);
+                self.emit(OpCode::Print, decl.line);
+            }
+            DeclKind::Stmt(Stmt::Expression(expr)) => {
+                self.expression(expr);
+                let end = if self.repl {
+                    OpCode::Print
+                } else {
+                    OpCode::Pop
+                };
+                self.emit(end, decl.line);
+            }
+        }
+    }
+
+    pub fn expression(&mut self, expr: &Expr) {
+        let line = expr.line;
+        match &expr.kind {
+            ExprKind::Literal(literal) => {
+                let value = match literal {
+                    Literal::Number(n) => Value::number(*n),
+                    Literal::Str(s) => Value::obj(self.heap.intern(s)),
+                    Literal::Bool(b) => Value::boolean(*b),
+                    Literal::Nil => Value::NIL,
+                };
+                self.emit(OpCode::Constant(value), line);
+            }
+            ExprKind::Variable(name) => {
+                let global = self.global(&name.name, name.span);
+                self.emit(OpCode::GetGlobal(global), line);
+            }
+            ExprKind::Assign { name, value } => {
+                self.expression(value);
+                let global = self.global(&name.name, name.span);
+                self.emit(OpCode::SetGlobal(global), line);
+            }
+            ExprKind::Unary { op, operand } => {
+                self.expression(operand);
+                match op {
+                    UnaryOp::Negate => self.emit(OpCode::Negate, line),
+                    UnaryOp::Not => self.emit(OpCode::Not, line),
+                    UnaryOp::Plus => {}
+                }
+            }
+            ExprKind::Binary { op, left, right } => {
+                self.expression(left);
+                self.expression(right);
+                // The comparisons without an instruction of their own are the negation of another
+                let (op, negate) = match op {
+                    BinaryOp::Add => (OpCode::Add, false),
+                    BinaryOp::Subtract => (OpCode::Subtract, false),
+                    BinaryOp::Multiply => (OpCode::Multiply, false),
+                    BinaryOp::Divide => (OpCode::Divide, false),
+                    BinaryOp::Equal => (OpCode::Equal, false),
+                    BinaryOp::NotEqual => (OpCode::Equal, true),
+                    BinaryOp::Greater => (OpCode::Greater, false),
+                    BinaryOp::GreaterEqual => (OpCode::Less, true),
+                    BinaryOp::Less => (OpCode::Less, false),
+                    BinaryOp::LessEqual => (OpCode::Greater, true),
+                };
+                self.emit(op, line);
+                if negate {
+                    self.emit(OpCode::Not, line);
+                }
+            }
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                self.emit(OpCode::Call(arguments.len()), line);
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+                self.emit(OpCode::BuildList(items.len()), line);
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.emit(OpCode::BuildMap(entries.len()), line);
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(OpCode::GetIndex, line);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.emit(OpCode::SetIndex, line);
+            }
+            // Members of modules are their globals under another name, the resolver binds those
+            ExprKind::Get { object, name } => match self.resolution.bindings.get(&name.span) {
+                Some(_) => {
+                    let global = self.global(&name.name, name.span);
+                    self.emit(OpCode::GetGlobal(global), line);
+                }
+                None => {
+                    self.expression(object);
+                    let name = self.heap.intern(&name.name);
+                    self.emit(OpCode::GetProperty(name), line);
+                }
+            },
+        }
+    }
+
+    pub fn finish(self) -> Code {
+        self.code
+    }
+}
+
+/// Emits the bytecode for a resolved program, without the final `Return`.
+pub fn generate(program: &[Decl], resolution: &Resolution, heap: &mut Heap, repl: bool) -> Code {
+    let mut codegen = Codegen::new(heap, resolution, repl);
+    for decl in program {
+        codegen.declaration(decl);
+    }
+    codegen.finish()
+}
+
+#[cfg(test)]
+mod test_codegen {
+    use super::*;
+    use crate::{
+        compile::Source,
+        parse::Parser,
+        resolve::{resolve, Resolver},
+        tokens::Tokenizer,
+    };
+
+    fn expression(source: &str) -> (Option<Code>, Heap) {
+        let input = Source(source.into());
+        let mut parser = Parser::new(Tokenizer::new(&input).peekable());
+        let mut heap = Heap::new();
+        let code = parser.expression(0).map(|expr| {
+            let mut resolver = Resolver::default();
+            resolver.expression(&expr);
+            let resolution = resolver.finish();
+            let mut codegen = Codegen::new(&mut heap, &resolution, false);
+            codegen.expression(&expr);
+            codegen.finish()
+        });
+        (code, heap)
+    }
+
+    fn compile(mut parser: Parser, repl: bool) -> (Option<Code>, Heap) {
+        let mut heap = Heap::new();
+        let code = parser.parse().map(|program| {
+            let resolution = resolve(&program, &[]);
+            generate(&program, &resolution, &mut heap, repl)
+        });
+        (code, heap)
+    }
+
+    fn program(source: &str) -> (Option<Code>, Heap) {
+        let input = Source(source.into());
+        compile(Parser::new(Tokenizer::new(&input).peekable()), false)
+    }
+
+    fn repl(source: &str) -> (Option<Code>, Heap) {
+        let input = Source(source.into());
+        compile(Parser::for_repl(Tokenizer::new(&input).peekable()), true)
+    }
+
+    #[test]
+    fn test_parse_number() {
+        let (expr, _) = expression("42");
+        let expr = expr.unwrap();
+        assert_eq!(expr, vec![(OpCode::Constant(Value::number(42.0)), 1)]);
+    }
+
+    #[test]
+    fn test_parse_greater() {
+        let (expr, _) = expression(">");
+        assert_eq!(expr, None);
+    }
+
+    #[test]
+    fn test_x_gt_y() {
+        let (expr, _) = expression("10 > 5");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(10.0)), 1),
+                (OpCode::Constant(Value::number(5.0)), 1),
+                (OpCode::Greater, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_less() {
+        let (expr, _) = expression("<");
+        assert_eq!(expr, None);
+    }
+
+    #[test]
+    fn test_x_lt_y() {
+        let (expr, _) = expression("10 < 5");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(10.0)), 1),
+                (OpCode::Constant(Value::number(5.0)), 1),
+                (OpCode::Less, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_greater_equal() {
+        let (expr, _) = expression(">=");
+        assert_eq!(expr, None);
+    }
+
+    #[test]
+    fn test_x_gte_y() {
+        let (expr, _) = expression("10 >= 5");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(10.0)), 1),
+                (OpCode::Constant(Value::number(5.0)), 1),
+                (OpCode::Less, 1),
+                (OpCode::Not, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_less_equal() {
+        let (expr, _) = expression("<=");
+        assert_eq!(expr, None);
+    }
+
+    #[test]
+    fn test_x_lte_y() {
+        let (expr, _) = expression("10 <= 5");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(10.0)), 1),
+                (OpCode::Constant(Value::number(5.0)), 1),
+                (OpCode::Greater, 1),
+                (OpCode::Not, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_equal_equal() {
+        let (expr, _) = expression("==");
+        assert_eq!(expr, None);
+    }
+
+    #[test]
+    fn test_x_eq_y() {
+        let (expr, _) = expression("10 == 5");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(10.0)), 1),
+                (OpCode::Constant(Value::number(5.0)), 1),
+                (OpCode::Equal, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_bang_equal() {
+        let (expr, _) = expression("!=");
+        assert_eq!(expr, None);
+    }
+
+    #[test]
+    fn test_x_neq_y() {
+        let (expr, _) = expression("10 != 5");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(10.0)), 1),
+                (OpCode::Constant(Value::number(5.0)), 1),
+                (OpCode::Equal, 1),
+                (OpCode::Not, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_string() {
+        let (expr, heap) = expression("\"hello world\"");
+        let expr = expr.unwrap();
+        let [(OpCode::Constant(value), 1)] = expr.as_slice() else {
+            panic!("Expected a single constant, got {:?}", expr);
+        };
+        assert_eq!(heap.as_str(value), Some("hello world"));
+    }
+
+    #[test]
+    fn test_equal_strings_share_a_constant() {
+        let (expr, heap) = expression("\"lox\" == \"lox\"");
+        let expr = expr.unwrap();
+        let [(OpCode::Constant(a), _), (OpCode::Constant(b), _), (OpCode::Equal, _)] =
+            expr.as_slice()
+        else {
+            panic!("Expected two constants and Equal, got {:?}", expr);
+        };
+        assert_eq!(a, b);
+        assert_eq!(heap.objects(), 1);
+    }
+
+    #[test]
+    fn parse_print_statement() {
+        let (expr, _) = program("print 42;");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(42.0)), 1),
+                (OpCode::Print, 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn expression_statement_pops() {
+        let (expr, _) = program("42;");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(42.0)), 1),
+                (OpCode::Pop, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_call_with_arguments() {
+        let (expr, heap) = program("print max(1, 2 + 3);");
+        let max = heap.find_string("max").unwrap();
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::GetGlobal(max), 1),
+                (OpCode::Constant(Value::number(1.0)), 1),
+                (OpCode::Constant(Value::number(2.0)), 1),
+                (OpCode::Constant(Value::number(3.0)), 1),
+                (OpCode::Add, 1),
+                (OpCode::Call(2), 1),
+                (OpCode::Print, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn call_binds_tighter_than_negation() {
+        let (expr, heap) = program("-clock();");
+        let clock = heap.find_string("clock").unwrap();
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::GetGlobal(clock), 1),
+                (OpCode::Call(0), 1),
+                (OpCode::Negate, 1),
+                (OpCode::Pop, 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_var_declaration() {
+        let (expr, heap) = program("var a = 1; var b;");
+        let (a, b) = (
+            heap.find_string("a").unwrap(),
+            heap.find_string("b").unwrap(),
+        );
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(1.0)), 1),
+                (OpCode::DefineGlobal(a), 1),
+                (OpCode::Constant(Value::NIL), 1),
+                (OpCode::DefineGlobal(b), 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_assignment() {
+        let (expr, heap) = program("a = b = 2;");
+        let (a, b) = (
+            heap.find_string("a").unwrap(),
+            heap.find_string("b").unwrap(),
+        );
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(2.0)), 1),
+                (OpCode::SetGlobal(b), 1),
+                (OpCode::SetGlobal(a), 1),
+                (OpCode::Pop, 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn repl_prints_bare_expression() {
+        let (expr, _) = repl("1 + 2");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(1.0)), 1),
+                (OpCode::Constant(Value::number(2.0)), 1),
+                (OpCode::Add, 1),
+                (OpCode::Print, 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_print_with_paren() {
+        let (expr, _) = program("print (42 + 5);");
+        assert_eq!(
+            expr,
+            Some(vec![
+                (OpCode::Constant(Value::number(42.0)), 1),
+                (OpCode::Constant(Value::number(5.0)), 1),
+                (OpCode::Add, 1),
+                (OpCode::Print, 1)
+            ])
+        );
+    }
+}
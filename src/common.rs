@@ -1,42 +1,101 @@
 use std::{fmt, rc::Rc};
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Value {
-    Number(f64),
-    Boolean(bool),
-    Nil,
-    Obj(Obj),
-}
+use crate::{
+    gc::{Heap, ObjRef},
+    table::Table,
+    vm::{RuntimeError, VM},
+};
+
+pub use crate::value::{Value, ValueKind};
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Number(n) => write!(f, "Number({})", n),
-            Value::Boolean(b) => write!(f, "Boolean({})", b),
-            Value::Nil => write!(f, "Nil"),
-            Value::Obj(obj) => write!(f, "Obj({:?})", obj),
+        match self.kind() {
+            ValueKind::Number(n) => write!(f, "Number({})", n),
+            ValueKind::Boolean(b) => write!(f, "Boolean({})", b),
+            ValueKind::Nil => write!(f, "Nil"),
+            ValueKind::Obj(obj) => write!(f, "Obj({})", obj),
         }
     }
 }
 
 impl Value {
-    pub fn print_lox(&self) -> String {
-        match self {
-            Value::Number(n) => format!("{}", n),
-            Value::Boolean(b) => format!("{}", b),
-            Value::Nil => String::from("nil"),
-            Value::Obj(obj) => match obj {
-                Obj::String(s) => s.into(),
-                // _ => String::from("unknown object"),
+    pub fn type_name(&self, heap: &Heap) -> &'static str {
+        match self.kind() {
+            ValueKind::Number(_) => "number",
+            ValueKind::Boolean(_) => "boolean",
+            ValueKind::Nil => "nil",
+            ValueKind::Obj(obj) => match heap.get(obj) {
+                Obj::String(_) => "string",
+                Obj::NativeFn(_) => "native function",
+                Obj::List(_) => "list",
+                Obj::Map(_) => "map",
+                Obj::BoundMethod(_) => "native method",
+            },
+        }
+    }
+
+    pub fn print_lox(&self, heap: &Heap) -> String {
+        self.print_nested(heap, &mut vec![])
+    }
+
+    // `printing` are the lists and maps being printed further out, so one that contains itself
+    // doesn't print forever.
+    fn print_nested(&self, heap: &Heap, printing: &mut Vec<ObjRef>) -> String {
+        match self.kind() {
+            ValueKind::Number(n) => format!("{}", n),
+            ValueKind::Boolean(b) => format!("{}", b),
+            ValueKind::Nil => String::from("nil"),
+            ValueKind::Obj(obj) => match heap.get(obj) {
+                Obj::String(s) => s.to_string(),
+                Obj::NativeFn(native) => format!("<native fn {}>", native.name),
+                Obj::List(_) if printing.contains(&obj) => "[...]".into(),
+                Obj::List(items) => {
+                    printing.push(obj);
+                    let items: Vec<_> = items
+                        .iter()
+                        .map(|item| item.print_nested(heap, printing))
+                        .collect();
+                    printing.pop();
+                    format!("[{}]", items.join(", "))
+                }
+                Obj::Map(_) if printing.contains(&obj) => "{...}".into(),
+                Obj::Map(table) => {
+                    printing.push(obj);
+                    let entries: Vec<_> = table
+                        .iter()
+                        .map(|(key, value)| {
+                            let key = key.print_nested(heap, printing);
+                            format!("{}: {}", key, value.print_nested(heap, printing))
+                        })
+                        .collect();
+                    printing.pop();
+                    format!("{{{}}}", entries.join(", "))
+                }
+                Obj::BoundMethod(bound) => format!("<native method {}>", bound.method.name),
             },
         }
     }
+
+    /// Like `Display`, but shows what objects hold, for disassembly and stack dumps.
+    pub fn describe(&self, heap: &Heap) -> String {
+        match self.kind() {
+            ValueKind::Obj(obj) => match heap.get(obj) {
+                Obj::String(s) => format!("String({:?})", s),
+                Obj::NativeFn(native) => format!("NativeFn({})", native.name),
+                Obj::List(_) => format!("List({})", self.print_lox(heap)),
+                Obj::Map(_) => format!("Map({})", self.print_lox(heap)),
+                Obj::BoundMethod(bound) => format!("BoundMethod({})", bound.method.name),
+            },
+            _ => self.to_string(),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum OpCode {
     Return,
-    Constant(Rc<Value>),
+    Constant(Value),
     Not,
     Negate,
     Add,
@@ -48,44 +107,159 @@ pub enum OpCode {
     Less,
     Print,
     Pop,
+    /// Global names are interned strings, so looking one up hashes a handle rather than the name.
+    GetGlobal(ObjRef),
+    DefineGlobal(ObjRef),
+    SetGlobal(ObjRef),
+    Call(usize),
+    /// Makes a list of the top this many values.
+    BuildList(usize),
+    /// Makes a map of the top this many key and value pairs, keys first.
+    BuildMap(usize),
+    GetIndex,
+    SetIndex,
+    /// Looks up a method of the value on top of the stack, and binds it to that value.
+    GetProperty(ObjRef),
+    /// Runs a module's chunk, unless it already ran.
+    Import(Rc<Chunk>),
+}
+
+impl OpCode {
+    /// The name of the instruction, without its operand.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Return => "Return",
+            OpCode::Constant(_) => "Constant",
+            OpCode::Not => "Not",
+            OpCode::Negate => "Negate",
+            OpCode::Add => "Add",
+            OpCode::Subtract => "Subtract",
+            OpCode::Multiply => "Multiply",
+            OpCode::Divide => "Divide",
+            OpCode::Equal => "Equal",
+            OpCode::Greater => "Greater",
+            OpCode::Less => "Less",
+            OpCode::Print => "Print",
+            OpCode::Pop => "Pop",
+            OpCode::GetGlobal(_) => "GetGlobal",
+            OpCode::DefineGlobal(_) => "DefineGlobal",
+            OpCode::SetGlobal(_) => "SetGlobal",
+            OpCode::Call(_) => "Call",
+            OpCode::BuildList(_) => "BuildList",
+            OpCode::BuildMap(_) => "BuildMap",
+            OpCode::GetIndex => "GetIndex",
+            OpCode::SetIndex => "SetIndex",
+            OpCode::GetProperty(_) => "GetProperty",
+            OpCode::Import(_) => "Import",
+        }
+    }
 }
 
+/// Objects live on the VM heap and are referred to by `ValueKind::Obj` handles.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Obj {
-    String(String),
-    // more to come
+    /// Always interned, see `Heap::intern`.
+    String(Rc<str>),
+    NativeFn(Rc<NativeFn>),
+    List(Vec<Value>),
+    Map(Table),
+    BoundMethod(BoundMethod),
 }
 
-pub struct Chunk<'a> {
-    name: &'a str,
+/// A native method together with the value it was looked up on, which it gets as its first
+/// argument when it's called.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<NativeFn>,
+}
+
+pub type NativeFnPtr = fn(&mut VM, &[Value]) -> Result<Value, RuntimeError>;
+pub type NativeFnBody = Box<dyn Fn(&mut VM, &[Value]) -> Result<Value, RuntimeError>>;
+
+/// A function implemented by the host, callable from Lox like any other value.
+pub struct NativeFn {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFnBody,
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}/{}>", self.name, self.arity)
+    }
+}
+
+// Natives are compared by identity, the same way clox compares object pointers.
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    /// The file the code comes from. Modules are named by their canonical path, which is also how
+    /// the VM tells whether one already ran.
+    name: String,
     pub code: Vec<(OpCode, i32)>,
 }
 
 pub trait Disassembler {
-    fn disassemble(&self);
+    fn disassemble(&self, heap: &Heap);
 }
 
-impl<'a> Chunk<'a> {
+impl Chunk {
     pub fn write(&mut self, byte: OpCode, line: i32) {
         self.code.push((byte, line));
     }
 
-    pub fn new(name: &'a str) -> Self {
-        Chunk { name, code: vec![] }
+    pub fn new(name: &str) -> Self {
+        Chunk {
+            name: name.into(),
+            code: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Every heap value the code refers to: constants and the names of globals and methods,
+    /// including those of the modules it imports, which have to stay alive until they run.
+    pub fn constants(&self) -> Vec<Value> {
+        let mut constants = vec![];
+        for (instruction, _) in &self.code {
+            match instruction {
+                OpCode::Constant(value) => constants.push(*value),
+                OpCode::GetGlobal(name)
+                | OpCode::DefineGlobal(name)
+                | OpCode::SetGlobal(name)
+                | OpCode::GetProperty(name) => constants.push(Value::obj(*name)),
+                OpCode::Import(module) => constants.extend(module.constants()),
+                _ => {}
+            }
+        }
+        constants
     }
 }
 
-impl Disassembler for Chunk<'_> {
-    fn disassemble(&self) {
+impl Disassembler for Chunk {
+    fn disassemble(&self, heap: &Heap) {
         println!("=== {} ===", self.name);
+        for (offset, (instruction, line)) in self.code.iter().enumerate() {
+            print!("{:04} {:>4} ", offset, line);
+            instruction.disassemble(heap);
+        }
     }
 }
 
 impl Disassembler for OpCode {
-    fn disassemble(&self) {
+    fn disassemble(&self, heap: &Heap) {
+        let name = |name: &ObjRef| heap.string(*name).unwrap_or_default().to_string();
         match self {
             OpCode::Return => println!("Return"),
-            OpCode::Constant(value) => println!("Constant {}", value),
+            OpCode::Constant(value) => println!("Constant {}", value.describe(heap)),
             OpCode::Negate => println!("Negate"),
             OpCode::Add => println!("Add"),
             OpCode::Subtract => println!("Subtract"),
@@ -97,6 +271,16 @@ impl Disassembler for OpCode {
             OpCode::Less => println!("Less"),
             OpCode::Print => println!("Print"),
             OpCode::Pop => println!("Pop"),
+            OpCode::GetGlobal(global) => println!("GetGlobal {}", name(global)),
+            OpCode::DefineGlobal(global) => println!("DefineGlobal {}", name(global)),
+            OpCode::SetGlobal(global) => println!("SetGlobal {}", name(global)),
+            OpCode::Call(arg_count) => println!("Call {}", arg_count),
+            OpCode::BuildList(count) => println!("BuildList {}", count),
+            OpCode::BuildMap(count) => println!("BuildMap {}", count),
+            OpCode::GetIndex => println!("GetIndex"),
+            OpCode::SetIndex => println!("SetIndex"),
+            OpCode::GetProperty(property) => println!("GetProperty {}", name(property)),
+            OpCode::Import(module) => println!("Import {}", module.name()),
         }
     }
 }
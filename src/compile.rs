@@ -1,24 +1,425 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
 use crate::{
-    common::{Chunk, OpCode},
+    ast::{Decl, DeclKind},
+    codegen::{self, Code},
+    common::{Chunk, OpCode, Value},
+    diagnostic::Diagnostic,
+    gc::Heap,
     parse::Parser,
+    resolve::{resolve, resolve_module, Module, Resolution, Scope},
     tokens::Tokenizer,
-    vm::InterpretMode,
+    vm::{InterpretMode, VM},
 };
 
 pub struct Source(pub String);
 
+/// What compiling a file depends on besides its source: the globals the host defines, where to
+/// look for modules, and, for the REPL, what earlier lines declared and imported. A REPL session
+/// compiles every line with the same one, the way it runs every line on the same VM.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// Globals that exist before anything runs, like natives. They're the only globals of the host
+    /// that modules can see.
+    pub predefined: HashSet<String>,
+    /// Searched for modules that aren't next to the file importing them, before `LOX_PATH`.
+    pub include: Vec<PathBuf>,
+    /// Every module compiled so far, by canonical path.
+    modules: HashMap<PathBuf, Module>,
+    scope: Scope,
+}
+
+impl Session {
+    /// A session for scripts run on `vm`, whose globals so far are all the host's.
+    pub fn new(vm: &VM, include: &[PathBuf]) -> Self {
+        Session {
+            predefined: vm
+                .globals
+                .keys()
+                .filter_map(|name| vm.heap.as_str(name))
+                .map(String::from)
+                .collect(),
+            include: include.to_vec(),
+            ..Session::default()
+        }
+    }
+
+    /// The constants of every module compiled so far. A module stays cached even when the file
+    /// importing it fails to compile or stops before the import runs, so a VM that keeps using the
+    /// session has to keep these alive, see `VM::roots`.
+    pub fn constants(&self) -> Vec<Value> {
+        self.modules
+            .values()
+            .flat_map(|module| module.chunk.constants())
+            .collect()
+    }
+}
+
 impl Source {
-    pub fn compile(self, file_name: &str, mode: InterpretMode) -> Option<Chunk> {
-        let mut chunk = Chunk::new(file_name);
-        let tokenizer = Tokenizer::new(&self);
+    /// Parses the source into its declarations, without resolving names or generating code.
+    pub fn parse(&self) -> Result<Vec<Decl>, Vec<Diagnostic>> {
+        self.parse_with(false)
+    }
+
+    /// Warnings about names in the source, which compiles regardless. `predefined` are the globals
+    /// that exist before it runs. There are none for source that doesn't parse, and none about
+    /// undefined names in source that imports modules, since they could define anything.
+    pub fn warnings(&self, predefined: &[&str]) -> Vec<Diagnostic> {
+        match self.parse() {
+            Ok(program) => resolve(&program, predefined)
+                .diagnostics
+                .into_iter()
+                .filter(|diagnostic| !diagnostic.is_error())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    fn parse_with(&self, repl: bool) -> Result<Vec<Decl>, Vec<Diagnostic>> {
+        let tokenizer = Tokenizer::new(self).peekable();
+        let mut parser = if repl {
+            Parser::for_repl(tokenizer)
+        } else {
+            Parser::new(tokenizer)
+        };
+        match parser.parse() {
+            Some(program) if parser.errors.is_empty() => Ok(program),
+            _ => Err(parser.errors),
+        }
+    }
+
+    /// Compiles the source into a chunk whose constants are allocated on `heap`, so the chunk can
+    /// only run on the VM that owns it. Modules it imports see no predefined globals, use
+    /// `compile_in` for those to see the natives.
+    pub fn compile(self, file_name: &str, mode: InterpretMode, heap: &mut Heap) -> Option<Chunk> {
+        report(self.compile_with(file_name, mode, heap, false, &mut Session::default()))
+    }
+
+    /// Same as `compile`, against the predefined globals and search path of `session`.
+    pub fn compile_in(
+        self,
+        file_name: &str,
+        mode: InterpretMode,
+        heap: &mut Heap,
+        session: &mut Session,
+    ) -> Option<Chunk> {
+        report(self.compile_with(file_name, mode, heap, false, session))
+    }
+
+    /// Same as `compile_in`, but hands back the errors instead of printing them.
+    pub fn try_compile(
+        self,
+        file_name: &str,
+        heap: &mut Heap,
+        session: &mut Session,
+    ) -> Result<Chunk, Vec<String>> {
+        self.compile_with(file_name, InterpretMode::Release, heap, false, session)
+    }
+
+    /// Compiles a line typed into the REPL, where expression statements print their value and the
+    /// trailing `;` of the last one may be omitted. What the line declares and imports stays in
+    /// `session` for the next one, unless it fails to compile.
+    pub fn compile_repl(
+        self,
+        file_name: &str,
+        mode: InterpretMode,
+        heap: &mut Heap,
+        session: &mut Session,
+    ) -> Option<Chunk> {
+        report(self.compile_with(file_name, mode, heap, true, session))
+    }
+
+    fn compile_with(
+        self,
+        file_name: &str,
+        mode: InterpretMode,
+        heap: &mut Heap,
+        repl: bool,
+        session: &mut Session,
+    ) -> Result<Chunk, Vec<String>> {
         if let InterpretMode::Debug = mode {
             for token in Tokenizer::new(&self) {
                 println!("{:?}", token);
             }
         }
-        let bytecode = Parser::new(tokenizer.peekable()).parse()?;
-        chunk.code = bytecode;
-        chunk.write(OpCode::Return, 0);
-        Some(chunk)
+        let program = self
+            .parse_with(repl)
+            .map_err(|errors| errors.iter().map(ToString::to_string).collect::<Vec<_>>())?;
+        let mut search_path = session.include.clone();
+        if let Some(lox_path) = env::var_os("LOX_PATH") {
+            search_path.extend(env::split_paths(&lox_path));
+        }
+        let mut loader = Loader {
+            heap,
+            search_path,
+            predefined: &session.predefined,
+            modules: &mut session.modules,
+            loading: vec![],
+        };
+        // The REPL and tests aren't files, so their imports are relative to the working directory
+        if let Ok(path) = fs::canonicalize(file_name) {
+            loader.loading.push((path, file_name.into()));
+        }
+        let dir = Path::new(file_name).parent().unwrap_or(Path::new(""));
+        // Only the REPL carries what's in scope over to the next compile
+        let scope = if repl {
+            session.scope.clone()
+        } else {
+            Scope::default()
+        };
+        let (code, resolution) = loader.compile(&program, None, None, dir, scope, repl)?;
+        if repl {
+            session.scope = resolution.scope;
+        }
+        Ok(chunk(file_name, code))
+    }
+}
+
+/// A chunk holding `code`, and the `Return` that ends it.
+fn chunk(name: &str, code: Code) -> Chunk {
+    let mut chunk = Chunk::new(name);
+    // Attributed to the last line, so stepping past the end doesn't jump back to the top. Code that
+    // is empty or only comments ends on the first line.
+    let line = code.last().map_or(1, |(_, line)| *line);
+    chunk.code = code;
+    chunk.write(OpCode::Return, line);
+    chunk
+}
+
+/// Finds and compiles the modules a file imports, and the modules those import, each once.
+struct Loader<'a> {
+    heap: &'a mut Heap,
+    /// Where modules that aren't next to the file importing them are looked for, in order.
+    search_path: Vec<PathBuf>,
+    predefined: &'a HashSet<String>,
+    /// Every module compiled so far, by canonical path.
+    modules: &'a mut HashMap<PathBuf, Module>,
+    /// The files being compiled, outermost first, with the path each was imported as.
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl Loader<'_> {
+    /// Compiles a file after the modules it imports. Errors in a module are prefixed with its
+    /// `file`, the file being run has none.
+    fn compile(
+        &mut self,
+        program: &[Decl],
+        file: Option<&Path>,
+        prefix: Option<&str>,
+        dir: &Path,
+        scope: Scope,
+        repl: bool,
+    ) -> Result<(Code, Resolution), Vec<String>> {
+        let located = |error: String| match file {
+            Some(file) => format!("{}: {}", file.display(), error),
+            None => error,
+        };
+        let mut modules = HashMap::new();
+        let mut errors = vec![];
+        for decl in program {
+            let DeclKind::Import { path, .. } = &decl.kind else {
+                continue;
+            };
+            let error = |message| located(Diagnostic::error(message, decl.line, 0).to_string());
+            match self.load(path, dir, error) {
+                Ok(module) => {
+                    modules.insert(path.clone(), module);
+                }
+                Err(load_errors) => errors.extend(load_errors),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let resolution = resolve_module(program, prefix, modules, self.predefined, scope);
+        let errors: Vec<_> = resolution
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .map(|error| located(error.to_string()))
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let code = codegen::generate(program, &resolution, self.heap, repl);
+        Ok((code, resolution))
+    }
+
+    /// The module `path` names, relative to `dir` or else to a directory of the search path.
+    /// `error` places what goes wrong with the import itself.
+    fn load(
+        &mut self,
+        path: &str,
+        dir: &Path,
+        error: impl Fn(String) -> String,
+    ) -> Result<Module, Vec<String>> {
+        let found = std::iter::once(dir)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file());
+        let Some(found) = found else {
+            return Err(vec![error(format!("Can't find module '{}'", path))]);
+        };
+        let read = fs::canonicalize(&found).and_then(|canonical| {
+            let text = fs::read_to_string(&canonical)?;
+            Ok((canonical, text))
+        });
+        let (canonical, text) =
+            read.map_err(|e| vec![error(format!("Can't read module '{}': {}", path, e))])?;
+        if let Some(start) = self.loading.iter().position(|(file, _)| *file == canonical) {
+            let cycle: Vec<&str> = self.loading[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .chain([path])
+                .collect();
+            return Err(vec![error(format!("Import cycle: {}", cycle.join(" -> ")))]);
+        }
+        if let Some(module) = self.modules.get(&canonical) {
+            return Ok(module.clone());
+        }
+        let program = Source(text).parse().map_err(|errors| {
+            errors
+                .iter()
+                .map(|e| format!("{}: {}", found.display(), e))
+                .collect::<Vec<_>>()
+        })?;
+        let name = canonical.to_string_lossy().to_string();
+        let dir = found.parent().unwrap_or(Path::new(""));
+        self.loading.push((canonical.clone(), path.into()));
+        let compiled = self.compile(
+            &program,
+            Some(&found),
+            Some(&name),
+            dir,
+            Scope::default(),
+            false,
+        );
+        self.loading.pop();
+        let (code, resolution) = compiled?;
+        let module = Module {
+            chunk: Rc::new(chunk(&name, code)),
+            exports: resolution.exports,
+        };
+        self.modules.insert(canonical, module.clone());
+        Ok(module)
+    }
+}
+
+fn report(result: Result<Chunk, Vec<String>>) -> Option<Chunk> {
+    match result {
+        Ok(chunk) => Some(chunk),
+        Err(errors) => {
+            for error in errors {
+                println!("{}", error);
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_compile {
+    use super::*;
+    use crate::{
+        test_util::temp_dir,
+        vm::{InterpretResult, VM},
+    };
+
+    fn write(dir: &Path, file: &str, source: &str) -> String {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, source).unwrap();
+        path.to_string_lossy().into()
+    }
+
+    fn compile(path: &str, heap: &mut Heap, include: &[PathBuf]) -> Result<Chunk, Vec<String>> {
+        let source = Source(fs::read_to_string(path).unwrap());
+        let mut session = Session {
+            include: include.to_vec(),
+            ..Session::default()
+        };
+        source.try_compile(path, heap, &mut session)
+    }
+
+    #[test]
+    fn runs_each_module_once_in_its_own_namespace() {
+        let dir = temp_dir("modules");
+        write(
+            &dir,
+            "lib/counter.lox",
+            "var count = 0;\ncount = count + 1;\n",
+        );
+        write(
+            &dir,
+            "lib/user.lox",
+            "import \"counter.lox\";\nvar twice = count * 2;\n",
+        );
+        write(&dir, "shared/greeting.lox", "var greeting = \"hi\";\n");
+        let main = write(
+            &dir,
+            "main.lox",
+            "import \"lib/counter.lox\" as c;\nimport \"lib/user.lox\";\nimport \"greeting.lox\";\nvar count = 100;\nvar total = count + c.count + twice;\nvar text = greeting;\n",
+        );
+        let mut vm = VM::new();
+        let chunk = compile(&main, &mut vm.heap, &[dir.join("shared")]).unwrap();
+        assert!(matches!(
+            vm.interpret(chunk, InterpretMode::Release),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global("total"), Ok(103.0));
+        assert_eq!(vm.get_global("text"), Ok(String::from("hi")));
+        assert_eq!(vm.modules.len(), 3);
+
+        let mut heap = Heap::new();
+        assert_eq!(
+            compile(&main, &mut heap, &[]).err(),
+            Some(vec![
+                "Error at line 3, Can't find module 'greeting.lox'".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn modules_only_see_what_they_declare_import_or_is_predefined() {
+        let dir = temp_dir("module-scope");
+        let leak = write(&dir, "leak.lox", "print secret;\nsecret = clock();\n");
+        let main = write(
+            &dir,
+            "main.lox",
+            "var secret = \"mine\";\nimport \"leak.lox\";\nprint secret;\n",
+        );
+        let vm = VM::new();
+        let mut session = Session::new(&vm, &[]);
+        let errors = Source(fs::read_to_string(&main).unwrap())
+            .try_compile(&main, &mut Heap::new(), &mut session)
+            .unwrap_err();
+        let undefined = "'secret' isn't declared or imported by this module";
+        assert_eq!(
+            errors,
+            vec![
+                format!("{}: Error at line 1, {}", leak, undefined),
+                format!("{}: Error at line 2, {}", leak, undefined),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_import_cycles() {
+        let dir = temp_dir("import-cycle");
+        let a = write(&dir, "a.lox", "import \"b.lox\";\n");
+        let b = write(&dir, "b.lox", "var b = 1;\nimport \"a.lox\";\n");
+        let errors = compile(&a, &mut Heap::new(), &[]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![format!(
+                "{}: Error at line 2, Import cycle: {} -> b.lox -> a.lox",
+                b, a
+            )]
+        );
     }
 }
@@ -0,0 +1,392 @@
+use crate::{
+    common::{Obj, Value, ValueKind},
+    vm::{RuntimeError, VM},
+};
+
+/// Conversion of a host value into a Lox value. Takes the VM since objects live on its heap.
+pub trait IntoLox {
+    fn into_lox(self, vm: &mut VM) -> Value;
+}
+
+/// Conversion of a Lox value into a host value. Fails with a runtime error naming the expected type.
+pub trait FromLox: Sized {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError>;
+
+    /// Same as `from_lox`, for an item nested in lists at the indices in `path`, so an error can
+    /// say which item it's about. Only containers need more than the default.
+    fn from_lox_at(value: &Value, vm: &VM, path: &mut Vec<usize>) -> Result<Self, RuntimeError> {
+        Self::from_lox(value, vm).map_err(|error| at_path(error, path))
+    }
+}
+
+/// Argument lists of typed native functions, implemented for tuples of `FromLox` values.
+pub trait FromLoxArgs: Sized {
+    const ARITY: usize;
+    fn from_lox_args(args: &[Value], vm: &VM) -> Result<Self, RuntimeError>;
+}
+
+pub(crate) fn expected(type_name: &str, value: &Value, vm: &VM) -> RuntimeError {
+    RuntimeError::new(format!(
+        "Expected {} but got {}",
+        type_name,
+        value.type_name(&vm.heap)
+    ))
+}
+
+/// Says where in nested lists a conversion failed, as in `at index [1][0]`.
+fn at_path(error: RuntimeError, path: &[usize]) -> RuntimeError {
+    if path.is_empty() {
+        return error;
+    }
+    let indices: String = path.iter().map(|index| format!("[{}]", index)).collect();
+    RuntimeError::new(format!("{} at index {}", error, indices))
+}
+
+impl IntoLox for Value {
+    fn into_lox(self, _vm: &mut VM) -> Value {
+        self
+    }
+}
+
+impl FromLox for Value {
+    fn from_lox(value: &Value, _vm: &VM) -> Result<Self, RuntimeError> {
+        Ok(*value)
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self, _vm: &mut VM) -> Value {
+        Value::number(self)
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+        match value.kind() {
+            ValueKind::Number(n) => Ok(n),
+            _ => Err(expected("number", value, vm)),
+        }
+    }
+}
+
+macro_rules! integer_conversions {
+    ($($int:ty),*) => {
+        $(
+            impl IntoLox for $int {
+                fn into_lox(self, _vm: &mut VM) -> Value {
+                    Value::number(self as f64)
+                }
+            }
+
+            // `MAX as f64` rounds up to the next power of two for the 64-bit types, so the upper
+            // bound is exclusive, and `as` would saturate anything past it instead of failing.
+            // Errors talk about Lox numbers, since that's what the script passed.
+            impl FromLox for $int {
+                fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+                    match value.kind() {
+                        ValueKind::Number(n) if n.fract() != 0.0 => Err(RuntimeError::new(
+                            format!("Expected whole number but got {}", n),
+                        )),
+                        ValueKind::Number(n)
+                            if n >= <$int>::MIN as f64 && n < <$int>::MAX as f64 + 1.0 =>
+                        {
+                            Ok(n as $int)
+                        }
+                        ValueKind::Number(n) => Err(RuntimeError::new(format!(
+                            "Expected number from {} to {} but got {}",
+                            <$int>::MIN,
+                            <$int>::MAX,
+                            n
+                        ))),
+                        _ => Err(expected("number", value, vm)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoLox for bool {
+    fn into_lox(self, _vm: &mut VM) -> Value {
+        Value::boolean(self)
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+        match value.kind() {
+            ValueKind::Boolean(b) => Ok(b),
+            _ => Err(expected("boolean", value, vm)),
+        }
+    }
+}
+
+impl IntoLox for String {
+    fn into_lox(self, vm: &mut VM) -> Value {
+        Value::obj(vm.intern(&self))
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self, vm: &mut VM) -> Value {
+        Value::obj(vm.intern(self))
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+        match vm.heap.as_str(value) {
+            Some(s) => Ok(s.into()),
+            None => Err(expected("string", value, vm)),
+        }
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self, _vm: &mut VM) -> Value {
+        Value::NIL
+    }
+}
+
+impl FromLox for () {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+        match value.kind() {
+            ValueKind::Nil => Ok(()),
+            _ => Err(expected("nil", value, vm)),
+        }
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self, vm: &mut VM) -> Value {
+        match self {
+            Some(value) => value.into_lox(vm),
+            None => Value::NIL,
+        }
+    }
+}
+
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+        match value.kind() {
+            ValueKind::Nil => Ok(None),
+            _ => T::from_lox(value, vm).map(Some),
+        }
+    }
+
+    fn from_lox_at(value: &Value, vm: &VM, path: &mut Vec<usize>) -> Result<Self, RuntimeError> {
+        match value.kind() {
+            ValueKind::Nil => Ok(None),
+            _ => T::from_lox_at(value, vm, path).map(Some),
+        }
+    }
+}
+
+// Lists. Converted items wait on the stack until the list holding them is allocated, since
+// converting the rest, or allocating the list, can collect.
+impl<T: IntoLox> IntoLox for Vec<T> {
+    fn into_lox(self, vm: &mut VM) -> Value {
+        let base = vm.stack.len();
+        for item in self {
+            let value = item.into_lox(vm);
+            vm.stack.push(value);
+        }
+        let items = vm.stack[base..].to_vec();
+        let list = vm.alloc(Obj::List(items));
+        vm.stack.truncate(base);
+        Value::obj(list)
+    }
+}
+
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+        Self::from_lox_at(value, vm, &mut vec![])
+    }
+
+    fn from_lox_at(value: &Value, vm: &VM, path: &mut Vec<usize>) -> Result<Self, RuntimeError> {
+        let items = match value.as_obj().map(|obj| vm.heap.get(obj)) {
+            Some(Obj::List(items)) => items,
+            _ => return Err(at_path(expected("list", value, vm), path)),
+        };
+        items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                path.push(index);
+                let item = T::from_lox_at(item, vm, path);
+                path.pop();
+                item
+            })
+            .collect()
+    }
+}
+
+macro_rules! args_conversions {
+    ($arity:expr; $($arg:ident $index:tt),*) => {
+        impl<$($arg: FromLox),*> FromLoxArgs for ($($arg,)*) {
+            const ARITY: usize = $arity;
+
+            #[allow(unused_variables)]
+            fn from_lox_args(args: &[Value], vm: &VM) -> Result<Self, RuntimeError> {
+                Ok(($(
+                    $arg::from_lox(&args[$index], vm).map_err(|error| {
+                        RuntimeError::new(format!("{} for argument {}", error, $index + 1))
+                    })?,
+                )*))
+            }
+        }
+    };
+}
+
+args_conversions!(0;);
+args_conversions!(1; A 0);
+args_conversions!(2; A 0, B 1);
+args_conversions!(3; A 0, B 1, C 2);
+args_conversions!(4; A 0, B 1, C 2, D 3);
+
+#[cfg(test)]
+mod test_convert {
+    use super::*;
+
+    fn vm() -> VM {
+        VM::new()
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        let mut vm = vm();
+        let value = 2.5.into_lox(&mut vm);
+        assert_eq!(f64::from_lox(&value, &vm), Ok(2.5));
+        let value = (-7).into_lox(&mut vm);
+        assert_eq!(i32::from_lox(&value, &vm), Ok(-7));
+    }
+
+    #[test]
+    fn fractional_number_is_not_an_integer() {
+        assert_eq!(
+            usize::from_lox(&Value::number(1.5), &vm()),
+            Err(RuntimeError::new("Expected whole number but got 1.5"))
+        );
+    }
+
+    #[test]
+    fn out_of_range_numbers_are_not_saturated() {
+        let vm = vm();
+        assert_eq!(
+            i64::from_lox(&Value::number(1e20), &vm),
+            Err(RuntimeError::new(
+                "Expected number from -9223372036854775808 to 9223372036854775807 but got 100000000000000000000"
+            ))
+        );
+        // i64::MAX as f64 is 2^63, which doesn't fit
+        let two_to_the_63 = Value::number(9223372036854775808.0);
+        assert!(i64::from_lox(&two_to_the_63, &vm).is_err());
+        assert_eq!(u64::from_lox(&two_to_the_63, &vm), Ok(1 << 63));
+        assert_eq!(
+            i64::from_lox(&Value::number(-9223372036854775808.0), &vm),
+            Ok(i64::MIN)
+        );
+        assert!(u64::from_lox(&Value::number(-1.0), &vm).is_err());
+        assert!(u64::from_lox(&Value::number(18446744073709551616.0), &vm).is_err());
+        assert!(i64::from_lox(&Value::number(f64::INFINITY), &vm).is_err());
+        assert!(i64::from_lox(&Value::number(f64::NAN), &vm).is_err());
+    }
+
+    #[test]
+    fn error_names_the_expected_type() {
+        let vm = vm();
+        assert_eq!(
+            bool::from_lox(&Value::NIL, &vm),
+            Err(RuntimeError::new("Expected boolean but got nil"))
+        );
+        assert_eq!(
+            String::from_lox(&Value::number(1.0), &vm),
+            Err(RuntimeError::new("Expected string but got number"))
+        );
+        // Integers are numbers to Lox
+        assert_eq!(
+            u8::from_lox(&Value::NIL, &vm),
+            Err(RuntimeError::new("Expected number but got nil"))
+        );
+        assert_eq!(
+            u8::from_lox(&Value::number(300.0), &vm),
+            Err(RuntimeError::new(
+                "Expected number from 0 to 255 but got 300"
+            ))
+        );
+    }
+
+    #[test]
+    fn option_maps_to_nil() {
+        let mut vm = vm();
+        assert_eq!(None::<f64>.into_lox(&mut vm), Value::NIL);
+        assert_eq!(Option::<String>::from_lox(&Value::NIL, &vm), Ok(None));
+        let hi = "hi".into_lox(&mut vm);
+        assert_eq!(
+            Option::<String>::from_lox(&hi, &vm),
+            Ok(Some("hi".to_string()))
+        );
+    }
+
+    #[test]
+    fn lists_round_trip() {
+        let mut vm = vm();
+        // Every allocation collects, so strings converted before the list is made must survive
+        vm.heap.stress = true;
+        let words = vec!["a".to_string(), "b".to_string()];
+        let value = words.clone().into_lox(&mut vm);
+        assert_eq!(Vec::<String>::from_lox(&value, &vm), Ok(words));
+        let nested = vec![vec![1, 2], vec![], vec![3]];
+        let value = nested.clone().into_lox(&mut vm);
+        assert_eq!(Vec::<Vec<i32>>::from_lox(&value, &vm), Ok(nested));
+        assert_eq!(value.print_lox(&vm.heap), "[[1, 2], [], [3]]");
+    }
+
+    #[test]
+    fn list_errors_name_the_item() {
+        let mut vm = vm();
+        let value = vec![Value::number(1.0), Value::NIL].into_lox(&mut vm);
+        assert_eq!(
+            Vec::<f64>::from_lox(&value, &vm),
+            Err(RuntimeError::new(
+                "Expected number but got nil at index [1]"
+            ))
+        );
+        let nested = vec![vec![1.0], vec![2.5]].into_lox(&mut vm);
+        assert_eq!(
+            Vec::<Vec<u8>>::from_lox(&nested, &vm),
+            Err(RuntimeError::new(
+                "Expected whole number but got 2.5 at index [1][0]"
+            ))
+        );
+        let nested = vec![
+            Value::NIL,
+            vec![1.0].into_lox(&mut vm),
+            Value::boolean(true),
+        ]
+        .into_lox(&mut vm);
+        assert_eq!(
+            Vec::<Option<Vec<f64>>>::from_lox(&nested, &vm),
+            Err(RuntimeError::new(
+                "Expected list but got boolean at index [2]"
+            ))
+        );
+        assert_eq!(
+            Vec::<Value>::from_lox(&Value::number(1.0), &vm),
+            Err(RuntimeError::new("Expected list but got number"))
+        );
+    }
+
+    #[test]
+    fn argument_errors_name_the_position() {
+        let args = [Value::number(1.0), Value::boolean(true)];
+        assert_eq!(
+            <(f64, String)>::from_lox_args(&args, &vm()),
+            Err(RuntimeError::new(
+                "Expected string but got boolean for argument 2"
+            ))
+        );
+    }
+}
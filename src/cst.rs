@@ -0,0 +1,593 @@
+use std::fmt;
+
+use crate::{
+    compile::Source,
+    diagnostic::Diagnostic,
+    tokens::{Token, TokenType, Tokenizer},
+};
+
+/// Source text that isn't a token.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    Whitespace(String),
+    /// `\n` or `\r\n`.
+    Newline(String),
+    /// A `//` comment, without the line break that ends it.
+    Comment(String),
+}
+
+impl Trivia {
+    pub fn text(&self) -> &str {
+        match self {
+            Trivia::Whitespace(text) | Trivia::Newline(text) | Trivia::Comment(text) => text,
+        }
+    }
+}
+
+/// A token with the trivia around it. A token owns what follows it up to the end of its line, and
+/// the next token owns everything after that, so a comment at the end of a line stays with the
+/// code before it and a comment on its own line goes with the code below it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenType,
+    pub text: String,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+    pub line: i32,
+    pub column: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Program,
+    VarDecl,
+    /// `: type` after a variable's name.
+    TypeAnnotation,
+    ImportDecl,
+    PrintStmt,
+    ExprStmt,
+    Assign,
+    Binary,
+    Unary,
+    Grouping,
+    Call,
+    /// `object.name`.
+    Get,
+    /// `[a, b, c]`.
+    List,
+    /// `{key: value, ...}`.
+    Map,
+    /// `key: value` in a map.
+    MapEntry,
+    /// `object[index]`.
+    Index,
+    Literal,
+    Variable,
+    /// A statement that doesn't parse, kept token for token.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Node(Node),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Element>,
+}
+
+impl Node {
+    fn new(kind: NodeKind, children: Vec<Element>) -> Self {
+        Node { kind, children }
+    }
+
+    /// Every token under this node, in source order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = vec![];
+        for child in &self.children {
+            match child {
+                Element::Node(node) => tokens.append(&mut node.tokens()),
+                Element::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+}
+
+/// Writes the node back out exactly as it was written.
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens() {
+            for trivia in &token.leading {
+                write!(f, "{}", trivia.text())?;
+            }
+            write!(f, "{}", token.text)?;
+            for trivia in &token.trailing {
+                write!(f, "{}", trivia.text())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A concrete syntax tree: every character of the source is in it, so printing it gives the source
+/// back. Unlike `Parser`, building one doesn't stop at the first error, a statement that doesn't
+/// parse becomes an `Error` node and parsing goes on with the next one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub program: Node,
+    /// Whatever follows the last token.
+    pub end: Vec<Trivia>,
+    pub errors: Vec<Diagnostic>,
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for trivia in &self.end {
+            write!(f, "{}", trivia.text())?;
+        }
+        Ok(())
+    }
+}
+
+fn split_trivia(text: &str) -> Vec<Trivia> {
+    let mut trivia = vec![];
+    let mut rest = text;
+    while !rest.is_empty() {
+        let (piece, after) = if let Some(after) = rest.strip_prefix("\r\n") {
+            (Trivia::Newline("\r\n".into()), after)
+        } else if let Some(after) = rest.strip_prefix('\n') {
+            (Trivia::Newline("\n".into()), after)
+        } else if rest.starts_with("//") {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            let end = if rest[..end].ends_with('\r') {
+                end - 1
+            } else {
+                end
+            };
+            (Trivia::Comment(rest[..end].into()), &rest[end..])
+        } else {
+            // Spaces, tabs and lone carriage returns
+            let end = rest
+                .char_indices()
+                .find(|(i, _)| {
+                    let rest = &rest[*i..];
+                    rest.starts_with('\n') || rest.starts_with("\r\n") || rest.starts_with("//")
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            (Trivia::Whitespace(rest[..end].into()), &rest[end..])
+        };
+        trivia.push(piece);
+        rest = after;
+    }
+    trivia
+}
+
+/// Tokenizes `source` without losing anything: the text between tokens becomes their trivia.
+fn syntax_tokens(source: &str) -> (Vec<SyntaxToken>, Vec<Trivia>) {
+    let mut tokens: Vec<SyntaxToken> = vec![];
+    let mut end = 0;
+    let source_tokens: Vec<Token> = Tokenizer::new(&Source(source.into())).collect();
+    let attach = |tokens: &mut Vec<SyntaxToken>, gap: &str| {
+        let mut trivia = split_trivia(gap);
+        if let Some(previous) = tokens.last_mut() {
+            let newline = trivia
+                .iter()
+                .position(|piece| matches!(piece, Trivia::Newline(_)))
+                .unwrap_or(trivia.len());
+            let rest = trivia.split_off(newline);
+            previous.trailing = trivia;
+            trivia = rest;
+        }
+        trivia
+    };
+    for token in source_tokens {
+        let leading = attach(&mut tokens, &source[end..token.span.start]);
+        tokens.push(SyntaxToken {
+            kind: token.token_type,
+            text: source[token.span.start..token.span.end].into(),
+            leading,
+            trailing: vec![],
+            line: token.line,
+            column: token.column,
+        });
+        end = token.span.end;
+    }
+    let rest = attach(&mut tokens, &source[end..]);
+    (tokens, rest)
+}
+
+struct CstParser {
+    tokens: Vec<SyntaxToken>,
+    current: usize,
+}
+
+type Parsed = Result<Element, Diagnostic>;
+
+impl CstParser {
+    fn peek(&self) -> Option<&TokenType> {
+        self.tokens.get(self.current).map(|token| &token.kind)
+    }
+
+    fn next_is(&self, kind: TokenType) -> bool {
+        self.peek() == Some(&kind)
+    }
+
+    fn bump(&mut self) -> Element {
+        let token = self.tokens[self.current].clone();
+        self.current += 1;
+        Element::Token(token)
+    }
+
+    /// An error at the next token, or at the last one at the end of the input.
+    fn error(&self, message: impl Into<String>) -> Diagnostic {
+        let token = self
+            .tokens
+            .get(self.current)
+            .or(self.tokens.last())
+            .expect("errors are only raised once there are tokens");
+        Diagnostic::error(message, token.line, token.column)
+    }
+
+    fn expect(&mut self, kind: TokenType, message: &str) -> Parsed {
+        if self.next_is(kind) {
+            Ok(self.bump())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    /// On an error, the whole statement up to its `;` ends up in an `Error` node instead.
+    fn declaration(&mut self) -> Result<Node, (Node, Diagnostic)> {
+        let start = self.current;
+        let statement = match self.peek() {
+            Some(TokenType::Var) => self.var_declaration(),
+            Some(TokenType::Import) => self.import_declaration(),
+            Some(TokenType::Print) => self.print_statement(),
+            _ => self.expression_statement(),
+        };
+        statement.map_err(|error| {
+            self.current = start;
+            let mut tokens = vec![];
+            while let Some(kind) = self.peek() {
+                let semicolon = *kind == TokenType::Semicolon;
+                tokens.push(self.bump());
+                if semicolon {
+                    break;
+                }
+            }
+            (Node::new(NodeKind::Error, tokens), error)
+        })
+    }
+
+    fn var_declaration(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.bump()];
+        match self.peek() {
+            Some(TokenType::Identifier(_)) => children.push(self.bump()),
+            _ => return Err(self.error("Expected variable name")),
+        }
+        if self.next_is(TokenType::Colon) {
+            let colon = self.bump();
+            let name = match self.peek() {
+                Some(TokenType::Identifier(_) | TokenType::Nil) => self.bump(),
+                _ => return Err(self.error("Expected a type after :")),
+            };
+            children.push(Element::Node(Node::new(
+                NodeKind::TypeAnnotation,
+                vec![colon, name],
+            )));
+        }
+        if self.next_is(TokenType::Equal) {
+            children.push(self.bump());
+            children.push(self.expression()?);
+        }
+        children.push(self.expect(TokenType::Semicolon, "Expected ;")?);
+        Ok(Node::new(NodeKind::VarDecl, children))
+    }
+
+    fn import_declaration(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.bump()];
+        match self.peek() {
+            Some(TokenType::Str(_)) => children.push(self.bump()),
+            _ => return Err(self.error("Expected a path after import")),
+        }
+        if matches!(self.peek(), Some(TokenType::Identifier(name)) if name == "as") {
+            children.push(self.bump());
+            match self.peek() {
+                Some(TokenType::Identifier(_)) => children.push(self.bump()),
+                _ => return Err(self.error("Expected a name after as")),
+            }
+        }
+        children.push(self.expect(TokenType::Semicolon, "Expected ;")?);
+        Ok(Node::new(NodeKind::ImportDecl, children))
+    }
+
+    fn print_statement(&mut self) -> Result<Node, Diagnostic> {
+        let children = vec![
+            self.bump(),
+            self.expression()?,
+            self.expect(TokenType::Semicolon, "Expected ;")?,
+        ];
+        Ok(Node::new(NodeKind::PrintStmt, children))
+    }
+
+    fn expression_statement(&mut self) -> Result<Node, Diagnostic> {
+        let children = vec![
+            self.expression()?,
+            self.expect(TokenType::Semicolon, "Expected ;")?,
+        ];
+        Ok(Node::new(NodeKind::ExprStmt, children))
+    }
+
+    fn expression(&mut self) -> Parsed {
+        let target = self.binary(0)?;
+        if !self.next_is(TokenType::Equal) {
+            return Ok(target);
+        }
+        match target {
+            Element::Node(Node {
+                kind: NodeKind::Variable | NodeKind::Index,
+                ..
+            }) => {
+                let equal = self.bump();
+                let value = self.expression()?;
+                Ok(Element::Node(Node::new(
+                    NodeKind::Assign,
+                    vec![target, equal, value],
+                )))
+            }
+            _ => Err(self.error("Invalid assignment target")),
+        }
+    }
+
+    fn binary(&mut self, precedence: i32) -> Parsed {
+        let mut left = self.unary()?;
+        while let Some(operator) = self.peek().and_then(binary_precedence) {
+            if operator <= precedence {
+                break;
+            }
+            let token = self.bump();
+            let right = self.binary(operator)?;
+            left = Element::Node(Node::new(NodeKind::Binary, vec![left, token, right]));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Parsed {
+        match self.peek() {
+            Some(TokenType::Bang | TokenType::Minus | TokenType::Plus) => {
+                let operator = self.bump();
+                let operand = self.unary()?;
+                Ok(Element::Node(Node::new(
+                    NodeKind::Unary,
+                    vec![operator, operand],
+                )))
+            }
+            _ => self.call(),
+        }
+    }
+
+    fn call(&mut self) -> Parsed {
+        let mut callee = self.primary()?;
+        loop {
+            if self.next_is(TokenType::LeftBracket) {
+                let children = vec![
+                    callee,
+                    self.bump(),
+                    self.expression()?,
+                    self.expect(TokenType::RightBracket, "Expected ] after index")?,
+                ];
+                callee = Element::Node(Node::new(NodeKind::Index, children));
+                continue;
+            }
+            if self.next_is(TokenType::Dot) {
+                let dot = self.bump();
+                let name = match self.peek() {
+                    Some(TokenType::Identifier(_)) => self.bump(),
+                    _ => return Err(self.error("Expected a name after .")),
+                };
+                callee = Element::Node(Node::new(NodeKind::Get, vec![callee, dot, name]));
+                continue;
+            }
+            if !self.next_is(TokenType::LeftParen) {
+                break;
+            }
+            let mut children = vec![callee, self.bump()];
+            if !self.next_is(TokenType::RightParen) {
+                loop {
+                    children.push(self.expression()?);
+                    if !self.next_is(TokenType::Comma) {
+                        break;
+                    }
+                    children.push(self.bump());
+                }
+            }
+            children.push(self.expect(TokenType::RightParen, "Expected ) after arguments")?);
+            callee = Element::Node(Node::new(NodeKind::Call, children));
+        }
+        Ok(callee)
+    }
+
+    fn primary(&mut self) -> Parsed {
+        let kind = match self.peek() {
+            Some(
+                TokenType::Number(_)
+                | TokenType::Str(_)
+                | TokenType::True
+                | TokenType::False
+                | TokenType::Nil,
+            ) => NodeKind::Literal,
+            Some(TokenType::Identifier(_)) => NodeKind::Variable,
+            Some(TokenType::LeftBracket) => {
+                let mut children = vec![self.bump()];
+                if !self.next_is(TokenType::RightBracket) {
+                    loop {
+                        children.push(self.expression()?);
+                        if !self.next_is(TokenType::Comma) {
+                            break;
+                        }
+                        children.push(self.bump());
+                    }
+                }
+                children.push(self.expect(TokenType::RightBracket, "Expected ] after list items")?);
+                return Ok(Element::Node(Node::new(NodeKind::List, children)));
+            }
+            Some(TokenType::LeftBrace) => {
+                let mut children = vec![self.bump()];
+                if !self.next_is(TokenType::RightBrace) {
+                    loop {
+                        let entry = vec![
+                            self.expression()?,
+                            self.expect(TokenType::Colon, "Expected : after map key")?,
+                            self.expression()?,
+                        ];
+                        children.push(Element::Node(Node::new(NodeKind::MapEntry, entry)));
+                        if !self.next_is(TokenType::Comma) {
+                            break;
+                        }
+                        children.push(self.bump());
+                    }
+                }
+                children.push(self.expect(TokenType::RightBrace, "Expected } after map entries")?);
+                return Ok(Element::Node(Node::new(NodeKind::Map, children)));
+            }
+            Some(TokenType::LeftParen) => {
+                let children = vec![
+                    self.bump(),
+                    self.expression()?,
+                    self.expect(TokenType::RightParen, "Expected )")?,
+                ];
+                return Ok(Element::Node(Node::new(NodeKind::Grouping, children)));
+            }
+            Some(kind) => return Err(self.error(format!("Unexpected token {:?}", kind))),
+            None => return Err(self.error("Unexpected end of input")),
+        };
+        Ok(Element::Node(Node::new(kind, vec![self.bump()])))
+    }
+}
+
+/// Same order as `Token::precedence`, for the tokens that are binary operators.
+fn binary_precedence(kind: &TokenType) -> Option<i32> {
+    match kind {
+        TokenType::Star | TokenType::Slash => Some(6),
+        TokenType::Plus | TokenType::Minus => Some(5),
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+            Some(4)
+        }
+        TokenType::EqualEqual | TokenType::BangEqual => Some(3),
+        _ => None,
+    }
+}
+
+/// Builds the concrete syntax tree of a whole file.
+pub fn parse(source: &str) -> SyntaxTree {
+    let (tokens, end) = syntax_tokens(source);
+    let mut parser = CstParser { tokens, current: 0 };
+    let mut statements = vec![];
+    let mut errors = vec![];
+    while parser.peek().is_some() {
+        match parser.declaration() {
+            Ok(node) => statements.push(Element::Node(node)),
+            Err((node, error)) => {
+                statements.push(Element::Node(node));
+                errors.push(error);
+            }
+        }
+    }
+    SyntaxTree {
+        program: Node::new(NodeKind::Program, statements),
+        end,
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod test_cst {
+    use super::*;
+
+    fn kinds(node: &Node) -> Vec<NodeKind> {
+        node.children
+            .iter()
+            .filter_map(|child| match child {
+                Element::Node(node) => Some(node.kind),
+                Element::Token(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gives_back_the_source() {
+        let source = "// header\r\nvar a = 1 ;  // one\n\n\tprint  -a+ clock( 1,2 ) ;\n// end\n";
+        let tree = parse(source);
+        assert!(tree.errors.is_empty());
+        assert_eq!(tree.to_string(), source);
+        assert_eq!(
+            kinds(&tree.program),
+            vec![NodeKind::VarDecl, NodeKind::PrintStmt]
+        );
+        assert_eq!(
+            tree.end,
+            vec![
+                Trivia::Newline("\n".into()),
+                Trivia::Comment("// end".into()),
+                Trivia::Newline("\n".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_go_with_the_right_token() {
+        let tree = parse("a = 1; // same line\n// next line\nb;");
+        let tokens = tree.program.tokens();
+        assert_eq!(
+            tokens[3].trailing,
+            vec![
+                Trivia::Whitespace(" ".into()),
+                Trivia::Comment("// same line".into())
+            ]
+        );
+        assert_eq!(
+            tokens[4].leading,
+            vec![
+                Trivia::Newline("\n".into()),
+                Trivia::Comment("// next line".into()),
+                Trivia::Newline("\n".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_imports_members_lists_and_maps() {
+        let source = "import \"util.lox\";\nimport \"lib/math.lox\"  as  m ;\nprint m.max(1, 2);\nl[ 0 ] = [ 1,2 ];\nprint { \"a\" :1, 2: {} }[\"a\"];";
+        let tree = parse(source);
+        assert!(tree.errors.is_empty());
+        assert_eq!(tree.to_string(), source);
+        assert_eq!(
+            kinds(&tree.program),
+            vec![
+                NodeKind::ImportDecl,
+                NodeKind::ImportDecl,
+                NodeKind::PrintStmt,
+                NodeKind::ExprStmt,
+                NodeKind::PrintStmt
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_going_after_an_error() {
+        let source = "print (1;\nvar = 2;\nprint 3;";
+        let tree = parse(source);
+        assert_eq!(tree.to_string(), source);
+        assert_eq!(
+            kinds(&tree.program),
+            vec![NodeKind::Error, NodeKind::Error, NodeKind::PrintStmt]
+        );
+        assert_eq!(tree.errors.len(), 2);
+        assert_eq!(tree.errors[1].line, 2);
+    }
+}
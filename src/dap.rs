@@ -0,0 +1,592 @@
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use rlox::{
+    common::Chunk,
+    compile::{self, Source},
+    debug::{globals, DebugHook, Location, Resume, Stepper, StopReason},
+    json::Json,
+    vm::{InterpretMode, InterpretResult, VM},
+};
+
+use crate::rpc::{read_message, write_message};
+
+/// Lox has a single thread, and editors still want to be told its id.
+const THREAD_ID: i32 = 1;
+/// `variablesReference` of the only scope there is so far.
+const GLOBALS: usize = 1;
+
+/// The editor's end of the connection, shared between the session and the script's output.
+struct Sender {
+    out: Box<dyn Write>,
+    seq: usize,
+}
+
+impl Sender {
+    fn send(&mut self, fields: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        let seq = ("seq", Json::from(self.seq));
+        write_message(
+            &mut self.out,
+            &Json::object([seq].into_iter().chain(fields)),
+        )
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(vec![
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+            ("body", body),
+        ])
+    }
+
+    fn output(&mut self, category: &str, text: &str) -> io::Result<()> {
+        self.event(
+            "output",
+            Json::object([
+                ("category", Json::from(category)),
+                ("output", Json::from(text)),
+            ]),
+        )
+    }
+}
+
+/// What `print` writes, forwarded to the editor as `output` events a line at a time.
+struct OutputEvents {
+    sender: Rc<RefCell<Sender>>,
+    line: Vec<u8>,
+}
+
+impl OutputEvents {
+    fn send(&mut self, end: usize) -> io::Result<()> {
+        let text: Vec<u8> = self.line.drain(..end).collect();
+        self.sender
+            .borrow_mut()
+            .output("stdout", &String::from_utf8_lossy(&text))
+    }
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        if let Some(newline) = self.line.iter().rposition(|byte| *byte == b'\n') {
+            self.send(newline + 1)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.send(self.line.len())?;
+        }
+        Ok(())
+    }
+}
+
+fn command(request: &Json) -> &str {
+    request.get("command").and_then(Json::as_str).unwrap_or("")
+}
+
+fn arguments(request: &Json) -> &Json {
+    request.get("arguments").unwrap_or(&Json::Null)
+}
+
+fn stack_trace(vm: &VM) -> Json {
+    let frames: Vec<_> = vm
+        .frames
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(id, frame)| {
+            Json::object([
+                ("id", Json::from(id)),
+                ("name", Json::from(frame.chunk.name())),
+                (
+                    "source",
+                    Json::object([("path", Json::from(frame.chunk.name()))]),
+                ),
+                ("line", Json::from(frame.line())),
+                ("column", Json::from(1)),
+            ])
+        })
+        .collect();
+    Json::object([
+        ("totalFrames", Json::from(frames.len())),
+        ("stackFrames", Json::Array(frames)),
+    ])
+}
+
+fn variables(vm: &VM, arguments: &Json) -> Json {
+    let reference = arguments.get("variablesReference").and_then(Json::as_f64);
+    let variables = if reference == Some(GLOBALS as f64) {
+        globals(vm)
+            .into_iter()
+            .map(|(name, value)| {
+                Json::object([
+                    ("name", Json::from(name)),
+                    ("value", Json::from(value.print_lox(&vm.heap))),
+                    ("variablesReference", Json::from(0)),
+                ])
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    Json::object([("variables", Json::Array(variables))])
+}
+
+/// One debug session with an editor. Requests are only read while the script is paused, or before
+/// and after it runs, so there's no way to pause a script that's running.
+struct Session {
+    input: Box<dyn BufRead>,
+    sender: Rc<RefCell<Sender>>,
+    stepper: Stepper,
+}
+
+impl Session {
+    fn read_request(&mut self) -> io::Result<Option<Json>> {
+        read_message(&mut self.input)
+    }
+
+    fn event(&self, event: &str, body: Json) -> io::Result<()> {
+        self.sender.borrow_mut().event(event, body)
+    }
+
+    fn respond(&self, request: &Json, body: Result<Json, String>) -> io::Result<()> {
+        let mut fields = vec![
+            ("type", Json::from("response")),
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("command", Json::from(command(request))),
+            ("success", Json::from(body.is_ok())),
+        ];
+        match body {
+            Ok(Json::Null) => {}
+            Ok(body) => fields.push(("body", body)),
+            Err(message) => fields.push(("message", Json::from(message))),
+        }
+        self.sender.borrow_mut().send(fields)
+    }
+
+    /// Answers requests until the editor has both launched a program and finished setting up, and
+    /// returns the compiled program. `None` means the editor went away first.
+    fn configure(&mut self, vm: &mut VM) -> io::Result<Option<Chunk>> {
+        let mut chunk = None;
+        let mut configured = false;
+        while chunk.is_none() || !configured {
+            let Some(request) = self.read_request()? else {
+                return Ok(None);
+            };
+            match command(&request) {
+                "initialize" => {
+                    let capabilities =
+                        Json::object([("supportsConfigurationDoneRequest", Json::from(true))]);
+                    self.respond(&request, Ok(capabilities))?;
+                    self.event("initialized", Json::Object(vec![]))?;
+                }
+                "launch" => {
+                    let launched = self.launch(arguments(&request), vm);
+                    self.respond(
+                        &request,
+                        launched.as_ref().map(|_| Json::Null).map_err(String::clone),
+                    )?;
+                    chunk = launched.ok();
+                }
+                "configurationDone" => {
+                    self.respond(&request, Ok(Json::Null))?;
+                    configured = true;
+                }
+                _ => {
+                    if self.handle(&request, None)? == Some(Resume::Quit) {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+        Ok(chunk)
+    }
+
+    fn launch(&mut self, arguments: &Json, vm: &mut VM) -> Result<Chunk, String> {
+        let program = arguments
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("launch needs a program to run")?;
+        let source = fs::read_to_string(program)
+            .map_err(|error| format!("Can't read {}: {}", program, error))?;
+        // Editors send breakpoints with absolute paths and expect them back in stack traces
+        let path = fs::canonicalize(program)
+            .map_or(program.into(), |path| path.to_string_lossy().into_owned());
+        if !arguments
+            .get("stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false)
+        {
+            self.stepper.resume();
+        }
+        let mut session = compile::Session::new(vm, &[]);
+        Source(source)
+            .try_compile(&path, &mut vm.heap, &mut session)
+            .map_err(|errors| errors.join("\n"))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .ok_or("setBreakpoints needs a source path")?;
+        let lines: Vec<i32> = arguments
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|breakpoint| breakpoint.get("line").and_then(Json::as_f64))
+            .map(|line| line as i32)
+            .collect();
+        self.stepper.set_breakpoints(path, lines.iter().copied());
+        let breakpoints = lines
+            .into_iter()
+            .map(|line| Json::object([("verified", Json::from(true)), ("line", Json::from(line))]))
+            .collect();
+        Ok(Json::object([("breakpoints", Json::Array(breakpoints))]))
+    }
+
+    /// Answers a request that can come at any point of the session. `vm` is there while the script
+    /// is paused, and what's returned tells it how to go on.
+    fn handle(&mut self, request: &Json, vm: Option<&VM>) -> io::Result<Option<Resume>> {
+        let (body, resume) = match (command(request), vm) {
+            ("setBreakpoints", _) => (self.set_breakpoints(arguments(request)), None),
+            ("threads", _) => {
+                let thread =
+                    Json::object([("id", Json::from(THREAD_ID)), ("name", Json::from("main"))]);
+                (
+                    Ok(Json::object([("threads", Json::Array(vec![thread]))])),
+                    None,
+                )
+            }
+            ("stackTrace", Some(vm)) => (Ok(stack_trace(vm)), None),
+            ("scopes", Some(_)) => {
+                let scope = Json::object([
+                    ("name", Json::from("Globals")),
+                    ("variablesReference", Json::from(GLOBALS)),
+                    ("expensive", Json::from(false)),
+                ]);
+                (
+                    Ok(Json::object([("scopes", Json::Array(vec![scope]))])),
+                    None,
+                )
+            }
+            ("variables", Some(vm)) => (Ok(variables(vm, arguments(request))), None),
+            ("continue", Some(_)) => {
+                self.stepper.resume();
+                let body = Json::object([("allThreadsContinued", Json::from(true))]);
+                (Ok(body), Some(Resume::Continue))
+            }
+            ("next", Some(vm)) => {
+                self.stepper.next(vm);
+                (Ok(Json::Null), Some(Resume::Continue))
+            }
+            ("stepIn", Some(_)) => {
+                self.stepper.step();
+                (Ok(Json::Null), Some(Resume::Continue))
+            }
+            ("stepOut", Some(vm)) => {
+                self.stepper.finish(vm);
+                (Ok(Json::Null), Some(Resume::Continue))
+            }
+            ("disconnect", _) => (Ok(Json::Null), Some(Resume::Quit)),
+            (
+                "stackTrace" | "scopes" | "variables" | "continue" | "next" | "stepIn" | "stepOut",
+                None,
+            ) => (Err("The program isn't paused".into()), None),
+            (command, _) => (Err(format!("Unsupported request {}", command)), None),
+        };
+        self.respond(request, body)?;
+        Ok(resume)
+    }
+
+    fn pause(&mut self, vm: &VM, reason: StopReason) -> io::Result<Resume> {
+        let reason = match reason {
+            StopReason::Entry => "entry",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+        };
+        self.event(
+            "stopped",
+            Json::object([
+                ("reason", Json::from(reason)),
+                ("threadId", Json::from(THREAD_ID)),
+                ("allThreadsStopped", Json::from(true)),
+            ]),
+        )?;
+        loop {
+            let Some(request) = self.read_request()? else {
+                return Ok(Resume::Quit);
+            };
+            if let Some(resume) = self.handle(&request, Some(vm))? {
+                return Ok(resume);
+            }
+        }
+    }
+}
+
+impl DebugHook for Session {
+    fn before_instruction(&mut self, vm: &VM, line: i32) -> Resume {
+        let Some(reason) = self.stepper.should_stop(Location::of(vm, line)) else {
+            return Resume::Continue;
+        };
+        self.pause(vm, reason).unwrap_or(Resume::Quit)
+    }
+}
+
+// The VM owns its debugger, but the session is still needed once the script is done.
+struct Attached(Rc<RefCell<Session>>);
+
+impl DebugHook for Attached {
+    fn before_instruction(&mut self, vm: &VM, line: i32) -> Resume {
+        self.0.borrow_mut().before_instruction(vm, line)
+    }
+}
+
+/// Runs one debug session over `input` and `out`, from `initialize` to `disconnect`.
+pub fn serve(input: Box<dyn BufRead>, out: Box<dyn Write>) -> io::Result<()> {
+    let sender = Rc::new(RefCell::new(Sender { out, seq: 0 }));
+    let session = Rc::new(RefCell::new(Session {
+        input,
+        sender: sender.clone(),
+        stepper: Stepper::new(),
+    }));
+    let mut vm = VM::new();
+    let Some(chunk) = session.borrow_mut().configure(&mut vm)? else {
+        return Ok(());
+    };
+    vm.output = Box::new(OutputEvents {
+        sender: sender.clone(),
+        line: vec![],
+    });
+    vm.debugger = Some(Box::new(Attached(session.clone())));
+    let result = vm.interpret(chunk, InterpretMode::Release);
+    vm.output.flush()?;
+    let exit_code = match result {
+        InterpretResult::Ok => 0,
+        InterpretResult::RuntimeError(error) => {
+            sender
+                .borrow_mut()
+                .output("stderr", &format!("{}\n", error))?;
+            70
+        }
+        // The editor disconnected, or went away
+        InterpretResult::Stopped => return Ok(()),
+    };
+    let mut session = session.borrow_mut();
+    session.event(
+        "exited",
+        Json::object([("exitCode", Json::from(exit_code))]),
+    )?;
+    session.event("terminated", Json::Object(vec![]))?;
+    while let Some(request) = session.read_request()? {
+        if session.handle(&request, None)? == Some(Resume::Quit) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// `rlox dap`: a Debug Adapter Protocol server on stdin and stdout, for editors to launch.
+pub fn dap(args: &[String]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: rlox dap".into());
+    }
+    serve(
+        Box::new(io::BufReader::new(io::stdin())),
+        Box::new(io::stdout()),
+    )
+    .map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod test_dap {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{self, SharedBuffer};
+
+    // The adapter reports canonical paths, so the tests compare against those
+    fn script(name: &str, source: &str) -> String {
+        let path = test_util::script(name, source);
+        fs::canonicalize(path).unwrap().to_string_lossy().into()
+    }
+
+    fn request(command: &str, arguments: Json) -> Json {
+        Json::object([
+            ("type", Json::from("request")),
+            ("command", Json::from(command)),
+            ("arguments", arguments),
+        ])
+    }
+
+    /// Plays the part of an editor that sends every request up front, and returns what the server
+    /// sent back.
+    fn session(program: &str, stop_on_entry: bool, requests: Vec<Json>) -> Vec<Json> {
+        let mut input = vec![];
+        let setup = [
+            request(
+                "initialize",
+                Json::object([("adapterID", Json::from("rlox"))]),
+            ),
+            request(
+                "launch",
+                Json::object([
+                    ("program", Json::from(program)),
+                    ("stopOnEntry", Json::from(stop_on_entry)),
+                ]),
+            ),
+        ];
+        for (seq, mut message) in setup.into_iter().chain(requests).enumerate() {
+            if let Json::Object(fields) = &mut message {
+                fields.insert(0, ("seq".into(), Json::from(seq + 1)));
+            }
+            write_message(&mut input, &message).unwrap();
+        }
+        let out = SharedBuffer::default();
+        serve(Box::new(Cursor::new(input)), Box::new(out.clone())).unwrap();
+        let mut output = Cursor::new(out.take());
+        let mut messages = vec![];
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn events<'a>(messages: &'a [Json], event: &'a str) -> impl Iterator<Item = &'a Json> {
+        messages
+            .iter()
+            .filter(move |message| message.get("event").and_then(Json::as_str) == Some(event))
+    }
+
+    fn response<'a>(messages: &'a [Json], command: &str) -> &'a Json {
+        messages
+            .iter()
+            .find(|message| {
+                message.get("type").and_then(Json::as_str) == Some("response")
+                    && message.get("command").and_then(Json::as_str) == Some(command)
+            })
+            .unwrap()
+    }
+
+    fn output(messages: &[Json]) -> String {
+        events(messages, "output")
+            .filter_map(|event| event.get("body")?.get("output")?.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn stops_at_a_breakpoint_and_shows_the_globals() {
+        let program = script(
+            "breakpoint",
+            "var a = 1;\na = a + 1;\nprint a;\nprint a + 1;\n",
+        );
+        let messages = session(
+            &program,
+            false,
+            vec![
+                request(
+                    "setBreakpoints",
+                    Json::object([
+                        (
+                            "source",
+                            Json::object([("path", Json::from(program.as_str()))]),
+                        ),
+                        (
+                            "breakpoints",
+                            Json::Array(vec![Json::object([("line", Json::from(3))])]),
+                        ),
+                    ]),
+                ),
+                request("configurationDone", Json::Null),
+                request("stackTrace", Json::object([("threadId", Json::from(1))])),
+                request("scopes", Json::object([("frameId", Json::from(0))])),
+                request(
+                    "variables",
+                    Json::object([("variablesReference", Json::from(GLOBALS))]),
+                ),
+                request("next", Json::object([("threadId", Json::from(1))])),
+                request("continue", Json::object([("threadId", Json::from(1))])),
+                request("disconnect", Json::Null),
+            ],
+        );
+        assert!(events(&messages, "initialized").next().is_some());
+        let stops: Vec<_> = events(&messages, "stopped")
+            .filter_map(|event| event.get("body")?.get("reason")?.as_str())
+            .collect();
+        assert_eq!(stops, ["breakpoint", "step"]);
+
+        let frames = response(&messages, "stackTrace").get("body").unwrap();
+        let top = &frames.get("stackFrames").and_then(Json::as_array).unwrap()[0];
+        assert_eq!(top.get("line"), Some(&Json::from(3)));
+        assert_eq!(
+            top.get("source").and_then(|source| source.get("path")),
+            Some(&Json::from(program.as_str()))
+        );
+
+        let variables = response(&messages, "variables").get("body").unwrap();
+        let variables = variables.get("variables").and_then(Json::as_array).unwrap();
+        let a = variables
+            .iter()
+            .find(|variable| variable.get("name") == Some(&Json::from("a")))
+            .unwrap();
+        assert_eq!(a.get("value"), Some(&Json::from("2")));
+
+        assert_eq!(output(&messages), "2\n3\n");
+        let exited = events(&messages, "exited").next().unwrap();
+        assert_eq!(
+            exited.get("body").and_then(|body| body.get("exitCode")),
+            Some(&Json::from(0))
+        );
+        assert!(events(&messages, "terminated").next().is_some());
+        assert_eq!(
+            response(&messages, "disconnect").get("success"),
+            Some(&Json::from(true))
+        );
+    }
+
+    #[test]
+    fn steps_from_entry_and_reports_runtime_errors() {
+        let program = script("steps", "var a = 1;\nprint a + nil;\n");
+        let messages = session(
+            &program,
+            true,
+            vec![
+                request("configurationDone", Json::Null),
+                request("stepIn", Json::object([("threadId", Json::from(1))])),
+                request("stepOut", Json::object([("threadId", Json::from(1))])),
+                request("disconnect", Json::Null),
+            ],
+        );
+        let stops: Vec<_> = events(&messages, "stopped")
+            .filter_map(|event| event.get("body")?.get("reason")?.as_str())
+            .collect();
+        assert_eq!(stops, ["entry", "step"]);
+        assert!(output(&messages).starts_with("Error at line 2"));
+        let exited = events(&messages, "exited").next().unwrap();
+        assert_eq!(
+            exited.get("body").and_then(|body| body.get("exitCode")),
+            Some(&Json::from(70))
+        );
+    }
+
+    #[test]
+    fn refuses_to_launch_a_broken_program() {
+        let program = script("broken", "print (1;\n");
+        let messages = session(&program, false, vec![request("disconnect", Json::Null)]);
+        let launch = response(&messages, "launch");
+        assert_eq!(launch.get("success"), Some(&Json::from(false)));
+        assert!(events(&messages, "exited").next().is_none());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{common::Value, vm::VM};
+
+/// What the VM should do after a debug hook returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    /// Stop running the script altogether.
+    Quit,
+}
+
+/// Called by the VM before every instruction while attached, with the frame's `ip` up to date. A
+/// debugger pauses the script simply by not returning until the user resumes it.
+pub trait DebugHook {
+    fn before_instruction(&mut self, vm: &VM, line: i32) -> Resume;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Before the first instruction.
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepMode {
+    Entry,
+    Continue,
+    /// Stop at the next line, whatever the frame.
+    Step,
+    /// Stop at the next line in this frame or one of its callers.
+    Next(usize),
+    /// Stop at the next line once this frame has returned.
+    Finish(usize),
+}
+
+/// Where execution is: the file of the running chunk, its frame depth and the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub depth: usize,
+    pub line: i32,
+}
+
+impl Location {
+    pub fn of(vm: &VM, line: i32) -> Location {
+        Location {
+            file: vm
+                .frames
+                .last()
+                .map_or(String::new(), |frame| frame.chunk.name().into()),
+            depth: vm.frames.len(),
+            line,
+        }
+    }
+}
+
+/// Global variables sorted by name, the way debuggers and the REPL list them. The globals of
+/// imported modules are left out: their names are prefixed with the module's path and `::`, and
+/// can't be written in a script anyway.
+pub fn globals(vm: &VM) -> Vec<(&str, Value)> {
+    let mut globals: Vec<_> = vm
+        .globals
+        .iter()
+        .map(|(name, value)| (vm.heap.as_str(name).unwrap_or_default(), *value))
+        .filter(|(name, _)| !name.contains("::"))
+        .collect();
+    globals.sort_by_key(|(name, _)| *name);
+    globals
+}
+
+/// Breakpoints and stepping, shared by the debugger front ends. Stops only happen when execution
+/// reaches a new line, so a line made of several instructions is stepped over in one go.
+pub struct Stepper {
+    breakpoints: HashMap<String, HashSet<i32>>,
+    mode: StepMode,
+    last: Option<Location>,
+}
+
+impl Default for Stepper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `file` as the user typed it matches a chunk named by a longer path, so `fib.lox` can stand for
+// `benches/fib.lox`.
+fn same_file(chunk_name: &str, file: &str) -> bool {
+    chunk_name == file || chunk_name.ends_with(&format!("/{}", file))
+}
+
+impl Stepper {
+    /// A stepper that stops before the first instruction.
+    pub fn new() -> Self {
+        Stepper {
+            breakpoints: HashMap::new(),
+            mode: StepMode::Entry,
+            last: None,
+        }
+    }
+
+    /// Doesn't stop at entry, only at breakpoints.
+    pub fn running() -> Self {
+        Stepper {
+            mode: StepMode::Continue,
+            ..Self::new()
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, file: &str, line: i32) {
+        self.breakpoints
+            .entry(file.into())
+            .or_default()
+            .insert(line);
+    }
+
+    pub fn remove_breakpoint(&mut self, file: &str, line: i32) -> bool {
+        self.breakpoints
+            .get_mut(file)
+            .is_some_and(|lines| lines.remove(&line))
+    }
+
+    /// Replaces every breakpoint in `file`.
+    pub fn set_breakpoints(&mut self, file: &str, lines: impl IntoIterator<Item = i32>) {
+        self.breakpoints
+            .insert(file.into(), lines.into_iter().collect());
+    }
+
+    /// Breakpoints as `(file, line)`, sorted.
+    pub fn breakpoints(&self) -> Vec<(&str, i32)> {
+        let mut breakpoints: Vec<_> = self
+            .breakpoints
+            .iter()
+            .flat_map(|(file, lines)| lines.iter().map(move |line| (file.as_str(), *line)))
+            .collect();
+        breakpoints.sort();
+        breakpoints
+    }
+
+    fn is_breakpoint(&self, location: &Location) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|(file, lines)| same_file(&location.file, file) && lines.contains(&location.line))
+    }
+
+    /// Whether to pause before the instruction about to run.
+    pub fn should_stop(&mut self, location: Location) -> Option<StopReason> {
+        if self.last.as_ref() == Some(&location) {
+            return None;
+        }
+        let reason = if self.mode == StepMode::Entry {
+            Some(StopReason::Entry)
+        } else if self.is_breakpoint(&location) {
+            Some(StopReason::Breakpoint)
+        } else {
+            match self.mode {
+                StepMode::Step => Some(StopReason::Step),
+                StepMode::Next(depth) if location.depth <= depth => Some(StopReason::Step),
+                StepMode::Finish(depth) if location.depth < depth => Some(StopReason::Step),
+                _ => None,
+            }
+        };
+        self.last = Some(location);
+        reason
+    }
+
+    pub fn resume(&mut self) {
+        self.mode = StepMode::Continue;
+    }
+
+    pub fn step(&mut self) {
+        self.mode = StepMode::Step;
+    }
+
+    pub fn next(&mut self, vm: &VM) {
+        self.mode = StepMode::Next(vm.frames.len());
+    }
+
+    pub fn finish(&mut self, vm: &VM) {
+        self.mode = StepMode::Finish(vm.frames.len());
+    }
+}
+
+#[cfg(test)]
+mod test_stepper {
+    use super::*;
+
+    fn at(depth: usize, line: i32) -> Location {
+        Location {
+            file: "scripts/main.lox".into(),
+            depth,
+            line,
+        }
+    }
+
+    #[test]
+    fn stops_at_entry_then_only_at_breakpoints() {
+        let mut stepper = Stepper::new();
+        stepper.add_breakpoint("main.lox", 3);
+        assert_eq!(stepper.should_stop(at(1, 1)), Some(StopReason::Entry));
+        stepper.resume();
+        assert_eq!(stepper.should_stop(at(1, 2)), None);
+        assert_eq!(stepper.should_stop(at(1, 3)), Some(StopReason::Breakpoint));
+        // Further instructions on the same line don't stop again
+        assert_eq!(stepper.should_stop(at(1, 3)), None);
+    }
+
+    #[test]
+    fn next_skips_deeper_frames_and_finish_waits_for_a_return() {
+        let mut stepper = Stepper::running();
+        stepper.mode = StepMode::Next(1);
+        assert_eq!(stepper.should_stop(at(2, 10)), None);
+        assert_eq!(stepper.should_stop(at(1, 4)), Some(StopReason::Step));
+        stepper.mode = StepMode::Finish(2);
+        assert_eq!(stepper.should_stop(at(2, 11)), None);
+        assert_eq!(stepper.should_stop(at(1, 5)), Some(StopReason::Step));
+    }
+
+    #[test]
+    fn breakpoints_can_be_replaced_and_removed() {
+        let mut stepper = Stepper::running();
+        stepper.add_breakpoint("main.lox", 1);
+        stepper.set_breakpoints("main.lox", [4, 2]);
+        assert_eq!(
+            stepper.breakpoints(),
+            vec![("main.lox", 2), ("main.lox", 4)]
+        );
+        assert!(stepper.remove_breakpoint("main.lox", 2));
+        assert!(!stepper.remove_breakpoint("main.lox", 2));
+        assert_eq!(stepper.should_stop(at(1, 4)), Some(StopReason::Breakpoint));
+    }
+}
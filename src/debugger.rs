@@ -0,0 +1,332 @@
+use std::io::{self, BufRead, Write};
+
+use rlox::{
+    compile::{Session, Source},
+    debug::{globals, DebugHook, Location, Resume, Stepper, StopReason},
+    vm::{InterpretMode, InterpretResult, VM},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Break(String, i32),
+    Delete(String, i32),
+    Breakpoints,
+    Step,
+    Next,
+    Finish,
+    Continue,
+    Locals,
+    Globals,
+    Stack,
+    Backtrace,
+    Help,
+    Quit,
+}
+
+const HELP: &str = "\
+break [file:]line    stop when execution reaches the line (b)
+delete [file:]line   remove a breakpoint (d)
+breakpoints          list breakpoints
+step                 run to the next line, entering calls (s)
+next                 run to the next line in this frame (n)
+finish               run until this frame returns (f)
+continue             run to the next breakpoint (c)
+locals               show the locals of the current frame
+globals              show the globals
+stack                show the value stack
+backtrace            show the call frames (bt)
+quit                 stop the script (q)
+An empty line repeats the last command.";
+
+// `file:line`, or just `line` in the file being debugged.
+fn parse_location(location: &str, file: &str) -> Result<(String, i32), String> {
+    let (file, line) = match location.rsplit_once(':') {
+        Some((file, line)) => (file, line),
+        None => (file, location),
+    };
+    match line.parse() {
+        Ok(line) if line > 0 => Ok((file.into(), line)),
+        _ => Err(format!("Expected [file:]line, got {}", location)),
+    }
+}
+
+impl Command {
+    fn parse(line: &str, file: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+        match (command, argument) {
+            ("break" | "b", "") => Err("Usage: break [file:]line".into()),
+            ("break" | "b", location) => {
+                let (file, line) = parse_location(location, file)?;
+                Ok(Command::Break(file, line))
+            }
+            ("delete" | "d", "") => Err("Usage: delete [file:]line".into()),
+            ("delete" | "d", location) => {
+                let (file, line) = parse_location(location, file)?;
+                Ok(Command::Delete(file, line))
+            }
+            ("breakpoints", "") => Ok(Command::Breakpoints),
+            ("step" | "s", "") => Ok(Command::Step),
+            ("next" | "n", "") => Ok(Command::Next),
+            ("finish" | "f", "") => Ok(Command::Finish),
+            ("continue" | "c", "") => Ok(Command::Continue),
+            ("locals", "") => Ok(Command::Locals),
+            ("globals", "") => Ok(Command::Globals),
+            ("stack", "") => Ok(Command::Stack),
+            ("backtrace" | "bt", "") => Ok(Command::Backtrace),
+            ("help" | "h", "") => Ok(Command::Help),
+            ("quit" | "q", "") => Ok(Command::Quit),
+            _ => Err(format!("Unknown command {}, try help", line)),
+        }
+    }
+}
+
+/// The gdb-like prompt behind `rlox debug`. It reads commands from `input` whenever the script is
+/// paused, and writes everything but the script's own output to `out`.
+pub struct CliDebugger {
+    stepper: Stepper,
+    file: String,
+    source: Vec<String>,
+    input: Box<dyn BufRead>,
+    out: Box<dyn Write>,
+    last_command: Option<Command>,
+}
+
+impl CliDebugger {
+    pub fn new(file: &str, source: &str, input: Box<dyn BufRead>, out: Box<dyn Write>) -> Self {
+        CliDebugger {
+            stepper: Stepper::new(),
+            file: file.into(),
+            source: source.lines().map(String::from).collect(),
+            input,
+            out,
+            last_command: None,
+        }
+    }
+
+    fn show_location(&mut self, location: &Location, reason: StopReason) -> io::Result<()> {
+        let reason = match reason {
+            StopReason::Entry => "entry",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+        };
+        writeln!(
+            self.out,
+            "Stopped at {}:{} ({})",
+            location.file, location.line, reason
+        )?;
+        if location.file != self.file {
+            return Ok(());
+        }
+        // Lines count from 1, and the end of an empty file has no line to show
+        let text = (location.line as usize)
+            .checked_sub(1)
+            .and_then(|index| self.source.get(index));
+        if let Some(text) = text {
+            writeln!(self.out, "{:>4} | {}", location.line, text)?;
+        }
+        Ok(())
+    }
+
+    fn read_command(&mut self) -> io::Result<Option<Command>> {
+        loop {
+            write!(self.out, "(rlox) ")?;
+            self.out.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let command = if line.trim().is_empty() {
+                match &self.last_command {
+                    Some(command) => Ok(command.clone()),
+                    None => continue,
+                }
+            } else {
+                Command::parse(&line, &self.file)
+            };
+            match command {
+                Ok(command) => {
+                    self.last_command = Some(command.clone());
+                    return Ok(Some(command));
+                }
+                Err(error) => writeln!(self.out, "{}", error)?,
+            }
+        }
+    }
+
+    /// Runs commands until one resumes the script.
+    fn prompt(&mut self, vm: &VM) -> io::Result<Resume> {
+        loop {
+            let Some(command) = self.read_command()? else {
+                return Ok(Resume::Quit);
+            };
+            match command {
+                Command::Break(file, line) => {
+                    self.stepper.add_breakpoint(&file, line);
+                    writeln!(self.out, "Breakpoint at {}:{}", file, line)?;
+                }
+                Command::Delete(file, line) => {
+                    if !self.stepper.remove_breakpoint(&file, line) {
+                        writeln!(self.out, "No breakpoint at {}:{}", file, line)?;
+                    }
+                }
+                Command::Breakpoints => {
+                    for (file, line) in self.stepper.breakpoints() {
+                        writeln!(self.out, "{}:{}", file, line)?;
+                    }
+                }
+                Command::Step => {
+                    self.stepper.step();
+                    return Ok(Resume::Continue);
+                }
+                Command::Next => {
+                    self.stepper.next(vm);
+                    return Ok(Resume::Continue);
+                }
+                Command::Finish => {
+                    self.stepper.finish(vm);
+                    return Ok(Resume::Continue);
+                }
+                Command::Continue => {
+                    self.stepper.resume();
+                    return Ok(Resume::Continue);
+                }
+                // Lox here has no local variables yet, everything lives in globals
+                Command::Locals => writeln!(self.out, "No locals")?,
+                Command::Globals => {
+                    for (name, value) in globals(vm) {
+                        writeln!(self.out, "{} = {}", name, value.print_lox(&vm.heap))?;
+                    }
+                }
+                Command::Stack => {
+                    for (slot, value) in vm.stack.iter().enumerate() {
+                        writeln!(self.out, "[{}] {}", slot, value.describe(&vm.heap))?;
+                    }
+                }
+                Command::Backtrace => {
+                    for (depth, frame) in vm.frames.iter().rev().enumerate() {
+                        let (name, line) = (frame.chunk.name(), frame.line());
+                        writeln!(self.out, "#{} {}:{}", depth, name, line)?;
+                    }
+                }
+                Command::Help => writeln!(self.out, "{}", HELP)?,
+                Command::Quit => return Ok(Resume::Quit),
+            }
+        }
+    }
+}
+
+impl DebugHook for CliDebugger {
+    fn before_instruction(&mut self, vm: &VM, line: i32) -> Resume {
+        let location = Location::of(vm, line);
+        let Some(reason) = self.stepper.should_stop(location.clone()) else {
+            return Resume::Continue;
+        };
+        self.show_location(&location, reason)
+            .and_then(|_| self.prompt(vm))
+            .unwrap_or(Resume::Quit)
+    }
+}
+
+/// `rlox debug file.lox`: runs the file under the debugger, paused before its first line.
+pub fn debug(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err("Usage: rlox debug file.lox".into());
+    };
+    let source = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let mut vm = VM::new();
+    let mut session = Session::new(&vm, &[]);
+    let chunk = Source(source.clone())
+        .try_compile(path, &mut vm.heap, &mut session)
+        .map_err(|errors| errors.join("\n"))?;
+    let input = Box::new(io::BufReader::new(io::stdin()));
+    vm.debugger = Some(Box::new(CliDebugger::new(
+        path,
+        &source,
+        input,
+        Box::new(io::stdout()),
+    )));
+    match vm.interpret(chunk, InterpretMode::Release) {
+        InterpretResult::Ok => println!("Program finished"),
+        InterpretResult::RuntimeError(error) => {
+            eprintln!("{}", error);
+            println!("Program failed");
+        }
+        InterpretResult::Stopped => println!("Program stopped"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_debugger {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::SharedBuffer;
+
+    fn debug_session(source: &str, commands: &str) -> String {
+        let out = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.output = Box::new(io::sink());
+        let mut session = Session::new(&vm, &[]);
+        let chunk = Source(source.into())
+            .try_compile("test.lox", &mut vm.heap, &mut session)
+            .unwrap();
+        vm.debugger = Some(Box::new(CliDebugger::new(
+            "test.lox",
+            source,
+            Box::new(Cursor::new(commands.to_string())),
+            Box::new(out.clone()),
+        )));
+        vm.interpret(chunk, InterpretMode::Release);
+        out.take_string()
+    }
+
+    #[test]
+    fn stops_in_a_file_with_no_code() {
+        let output = debug_session("// only a comment\n", "quit\n");
+        assert!(output.starts_with("Stopped at test.lox:1 (entry)\n   1 | // only a comment\n"));
+        let output = debug_session("", "quit\n");
+        assert!(output.starts_with("Stopped at test.lox:1 (entry)\n(rlox) "));
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            Command::parse("b 3", "main.lox"),
+            Ok(Command::Break("main.lox".into(), 3))
+        );
+        assert_eq!(
+            Command::parse("break lib/a.lox:12", "main.lox"),
+            Ok(Command::Break("lib/a.lox".into(), 12))
+        );
+        assert_eq!(Command::parse("bt", "main.lox"), Ok(Command::Backtrace));
+        assert!(Command::parse("break 0", "main.lox").is_err());
+        assert!(Command::parse("jump", "main.lox").is_err());
+    }
+
+    #[test]
+    fn stops_at_a_breakpoint_and_inspects_the_vm() {
+        let output = debug_session(
+            "var a = 1;\na = a + 1;\nprint a;\n",
+            "break 3\ncontinue\nglobals\nbt\nquit\n",
+        );
+        assert!(output.starts_with("Stopped at test.lox:1 (entry)\n   1 | var a = 1;\n"));
+        assert!(output.contains("Breakpoint at test.lox:3"));
+        assert!(output.contains("Stopped at test.lox:3 (breakpoint)\n   3 | print a;"));
+        assert!(output.contains("a = 2\n"));
+        assert!(output.contains("#0 test.lox:3\n"));
+    }
+
+    #[test]
+    fn steps_line_by_line() {
+        let output = debug_session("var a = 1;\nvar b = a;\n\nprint b;\n", "next\n\nstack\n");
+        assert!(output.contains("Stopped at test.lox:2 (step)"));
+        assert!(output.contains("Stopped at test.lox:4 (step)"));
+        // Paused before `print b`'s first instruction, nothing is on the stack yet
+        assert!(output.ends_with("   4 | print b;\n(rlox) (rlox) "));
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The file can't be compiled, or `rlox check` fails.
+    Error,
+    /// Most likely a mistake, but the file compiles regardless.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// Something wrong with a piece of source, found by the parser, the resolver or the type checker.
+/// Lines count from 1 and columns from 0, like tokens do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: i32,
+    pub column: i32,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, line: i32, column: i32) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            line,
+            column,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, line: i32, column: i32) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, line, column)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, {}",
+            self.severity, self.line, self.message
+        )
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+use rlox::format::format;
+
+const USAGE: &str = "Usage: rlox fmt [--check] [file.lox]...";
+
+fn format_file(path: &str, source: &str) -> Result<String, String> {
+    format(source).map_err(|errors| {
+        errors
+            .iter()
+            .map(|error| std::format!("{}: {}", path, error))
+            .collect::<Vec<_>>()
+            .join("\n")
+    })
+}
+
+/// `rlox fmt`: formats files in place, or stdin to stdout when there are none. With `--check`
+/// nothing is written, the files that would change are listed and the command fails, for CI.
+pub fn fmt(args: &[String]) -> Result<(), String> {
+    let mut check = false;
+    let mut paths = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ if arg.starts_with('-') => return Err(USAGE.into()),
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    if paths.is_empty() {
+        let mut source = String::new();
+        io::stdin()
+            .read_to_string(&mut source)
+            .map_err(|error| error.to_string())?;
+        let formatted = format_file("<stdin>", &source)?;
+        if !check {
+            print!("{}", formatted);
+        } else if formatted != source {
+            return Err("<stdin> is not formatted".into());
+        }
+        return Ok(());
+    }
+    let mut unformatted = 0;
+    for path in paths {
+        let source =
+            fs::read_to_string(path).map_err(|error| std::format!("{}: {}", path, error))?;
+        let formatted = format_file(path, &source)?;
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("Would reformat {}", path);
+            unformatted += 1;
+        } else {
+            fs::write(path, formatted).map_err(|error| std::format!("{}: {}", path, error))?;
+        }
+    }
+    match unformatted {
+        0 => Ok(()),
+        1 => Err("1 file is not formatted".into()),
+        n => Err(std::format!("{} files are not formatted", n)),
+    }
+}
+
+#[cfg(test)]
+mod test_fmt {
+    use super::*;
+    use crate::test_util::script;
+
+    #[test]
+    fn check_leaves_files_alone() {
+        let path = script("check", "print  1+2;").to_string_lossy().to_string();
+        assert!(fmt(&["--check".into(), path.clone()]).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "print  1+2;");
+        assert_eq!(fmt(std::slice::from_ref(&path)), Ok(()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "print 1 + 2;\n");
+        assert_eq!(fmt(&["--check".into(), path]), Ok(()));
+    }
+
+    #[test]
+    fn reports_parse_errors_with_the_file() {
+        let path = script("broken", "print (1;").to_string_lossy().to_string();
+        let error = fmt(std::slice::from_ref(&path)).unwrap_err();
+        assert!(error.starts_with(&std::format!("{}: Error at line 1", path)));
+    }
+}
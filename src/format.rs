@@ -0,0 +1,407 @@
+use crate::{
+    cst::{self, Element, Node, NodeKind, SyntaxToken, SyntaxTree, Trivia},
+    diagnostic::Diagnostic,
+};
+
+/// Lines are wrapped to fit in this many columns where possible.
+pub const MAX_WIDTH: usize = 80;
+const INDENT: usize = 4;
+
+/// The layout of a file before it's decided where lines break, as in Wadler's "A prettier printer".
+#[derive(Debug, Clone)]
+enum Doc {
+    Text(String),
+    /// A space, or a line break if the group around it doesn't fit on the line.
+    Line,
+    /// Nothing, or a line break.
+    SoftLine,
+    /// Always a line break, which also keeps the groups around it from being flat.
+    HardLine,
+    /// Indents the lines broken inside it.
+    Nest(Vec<Doc>),
+    /// All on one line if that fits, otherwise with every `Line` directly inside broken.
+    Group(Vec<Doc>),
+}
+
+fn text(text: &str) -> Doc {
+    Doc::Text(text.into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+struct Printer {
+    out: String,
+    column: usize,
+    /// The indentation of the line just started, written out along with its first text.
+    line_start: Option<usize>,
+}
+
+impl Printer {
+    fn text(&mut self, text: &str) {
+        if let Some(indent) = self.line_start {
+            // Spaces at the start of a line are the indentation's job
+            if text.trim().is_empty() {
+                return;
+            }
+            self.out.push_str(&" ".repeat(indent));
+            self.line_start = None;
+        }
+        self.out.push_str(text);
+        self.column += text.chars().count();
+    }
+
+    fn newline(&mut self, indent: usize) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        self.out.push('\n');
+        self.column = indent;
+        self.line_start = Some(indent);
+    }
+
+    // A line break that's optional doesn't leave an empty line behind, it only changes the
+    // indentation of the line just started.
+    fn soft_newline(&mut self, indent: usize) {
+        if self.line_start.is_some() {
+            self.column = indent;
+            self.line_start = Some(indent);
+        } else {
+            self.newline(indent);
+        }
+    }
+}
+
+/// Whether `docs` fit in `width` columns when laid out flat, along with whatever follows them on
+/// the same line.
+fn fits(width: usize, docs: &[Doc], rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut width = width as isize;
+    let mut todo: Vec<(Mode, &Doc)> = docs.iter().rev().map(|doc| (Mode::Flat, doc)).collect();
+    let mut rest = rest.iter().rev();
+    loop {
+        let (mode, doc) = match todo.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some((_, mode, doc)) => (*mode, *doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(text) => width -= text.chars().count() as isize,
+            Doc::Line if mode == Mode::Flat => width -= 1,
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::HardLine if mode == Mode::Flat => return false,
+            Doc::Line | Doc::SoftLine | Doc::HardLine => return true,
+            Doc::Nest(docs) => todo.extend(docs.iter().rev().map(|doc| (mode, doc))),
+            Doc::Group(docs) => todo.extend(docs.iter().rev().map(|doc| (Mode::Flat, doc))),
+        }
+        if width < 0 {
+            return false;
+        }
+    }
+}
+
+fn render(docs: &[Doc]) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        column: 0,
+        line_start: Some(0),
+    };
+    let mut stack: Vec<(usize, Mode, &Doc)> =
+        docs.iter().rev().map(|doc| (0, Mode::Break, doc)).collect();
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(text) => printer.text(text),
+            Doc::Line if mode == Mode::Flat => printer.text(" "),
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine => printer.soft_newline(indent),
+            Doc::HardLine => printer.newline(indent),
+            Doc::Nest(docs) => {
+                stack.extend(docs.iter().rev().map(|doc| (indent + INDENT, mode, doc)));
+            }
+            Doc::Group(docs) => {
+                let width = MAX_WIDTH.saturating_sub(printer.column);
+                let mode = if mode == Mode::Flat || fits(width, docs, &stack) {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc)));
+            }
+        }
+    }
+    printer.out
+}
+
+fn comments(trivia: &[Trivia]) -> impl Iterator<Item = &str> {
+    trivia.iter().filter_map(|piece| match piece {
+        Trivia::Comment(comment) => Some(comment.trim_end()),
+        _ => None,
+    })
+}
+
+/// A token inside a statement, with the comments around it. Comments before the statement and
+/// after its end are laid out by `program` instead.
+fn token(token: &SyntaxToken) -> Vec<Doc> {
+    let mut docs = vec![];
+    for comment in comments(&token.leading) {
+        docs.push(Doc::HardLine);
+        docs.push(text(comment));
+    }
+    if !docs.is_empty() {
+        docs.push(Doc::HardLine);
+    }
+    docs.push(text(&token.text));
+    if let Some(comment) = comments(&token.trailing).next() {
+        docs.push(Doc::Text(format!(" {}", comment)));
+        docs.push(Doc::HardLine);
+    }
+    docs
+}
+
+fn element(element: &Element) -> Vec<Doc> {
+    match element {
+        Element::Token(t) => token(t),
+        Element::Node(node) => self::node(node),
+    }
+}
+
+fn node(node: &Node) -> Vec<Doc> {
+    let children = &node.children;
+    match node.kind {
+        NodeKind::VarDecl => {
+            let mut docs = [
+                element(&children[0]),
+                vec![text(" ")],
+                element(&children[1]),
+            ]
+            .concat();
+            let mut rest = &children[2..];
+            if let [Element::Node(annotation), after @ ..] = rest {
+                docs.append(&mut self::node(annotation));
+                rest = after;
+            }
+            if let [equal, value, _] = rest {
+                docs.push(text(" "));
+                docs.append(&mut element(equal));
+                docs.push(text(" "));
+                docs.append(&mut element(value));
+            }
+            docs.append(&mut element(children.last().unwrap()));
+            docs
+        }
+        NodeKind::TypeAnnotation => [
+            element(&children[0]),
+            vec![text(" ")],
+            element(&children[1]),
+        ]
+        .concat(),
+        NodeKind::ImportDecl => {
+            let (semicolon, words) = children.split_last().unwrap();
+            let mut docs = element(&words[0]);
+            for word in &words[1..] {
+                docs.push(text(" "));
+                docs.append(&mut element(word));
+            }
+            docs.append(&mut element(semicolon));
+            docs
+        }
+        NodeKind::PrintStmt => [
+            element(&children[0]),
+            vec![text(" ")],
+            element(&children[1]),
+            element(&children[2]),
+        ]
+        .concat(),
+        NodeKind::Assign => [
+            element(&children[0]),
+            vec![text(" ")],
+            element(&children[1]),
+            vec![text(" ")],
+            element(&children[2]),
+        ]
+        .concat(),
+        // Long expressions break before their operators
+        NodeKind::Binary => {
+            let right = [
+                vec![Doc::Line],
+                element(&children[1]),
+                vec![text(" ")],
+                element(&children[2]),
+            ]
+            .concat();
+            vec![Doc::Group(
+                [element(&children[0]), vec![Doc::Nest(right)]].concat(),
+            )]
+        }
+        // And calls, lists and maps put each of their items on their own line
+        NodeKind::Call => [element(&children[0]), items(&children[1..])].concat(),
+        NodeKind::List | NodeKind::Map => items(children),
+        NodeKind::MapEntry => [
+            element(&children[0]),
+            element(&children[1]),
+            vec![text(" ")],
+            element(&children[2]),
+        ]
+        .concat(),
+        _ => children.iter().flat_map(element).collect(),
+    }
+}
+
+// Brackets, braces or parentheses around items separated by commas.
+fn items(children: &[Element]) -> Vec<Doc> {
+    let mut docs = element(&children[0]);
+    let (close, items) = children[1..].split_last().unwrap();
+    if !items.is_empty() {
+        let mut nested = vec![Doc::SoftLine];
+        for item in items {
+            match item {
+                Element::Token(comma) => {
+                    nested.append(&mut token(comma));
+                    nested.push(Doc::Line);
+                }
+                node => nested.append(&mut element(node)),
+            }
+        }
+        docs.push(Doc::Group(vec![Doc::Nest(nested), Doc::SoftLine]));
+    }
+    docs.append(&mut element(close));
+    docs
+}
+
+// The tokens at either end of a statement, whose comments are laid out around the statement.
+fn edge_token(node: &mut Node, last: bool) -> Option<&mut SyntaxToken> {
+    let child = if last {
+        node.children.last_mut()
+    } else {
+        node.children.first_mut()
+    };
+    match child? {
+        Element::Token(token) => Some(token),
+        Element::Node(node) => edge_token(node, last),
+    }
+}
+
+/// Comments on their own lines before a statement, or at the end of the file. A blank line before
+/// any of them is kept, more than one blank line is not.
+fn leading_lines(trivia: &[Trivia], docs: &mut Vec<Doc>) {
+    let mut newlines = 0;
+    for piece in trivia {
+        match piece {
+            Trivia::Newline(_) => newlines += 1,
+            Trivia::Comment(comment) => {
+                if newlines > 1 && !docs.is_empty() {
+                    docs.push(Doc::HardLine);
+                }
+                docs.push(text(comment.trim_end()));
+                docs.push(Doc::HardLine);
+                newlines = 0;
+            }
+            Trivia::Whitespace(_) => {}
+        }
+    }
+    if newlines > 1 && !docs.is_empty() {
+        docs.push(Doc::HardLine);
+    }
+}
+
+fn program(tree: &SyntaxTree) -> Vec<Doc> {
+    let mut docs = vec![];
+    for statement in &tree.program.children {
+        let Element::Node(statement) = statement else {
+            continue;
+        };
+        let mut statement = statement.clone();
+        if let Some(first) = edge_token(&mut statement, false) {
+            leading_lines(&std::mem::take(&mut first.leading), &mut docs);
+        }
+        let trailing = edge_token(&mut statement, true)
+            .map_or(vec![], |last| std::mem::take(&mut last.trailing));
+        docs.append(&mut node(&statement));
+        if let Some(comment) = comments(&trailing).next() {
+            docs.push(Doc::Text(format!(" {}", comment)));
+        }
+        docs.push(Doc::HardLine);
+    }
+    leading_lines(&tree.end, &mut docs);
+    // Blank lines at the very end of the file aren't kept
+    while docs.len() > 1
+        && docs[docs.len() - 2..]
+            .iter()
+            .all(|doc| matches!(doc, Doc::HardLine))
+    {
+        docs.pop();
+    }
+    docs
+}
+
+/// Formats a whole file: one statement per line, single spaces around operators, four spaces of
+/// indentation for wrapped lines, and comments where they were. Formatting formatted code changes
+/// nothing. A file that doesn't parse is left alone, and its errors come back instead.
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let tree = cst::parse(source);
+    if !tree.errors.is_empty() {
+        return Err(tree.errors);
+    }
+    Ok(render(&program(&tree)))
+}
+
+#[cfg(test)]
+mod test_format {
+    use super::*;
+
+    fn formatted(source: &str) -> String {
+        let once = format(source).unwrap();
+        assert_eq!(format(&once).unwrap(), once, "formatting isn't idempotent");
+        once
+    }
+
+    #[test]
+    fn normalizes_spacing() {
+        assert_eq!(
+            formatted("var  a=1;print -a+clock( )*2;a = a ==(1);var b :string;import\"m.lox\"as m ;print m . x;l [0]=[ 1,2 ,[ ]];l={ \"a\" :1 ,2:{ } };"),
+            "var a = 1;\nprint -a + clock() * 2;\na = a == (1);\nvar b: string;\nimport \"m.lox\" as m;\nprint m.x;\nl[0] = [1, 2, []];\nl = {\"a\": 1, 2: {}};\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_and_single_blank_lines() {
+        let source = "\n\n// header\nvar a = 1;   // one\n\n\n\n// two\nprint a +\n  // why\n  1;\n\n// end\n\n";
+        assert_eq!(
+            formatted(source),
+            "// header\nvar a = 1; // one\n\n// two\nprint a\n    +\n    // why\n    1;\n\n// end\n"
+        );
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        let source = "var total = firstValueOfManyThings + secondValueOfManyThings + thirdValueOfManyThings;\nprint describe(firstValueOfManyThings, secondValueOfManyThings, thirdValueOfManyThings);";
+        assert_eq!(
+            formatted(source),
+            "var total = firstValueOfManyThings + secondValueOfManyThings
+    + thirdValueOfManyThings;
+print describe(
+    firstValueOfManyThings,
+    secondValueOfManyThings,
+    thirdValueOfManyThings
+);
+"
+        );
+    }
+
+    #[test]
+    fn trailing_comments_inside_expressions_break_the_line() {
+        assert_eq!(
+            formatted("print max(1, // first\n2);"),
+            "print max(\n    1, // first\n    2\n);\n"
+        );
+    }
+
+    #[test]
+    fn refuses_files_that_do_not_parse() {
+        let errors = format("print (1;\nprint 2;").unwrap_err();
+        assert_eq!(errors[0].message, "Expected )");
+    }
+}
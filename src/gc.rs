@@ -0,0 +1,345 @@
+use std::{collections::HashMap, fmt, mem, rc::Rc};
+
+use crate::common::{Obj, Value, ValueKind};
+
+const INITIAL_GC_THRESHOLD: usize = 1024 * 1024;
+const HEAP_GROW_FACTOR: usize = 2;
+
+/// Handle to an object living on the `Heap`. Handles are only meaningful for the heap that issued
+/// them, and stay valid as long as the object is reachable from the VM roots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(u32);
+
+impl ObjRef {
+    pub(crate) fn index(self) -> u32 {
+        self.0
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    pub(crate) fn from_index(index: u32) -> Self {
+        ObjRef(index)
+    }
+}
+
+impl fmt::Display for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+struct Slot {
+    marked: bool,
+    /// What the object counted for in `bytes_allocated` when it was last measured.
+    size: usize,
+    obj: Obj,
+}
+
+/// Every object the VM allocates lives here. Collection is a tri-color mark-and-sweep: unmarked
+/// objects are white, marked objects waiting in `gray` still have to be traced, and marked objects
+/// that left `gray` are black. Whatever is still white after tracing is swept.
+///
+/// The heap never decides on its own to collect, since it doesn't know the roots. The VM checks
+/// `should_collect` before allocating and passes its roots to `collect`. The compiler allocates
+/// constants straight into the heap, and nothing can collect until the compiled chunk is running,
+/// at which point its constants are rooted through the VM call frames.
+///
+/// Strings are interned: `intern` hands out the same object for equal contents, so strings can be
+/// compared and hashed by handle. The intern table holds its entries weakly, a string that is only
+/// referenced from the table is swept like any other garbage and its entry dropped.
+pub struct Heap {
+    slots: Vec<Option<Slot>>,
+    strings: HashMap<Rc<str>, ObjRef>,
+    free: Vec<u32>,
+    gray: Vec<ObjRef>,
+    bytes_allocated: usize,
+    allocations: usize,
+    next_gc: usize,
+    /// Collect before every allocation, to shake out objects that aren't rooted properly.
+    pub stress: bool,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            slots: vec![],
+            strings: HashMap::new(),
+            free: vec![],
+            gray: vec![],
+            bytes_allocated: 0,
+            allocations: 0,
+            next_gc: INITIAL_GC_THRESHOLD,
+            stress: false,
+        }
+    }
+
+    /// Allocates a new object. Strings have to go through `intern` instead.
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        debug_assert!(
+            !matches!(obj, Obj::String(_)),
+            "Strings must be allocated with Heap::intern"
+        );
+        self.alloc_slot(obj)
+    }
+
+    /// The string object holding `s`, allocating it if no such string exists yet.
+    pub fn intern(&mut self, s: &str) -> ObjRef {
+        if let Some(obj) = self.find_string(s) {
+            return obj;
+        }
+        let s: Rc<str> = s.into();
+        let obj = self.alloc_slot(Obj::String(Rc::clone(&s)));
+        self.strings.insert(s, obj);
+        obj
+    }
+
+    /// The interned string holding `s`, without allocating it if there is none.
+    pub fn find_string(&self, s: &str) -> Option<ObjRef> {
+        self.strings.get(s).copied()
+    }
+
+    fn alloc_slot(&mut self, obj: Obj) -> ObjRef {
+        let size = obj.size();
+        self.bytes_allocated += size;
+        self.allocations += 1;
+        let slot = Some(Slot {
+            marked: false,
+            size,
+            obj,
+        });
+        match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize] = slot;
+                ObjRef(index)
+            }
+            None => {
+                self.slots.push(slot);
+                ObjRef(self.slots.len() as u32 - 1)
+            }
+        }
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Obj {
+        match &self.slots[obj.0 as usize] {
+            Some(slot) => &slot.obj,
+            None => panic!("Use of collected object {}", obj),
+        }
+    }
+
+    pub fn get_mut(&mut self, obj: ObjRef) -> &mut Obj {
+        match &mut self.slots[obj.0 as usize] {
+            Some(slot) => &mut slot.obj,
+            None => panic!("Use of collected object {}", obj),
+        }
+    }
+
+    /// Measures `obj` again after it grew or shrank, like a list does, so collections are paced by
+    /// what it holds now.
+    pub fn resized(&mut self, obj: ObjRef) {
+        if let Some(slot) = &mut self.slots[obj.0 as usize] {
+            let size = slot.obj.size();
+            self.bytes_allocated = self.bytes_allocated - slot.size + size;
+            slot.size = size;
+        }
+    }
+
+    /// The string behind `value`, if it is one.
+    pub fn as_str(&self, value: &Value) -> Option<&str> {
+        self.string(value.as_obj()?)
+    }
+
+    pub fn string(&self, obj: ObjRef) -> Option<&str> {
+        match self.get(obj) {
+            Obj::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn objects(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    /// Number of objects allocated over the heap's lifetime, including ones since freed.
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_gc
+    }
+
+    /// Frees every object that isn't reachable from `roots` and returns how many were freed.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = Value>) -> usize {
+        for root in roots {
+            self.mark_value(&root);
+        }
+        self.trace_references();
+        let freed = self.sweep();
+        self.next_gc = (self.bytes_allocated * HEAP_GROW_FACTOR).max(INITIAL_GC_THRESHOLD);
+        freed
+    }
+
+    fn mark_value(&mut self, value: &Value) {
+        if let ValueKind::Obj(obj) = value.kind() {
+            self.mark_object(obj);
+        }
+    }
+
+    fn mark_object(&mut self, obj: ObjRef) {
+        if let Some(slot) = &mut self.slots[obj.0 as usize] {
+            if !slot.marked {
+                slot.marked = true;
+                self.gray.push(obj);
+            }
+        }
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(obj) = self.gray.pop() {
+            let mut children = vec![];
+            self.get(obj).trace(&mut children);
+            for child in children {
+                self.mark_object(child);
+            }
+        }
+    }
+
+    fn sweep(&mut self) -> usize {
+        let mut freed = 0;
+        for (index, entry) in self.slots.iter_mut().enumerate() {
+            match entry {
+                Some(slot) if slot.marked => slot.marked = false,
+                Some(slot) => {
+                    if let Obj::String(s) = &slot.obj {
+                        self.strings.remove(s);
+                    }
+                    self.bytes_allocated -= slot.size;
+                    *entry = None;
+                    self.free.push(index as u32);
+                    freed += 1;
+                }
+                None => {}
+            }
+        }
+        freed
+    }
+}
+
+impl Obj {
+    /// Approximate number of bytes this object holds on to, used to pace collections.
+    fn size(&self) -> usize {
+        mem::size_of::<Obj>()
+            + match self {
+                Obj::String(s) => s.len(),
+                Obj::NativeFn(_) | Obj::BoundMethod(_) => 0,
+                Obj::List(items) => items.capacity() * mem::size_of::<Value>(),
+                Obj::Map(table) => table.allocated_bytes(),
+            }
+    }
+
+    /// Pushes every object directly referenced by this one.
+    fn trace(&self, children: &mut Vec<ObjRef>) {
+        match self {
+            Obj::String(_) | Obj::NativeFn(_) => {}
+            Obj::List(items) => children.extend(items.iter().filter_map(Value::as_obj)),
+            Obj::Map(table) => children.extend(
+                table
+                    .iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .filter_map(Value::as_obj),
+            ),
+            Obj::BoundMethod(bound) => children.extend(bound.receiver.as_obj()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_heap {
+    use super::*;
+
+    #[test]
+    fn collects_unreachable_objects() {
+        let mut heap = Heap::new();
+        let kept = heap.intern("kept");
+        heap.intern("garbage");
+        assert_eq!(heap.collect([Value::obj(kept)]), 1);
+        assert_eq!(heap.objects(), 1);
+        assert_eq!(heap.string(kept), Some("kept"));
+    }
+
+    #[test]
+    fn reuses_freed_slots() {
+        let mut heap = Heap::new();
+        let garbage = heap.intern("garbage");
+        heap.collect([]);
+        let reused = heap.intern("new");
+        assert_eq!(garbage, reused);
+        assert_eq!(heap.objects(), 1);
+    }
+
+    #[test]
+    fn survivors_are_unmarked_for_the_next_cycle() {
+        let mut heap = Heap::new();
+        let obj = heap.intern("a");
+        heap.collect([Value::obj(obj)]);
+        assert_eq!(heap.collect([]), 1);
+    }
+
+    #[test]
+    fn lists_keep_their_items_alive() {
+        let mut heap = Heap::new();
+        let item = heap.intern("item");
+        heap.intern("garbage");
+        let list = heap.alloc(Obj::List(vec![Value::obj(item), Value::number(1.0)]));
+        assert_eq!(heap.collect([Value::obj(list)]), 1);
+        assert_eq!(heap.string(item), Some("item"));
+    }
+
+    #[test]
+    fn tracks_allocated_bytes() {
+        let mut heap = Heap::new();
+        heap.intern("garbage");
+        assert!(heap.bytes_allocated() > 0);
+        let list = heap.alloc(Obj::List(vec![]));
+        let empty = heap.bytes_allocated();
+        if let Obj::List(items) = heap.get_mut(list) {
+            items.extend([Value::NIL; 8]);
+        }
+        heap.resized(list);
+        assert!(heap.bytes_allocated() > empty);
+        heap.collect([]);
+        assert_eq!(heap.bytes_allocated(), 0);
+    }
+
+    #[test]
+    fn equal_strings_are_interned_once() {
+        let mut heap = Heap::new();
+        let a = heap.intern("lox");
+        let b = heap.intern(&format!("{}{}", "lo", "x"));
+        assert_eq!(a, b);
+        assert_ne!(a, heap.intern("clox"));
+        assert_eq!(heap.objects(), 2);
+    }
+
+    #[test]
+    fn interned_strings_are_weak() {
+        let mut heap = Heap::new();
+        let kept = heap.intern("kept");
+        heap.intern("garbage");
+        heap.collect([Value::obj(kept)]);
+        assert_eq!(heap.find_string("kept"), Some(kept));
+        assert_eq!(heap.find_string("garbage"), None);
+        // Interning it again allocates a fresh string rather than handing out a stale handle
+        let garbage = heap.intern("garbage");
+        assert_eq!(heap.string(garbage), Some("garbage"));
+    }
+}
@@ -0,0 +1,397 @@
+use std::{fmt, iter::Peekable, str::Chars};
+
+/// Just enough JSON to report results and talk to editors, without pulling in a dependency.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Keys keep their insertion order, so output is stable.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    /// Parses a single JSON document. Numbers are all `f64`, like in JavaScript.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+        let json = parser.value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(json),
+            Some(ch) => Err(format!("Unexpected {:?} after the end of the document", ch)),
+        }
+    }
+
+    /// The field `key` of an object, if this is one and it has that field.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|ch| ch.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some(ch) if ch == expected => Ok(()),
+            Some(ch) => Err(format!("Expected {:?}, got {:?}", expected, ch)),
+            None => Err(format!(
+                "Expected {:?}, got the end of the document",
+                expected
+            )),
+        }
+    }
+
+    fn literal(&mut self, word: &str, json: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(json)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(ch) if *ch == '-' || ch.is_ascii_digit() => self.number(),
+            Some(ch) => Err(format!("Unexpected {:?}", ch)),
+            None => Err("Unexpected end of the document".into()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let mut number = String::new();
+        while let Some(ch) = self
+            .chars
+            .next_if(|ch| ch.is_ascii_digit() || matches!(ch, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            number.push(ch);
+        }
+        number
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| format!("Invalid number {}", number))
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, String> {
+        let digits: String = (0..4).filter_map(|_| self.chars.next()).collect();
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("Invalid escape \\u{}", digits))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let ch = match self.chars.next() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let mut code = self.hex_escape()?;
+                            // Characters outside the BMP come as a surrogate pair, a high one
+                            // followed by a low one. A low one on its own isn't a character.
+                            if (0xd800..0xdc00).contains(&code) {
+                                let high = code;
+                                let unpaired = || format!("Unpaired surrogate \\u{:04x}", high);
+                                if self.chars.next() != Some('\\') || self.chars.next() != Some('u')
+                                {
+                                    return Err(unpaired());
+                                }
+                                let low = self.hex_escape()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(unpaired());
+                                }
+                                code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or(format!("Invalid character {:#x}", code))?
+                        }
+                        Some(ch @ ('"' | '\\' | '/')) => ch,
+                        Some(ch) => return Err(format!("Invalid escape \\{}", ch)),
+                        None => return Err("Unterminated string".into()),
+                    };
+                    s.push(ch);
+                }
+                Some(ch) => s.push(ch),
+                None => return Err("Unterminated string".into()),
+            }
+        }
+    }
+
+    // Calls `item` for every element up to `close`, with the commas in between.
+    fn elements(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.chars.next();
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&close).is_some() {
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(ch) if ch == close => return Ok(()),
+                Some(ch) => return Err(format!("Expected ',' or {:?}, got {:?}", close, ch)),
+                None => return Err(format!("Expected {:?}, got the end of the document", close)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        let mut items = vec![];
+        self.elements(']', |parser| {
+            items.push(parser.value()?);
+            Ok(())
+        })?;
+        Ok(Json::Array(items))
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        let mut fields = vec![];
+        self.elements('}', |parser| {
+            parser.skip_whitespace();
+            let key = parser.string()?;
+            parser.skip_whitespace();
+            parser.expect(':')?;
+            fields.push((key, parser.value()?));
+            Ok(())
+        })?;
+        Ok(Json::Object(fields))
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Self {
+        Json::Number(n)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.into())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(items: Vec<T>) -> Self {
+        Json::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Compact serialization, on a single line.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // JSON has no NaN or infinities
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_json {
+    use super::*;
+
+    #[test]
+    fn serializes_nested_values() {
+        let json = Json::object([
+            ("name", Json::from("fib")),
+            ("ok", Json::from(true)),
+            ("time", Json::from(1.5)),
+            ("runs", Json::from(vec![1usize, 2])),
+            ("clox", Json::Null),
+        ]);
+        assert_eq!(
+            json.to_string(),
+            r#"{"name":"fib","ok":true,"time":1.5,"runs":[1,2],"clox":null}"#
+        );
+    }
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(
+            Json::from("a \"quote\"\n\\ \u{1}").to_string(),
+            r#""a \"quote\"\n\\ \u0001""#
+        );
+    }
+
+    #[test]
+    fn numbers_without_a_json_form_are_null() {
+        assert_eq!(Json::from(f64::NAN).to_string(), "null");
+        assert_eq!(Json::from(42usize).to_string(), "42");
+    }
+
+    #[test]
+    fn parses_what_it_serializes() {
+        let text = r#"{"seq":1,"type":"request","arguments":{"lines":[3,-4.5e1],"ok":false,"x":null},"s":"a\"\n\u00e9\ud83d\ude00"}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.get("seq").and_then(Json::as_f64), Some(1.0));
+        let arguments = json.get("arguments").unwrap();
+        assert_eq!(
+            arguments.get("lines").and_then(Json::as_array),
+            Some(&[Json::from(3.0), Json::from(-45.0)][..])
+        );
+        assert_eq!(arguments.get("ok").and_then(Json::as_bool), Some(false));
+        assert_eq!(json.get("s").and_then(Json::as_str), Some("a\"\né😀"));
+        assert_eq!(Json::parse(&json.to_string()), Ok(json));
+        assert_eq!(Json::parse(" [ ] "), Ok(Json::Array(vec![])));
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("\"open").is_err());
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse("").is_err());
+    }
+
+    #[test]
+    fn rejects_unpaired_surrogates() {
+        let unpaired = Err("Unpaired surrogate \\ud800".to_string());
+        assert_eq!(Json::parse(r#""\ud800\u0041""#), unpaired);
+        assert_eq!(Json::parse(r#""\ud800x""#), unpaired);
+        assert_eq!(Json::parse(r#""\ud800""#), unpaired);
+        assert!(Json::parse(r#""\udc00""#).is_err());
+    }
+}
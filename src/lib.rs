@@ -0,0 +1,25 @@
+pub mod ast;
+pub mod check;
+pub mod codegen;
+pub mod common;
+pub mod compile;
+pub mod convert;
+pub mod cst;
+pub mod debug;
+pub mod diagnostic;
+pub mod format;
+pub mod gc;
+pub mod json;
+pub mod lint;
+pub mod list;
+pub mod map;
+pub mod parse;
+pub mod profile;
+pub mod resolve;
+pub mod table;
+#[cfg(test)]
+mod test_util;
+pub mod tokens;
+pub mod trace;
+mod value;
+pub mod vm;
@@ -0,0 +1,511 @@
+use std::{
+    fs,
+    io::{self, IsTerminal, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+const MAX_HISTORY: usize = 1000;
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Ctrl(char),
+    Escape,
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => match read_byte(input)? {
+            Some(b'[') | Some(b'O') => match read_byte(input)? {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'C') => Key::Right,
+                Some(b'D') => Key::Left,
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                Some(code @ b'0'..=b'9') => {
+                    // Sequences like `ESC [ 3 ~`, skip until the terminating `~`
+                    let mut last = Some(code);
+                    while last.is_some_and(|b| b != b'~') {
+                        last = read_byte(input)?;
+                    }
+                    match code {
+                        b'1' | b'7' => Key::Home,
+                        b'4' | b'8' => Key::End,
+                        b'3' => Key::Delete,
+                        _ => Key::Escape,
+                    }
+                }
+                _ => Key::Escape,
+            },
+            _ => Key::Escape,
+        },
+        0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+        _ => {
+            // Collect the rest of a multi-byte UTF-8 character
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match read_byte(input)? {
+                    Some(b) => bytes.push(b),
+                    None => break,
+                }
+            }
+            match String::from_utf8_lossy(&bytes).chars().next() {
+                Some(ch) => Key::Char(ch),
+                None => Key::Escape,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+/// The line being edited and the cursor position in it, counted in chars.
+#[derive(Debug, Default, PartialEq)]
+struct Buffer {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Buffer {
+    fn set(&mut self, line: &str) {
+        self.chars = line.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn insert(&mut self, ch: char) {
+        self.chars.insert(self.cursor, ch);
+        self.cursor += 1;
+    }
+
+    fn insert_str(&mut self, s: &str) {
+        for ch in s.chars() {
+            self.insert(ch);
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+
+    fn kill_to_end(&mut self) {
+        self.chars.truncate(self.cursor);
+    }
+
+    fn kill_to_start(&mut self) {
+        self.chars.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    fn kill_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// The identifier-like word right before the cursor, which is what tab completes.
+    fn word_before_cursor(&self) -> String {
+        let start = self.chars[..self.cursor]
+            .iter()
+            .rposition(|ch| !(ch.is_alphanumeric() || *ch == '_'))
+            .map_or(0, |i| i + 1);
+        self.chars[start..self.cursor].iter().collect()
+    }
+}
+
+/// Completes `word` against `candidates`. Returns the text to insert and, when it's ambiguous,
+/// every matching candidate so they can be listed.
+fn complete(word: &str, candidates: &[String]) -> (String, Vec<String>) {
+    let mut matches: Vec<String> = candidates
+        .iter()
+        .filter(|c| c.starts_with(word))
+        .cloned()
+        .collect();
+    matches.sort();
+    matches.dedup();
+    let Some(first) = matches.first() else {
+        return (String::new(), vec![]);
+    };
+    let mut common = first.clone();
+    for candidate in &matches[1..] {
+        while !candidate.starts_with(&common) {
+            common.pop();
+        }
+    }
+    let insertion = common[word.len()..].to_string();
+    if matches.len() == 1 {
+        (insertion, vec![])
+    } else {
+        (insertion, matches)
+    }
+}
+
+/// Puts the terminal into raw mode with `stty` and restores the previous settings when dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Ok(RawMode {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(io::Error::other("stty failed"))
+    }
+}
+
+pub struct LineEditor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    /// An editor with history persisted in `~/.rlox_history`.
+    pub fn new() -> Self {
+        let history_path =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rlox_history"));
+        let history = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(String::from).collect())
+            .unwrap_or_default();
+        LineEditor {
+            history,
+            history_path,
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.drain(..self.history.len() - MAX_HISTORY);
+        }
+        if let Some(path) = &self.history_path {
+            let _ = fs::write(path, self.history.join("\n") + "\n");
+        }
+    }
+
+    /// Reads one line, without the trailing newline. Returns `None` at end of input and an
+    /// `Interrupted` error on Ctrl-C. Falls back to plain `read_line` when stdin is not a terminal.
+    pub fn read_line(&mut self, prompt: &str, candidates: &[String]) -> io::Result<Option<String>> {
+        print!("{}", prompt);
+        io::stdout().flush()?;
+        if !io::stdin().is_terminal() {
+            return read_plain_line();
+        }
+        let line = match RawMode::enable() {
+            Ok(_raw) => self.edit(prompt, candidates)?,
+            Err(_) => return read_plain_line(),
+        };
+        print!("\r\n");
+        io::stdout().flush()?;
+        if let Some(line) = &line {
+            self.add_history(line);
+        }
+        Ok(line)
+    }
+
+    fn edit(&mut self, prompt: &str, candidates: &[String]) -> io::Result<Option<String>> {
+        let mut stdin = io::stdin().lock();
+        let mut buffer = Buffer::default();
+        // Index into history while browsing it with up/down, and the line that was being typed
+        let mut history_index = self.history.len();
+        let mut pending = String::new();
+        loop {
+            let Some(key) = read_key(&mut stdin)? else {
+                return Ok(None);
+            };
+            match key {
+                Key::Enter => return Ok(Some(buffer.text())),
+                Key::Ctrl('d') if buffer.chars.is_empty() => return Ok(None),
+                Key::Ctrl('c') => {
+                    print!("^C\r\n");
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Char(ch) => buffer.insert(ch),
+                Key::Backspace | Key::Ctrl('h') => buffer.backspace(),
+                Key::Delete | Key::Ctrl('d') => buffer.delete(),
+                Key::Left | Key::Ctrl('b') => buffer.left(),
+                Key::Right | Key::Ctrl('f') => buffer.right(),
+                Key::Home | Key::Ctrl('a') => buffer.cursor = 0,
+                Key::End | Key::Ctrl('e') => buffer.cursor = buffer.chars.len(),
+                Key::Ctrl('k') => buffer.kill_to_end(),
+                Key::Ctrl('u') => buffer.kill_to_start(),
+                Key::Ctrl('w') => buffer.kill_word(),
+                Key::Ctrl('l') => print!("\x1b[2J\x1b[H"),
+                Key::Up | Key::Ctrl('p') if history_index > 0 => {
+                    if history_index == self.history.len() {
+                        pending = buffer.text();
+                    }
+                    history_index -= 1;
+                    buffer.set(&self.history[history_index]);
+                }
+                Key::Down | Key::Ctrl('n') if history_index < self.history.len() => {
+                    history_index += 1;
+                    match self.history.get(history_index) {
+                        Some(line) => buffer.set(line),
+                        None => buffer.set(&pending),
+                    }
+                }
+                Key::Tab => {
+                    let (insertion, matches) = complete(&buffer.word_before_cursor(), candidates);
+                    buffer.insert_str(&insertion);
+                    if !matches.is_empty() && insertion.is_empty() {
+                        print!("\r\n{}\r\n", matches.join("  "));
+                    }
+                }
+                Key::Ctrl('r') => {
+                    let (found, key) = self.reverse_search(&mut stdin, &buffer.text())?;
+                    if let Some(line) = found {
+                        buffer.set(&line);
+                    }
+                    if key == Some(Key::Enter) {
+                        return Ok(Some(buffer.text()));
+                    }
+                }
+                _ => {}
+            }
+            redraw(prompt, &buffer)?;
+        }
+    }
+
+    /// Ctrl-R incremental search through history. Returns the match the user settled on, or `None`
+    /// when the search was cancelled, along with the key that ended the search.
+    fn reverse_search(
+        &self,
+        input: &mut impl Read,
+        initial: &str,
+    ) -> io::Result<(Option<String>, Option<Key>)> {
+        let mut query = String::new();
+        // Matches are searched from the newest entry older than `from`
+        let mut from = self.history.len();
+        let mut found: Option<usize> = None;
+        loop {
+            let matched = found.map_or(initial, |i| self.history[i].as_str());
+            print!("\r(reverse-i-search)`{}': {}\x1b[K", query, matched);
+            io::stdout().flush()?;
+            let Some(key) = read_key(input)? else {
+                return Ok((None, None));
+            };
+            match key {
+                Key::Char(ch) => {
+                    query.push(ch);
+                    from = self.history.len();
+                }
+                Key::Backspace => {
+                    query.pop();
+                    from = self.history.len();
+                }
+                Key::Ctrl('r') => from = found.unwrap_or(from),
+                Key::Ctrl('g') | Key::Ctrl('c') => return Ok((None, Some(key))),
+                _ => return Ok((found.map(|i| self.history[i].clone()), Some(key))),
+            }
+            found = search_history(&self.history, &query, from).or(found);
+        }
+    }
+}
+
+fn search_history(history: &[String], query: &str, before: usize) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    history[..before]
+        .iter()
+        .rposition(|line| line.contains(query))
+}
+
+fn redraw(prompt: &str, buffer: &Buffer) -> io::Result<()> {
+    let column = prompt.chars().count() + buffer.cursor;
+    print!("\r{}{}\x1b[K\r", prompt, buffer.text());
+    if column > 0 {
+        print!("\x1b[{}C", column);
+    }
+    io::stdout().flush()
+}
+
+fn read_plain_line() -> io::Result<Option<String>> {
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line.trim_end_matches(['\n', '\r']).to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test_line_editor {
+    use super::*;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut input = bytes;
+        let mut keys = vec![];
+        while let Some(key) = read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn decodes_escape_sequences() {
+        assert_eq!(
+            keys(b"a\x1b[A\x1b[D\x1b[3~\x12\r"),
+            vec![
+                Key::Char('a'),
+                Key::Up,
+                Key::Left,
+                Key::Delete,
+                Key::Ctrl('r'),
+                Key::Enter
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_utf8() {
+        assert_eq!(keys("λ".as_bytes()), vec![Key::Char('λ')]);
+    }
+
+    #[test]
+    fn edits_in_the_middle_of_the_line() {
+        let mut buffer = Buffer::default();
+        buffer.insert_str("prnt");
+        buffer.left();
+        buffer.left();
+        buffer.insert('i');
+        assert_eq!(buffer.text(), "print");
+        buffer.backspace();
+        buffer.delete();
+        assert_eq!(buffer.text(), "prt");
+        assert_eq!(buffer.cursor, 2);
+    }
+
+    #[test]
+    fn kills_words_and_line_ends() {
+        let mut buffer = Buffer::default();
+        buffer.set("print a + b");
+        buffer.kill_word();
+        assert_eq!(buffer.text(), "print a + ");
+        buffer.cursor = 5;
+        buffer.kill_to_end();
+        assert_eq!(buffer.text(), "print");
+        buffer.kill_to_start();
+        assert_eq!(buffer, Buffer::default());
+    }
+
+    #[test]
+    fn completes_unique_prefix() {
+        let candidates = vec!["print".to_string(), "clock".to_string()];
+        assert_eq!(complete("pr", &candidates), ("int".to_string(), vec![]));
+    }
+
+    #[test]
+    fn completes_common_prefix_of_ambiguous_matches() {
+        let candidates = vec!["false".to_string(), "fun".to_string(), "for".to_string()];
+        assert_eq!(
+            complete("f", &candidates),
+            (
+                String::new(),
+                vec!["false".to_string(), "for".to_string(), "fun".to_string()]
+            )
+        );
+        let candidates = vec!["counter".to_string(), "count".to_string()];
+        assert_eq!(complete("co", &candidates).0, "unt");
+    }
+
+    #[test]
+    fn word_before_cursor_stops_at_punctuation() {
+        let mut buffer = Buffer::default();
+        buffer.set("print(cl");
+        assert_eq!(buffer.word_before_cursor(), "cl");
+    }
+
+    #[test]
+    fn searches_history_backwards() {
+        let history = vec![
+            "var a = 1;".to_string(),
+            "print a;".to_string(),
+            "var b = 2;".to_string(),
+        ];
+        assert_eq!(search_history(&history, "var", 3), Some(2));
+        assert_eq!(search_history(&history, "var", 2), Some(0));
+        assert_eq!(search_history(&history, "nope", 3), None);
+    }
+}
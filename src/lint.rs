@@ -0,0 +1,324 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    ast::{BinaryOp, Decl, DeclKind, Expr, ExprKind, Identifier, Literal, Stmt},
+    compile::Source,
+    cst::{self, Trivia},
+    diagnostic::Diagnostic,
+    tokens::Span,
+};
+
+/// Every rule, with what it catches. All of them are on unless `rlox.toml` turns them off.
+///
+/// Unreachable code after `return`, empty blocks, assignments in conditions, concatenation in
+/// loops and long functions need statements the language doesn't have yet, so they aren't rules
+/// yet either.
+pub const RULES: &[(&str, &str)] = &[
+    (
+        "nil-comparison",
+        "comparing to nil with == or != where truthiness would do",
+    ),
+    (
+        "shadowed-variable",
+        "declaring a name that hides a native function or an imported module",
+    ),
+];
+
+/// A finding of one of the `RULES`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub message: String,
+    pub line: i32,
+    pub column: i32,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Warning at line {}, {} [{}]",
+            self.line, self.message, self.rule
+        )
+    }
+}
+
+/// Which rules are on, as read from the `[lint]` table of an `rlox.toml`:
+///
+/// ```toml
+/// [lint]
+/// nil-comparison = false
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    disabled: HashSet<&'static str>,
+}
+
+impl Config {
+    /// Reads the handful of TOML this needs: tables, comments and `rule = true|false`. Other
+    /// tables are skipped, so they're free for settings that aren't about linting.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut in_lint = false;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(table) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_lint = table.trim() == "lint";
+                continue;
+            }
+            if !in_lint {
+                continue;
+            }
+            let error = |message: String| format!("rlox.toml line {}: {}", number + 1, message);
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error(format!("expected rule = true|false, got {}", line)));
+            };
+            let key = key.trim().trim_matches('"');
+            let Some(&(rule, _)) = RULES.iter().find(|(name, _)| *name == key) else {
+                return Err(error(format!("unknown rule '{}'", key)));
+            };
+            match value.trim() {
+                "true" => config.disabled.remove(rule),
+                "false" => config.disabled.insert(rule),
+                value => return Err(error(format!("expected true or false, got {}", value))),
+            };
+        }
+        Ok(config)
+    }
+
+    pub fn enabled(&self, rule: &str) -> bool {
+        !self.disabled.contains(rule)
+    }
+}
+
+/// The rules turned off by `// lint:allow(rule, ...)` comments, per line. A comment at the end of
+/// a line covers that line, a comment on its own line covers the line below it.
+fn allowed(source: &str) -> HashMap<i32, Vec<String>> {
+    let mut allowed: HashMap<i32, Vec<String>> = HashMap::new();
+    for token in cst::parse(source).program.tokens() {
+        for trivia in token.leading.iter().chain(&token.trailing) {
+            let Trivia::Comment(comment) = trivia else {
+                continue;
+            };
+            let rules = comment
+                .trim_start_matches('/')
+                .trim()
+                .strip_prefix("lint:allow(")
+                .and_then(|rest| rest.split_once(')'));
+            if let Some((rules, _)) = rules {
+                let rules = rules.split(',').map(|rule| rule.trim().to_string());
+                allowed.entry(token.line).or_default().extend(rules);
+            }
+        }
+    }
+    allowed
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    natives: HashSet<&'a str>,
+    /// The names modules are imported as, with the line of the import.
+    aliases: HashMap<String, i32>,
+    lints: Vec<Lint>,
+}
+
+impl<'a> Linter<'a> {
+    fn text(&self, span: Span) -> &'a str {
+        &self.source[span.start..span.end]
+    }
+
+    fn report(&mut self, rule: &'static str, span: Span, message: String) {
+        let (line, column) = span.position(self.source);
+        self.lints.push(Lint {
+            rule,
+            message,
+            line,
+            column,
+        });
+    }
+
+    fn declaration(&mut self, decl: &Decl) {
+        match &decl.kind {
+            DeclKind::Var {
+                name, initializer, ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.shadowing(name);
+            }
+            DeclKind::Import {
+                alias: Some(alias), ..
+            } => {
+                self.shadowing(alias);
+                self.aliases.insert(alias.name.clone(), alias.line);
+            }
+            DeclKind::Import { alias: None, .. } => {}
+            DeclKind::Stmt(Stmt::Print(expr) | Stmt::Expression(expr)) => self.expression(expr),
+        }
+    }
+
+    // The members of plain imports would count too, but finding them means loading the modules
+    fn shadowing(&mut self, name: &Identifier) {
+        let message = if self.natives.contains(name.name.as_str()) {
+            format!("'{}' hides the native function of the same name", name.name)
+        } else if let Some(line) = self.aliases.get(&name.name) {
+            format!("'{}' hides the module imported on line {}", name.name, line)
+        } else {
+            return;
+        };
+        self.report("shadowed-variable", name.span, message);
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(_) | ExprKind::Variable(_) => {}
+            ExprKind::Assign { value, .. } => self.expression(value),
+            ExprKind::Unary { operand, .. } => self.expression(operand),
+            ExprKind::Binary { op, left, right } => {
+                self.nil_comparison(expr, *op, left, right);
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Grouping(inner) => self.expression(inner),
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            ExprKind::Get { object, .. } => self.expression(object),
+        }
+    }
+
+    fn nil_comparison(&mut self, expr: &Expr, op: BinaryOp, left: &Expr, right: &Expr) {
+        let is_nil = |expr: &Expr| matches!(expr.kind, ExprKind::Literal(Literal::Nil));
+        let other = match (is_nil(left), is_nil(right)) {
+            (false, true) => left,
+            (true, false) => right,
+            _ => return,
+        };
+        let suggestion = match op {
+            BinaryOp::Equal => format!("!{}", self.text(other.span)),
+            BinaryOp::NotEqual => self.text(other.span).to_string(),
+            _ => return,
+        };
+        let message = format!(
+            "`{}` can be written `{}` unless it can be false",
+            self.text(expr.span),
+            suggestion
+        );
+        self.report("nil-comparison", expr.span, message);
+    }
+}
+
+/// Runs the rules `config` enables over a file, minus what its `lint:allow` comments allow.
+/// `natives` are the functions that exist before the file runs.
+pub fn lint(source: &str, natives: &[&str], config: &Config) -> Result<Vec<Lint>, Vec<Diagnostic>> {
+    let program = Source(source.into()).parse()?;
+    let mut linter = Linter {
+        source,
+        natives: natives.iter().copied().collect(),
+        aliases: HashMap::new(),
+        lints: vec![],
+    };
+    for decl in &program {
+        linter.declaration(decl);
+    }
+    let allowed = allowed(source);
+    Ok(linter
+        .lints
+        .into_iter()
+        .filter(|lint| config.enabled(lint.rule))
+        .filter(|lint| {
+            !allowed
+                .get(&lint.line)
+                .is_some_and(|rules| rules.iter().any(|rule| rule == lint.rule))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test_lint {
+    use super::*;
+
+    fn rules(source: &str, config: &Config) -> Vec<(&'static str, i32)> {
+        lint(source, &["clock"], config)
+            .unwrap()
+            .into_iter()
+            .map(|lint| (lint.rule, lint.line))
+            .collect()
+    }
+
+    #[test]
+    fn finds_each_rule() {
+        let source = "var a = 1;\nprint a == nil;\nimport \"m.lox\" as m;\nvar clock = 2;\nvar m = a + clock;\nprint m != nil == a;";
+        let lints = lint(source, &["clock"], &Config::default()).unwrap();
+        assert_eq!(
+            lints.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "Warning at line 2, `a == nil` can be written `!a` unless it can be false [nil-comparison]",
+                "Warning at line 4, 'clock' hides the native function of the same name [shadowed-variable]",
+                "Warning at line 5, 'm' hides the module imported on line 3 [shadowed-variable]",
+                "Warning at line 6, `m != nil` can be written `m` unless it can be false [nil-comparison]",
+            ]
+        );
+        assert_eq!((lints[2].line, lints[2].column), (5, 4));
+    }
+
+    #[test]
+    fn comments_allow_rules_on_their_line() {
+        let source = "var a = nil != a; // lint:allow(nil-comparison)\n// lint:allow(shadowed-variable, nil-comparison)\nvar clock = a == nil;\nvar clock = 1;";
+        assert_eq!(
+            rules(source, &Config::default()),
+            vec![("shadowed-variable", 4)]
+        );
+    }
+
+    #[test]
+    fn config_turns_rules_off() {
+        let config = Config::parse(
+            "# project settings\n[package]\nname = \"demo\"\n\n[lint]\nshadowed-variable = false # noisy\nnil-comparison = true\n",
+        )
+        .unwrap();
+        assert!(!config.enabled("shadowed-variable"));
+        assert_eq!(
+            rules("var clock = 1;\nprint 1 != nil;", &config),
+            vec![("nil-comparison", 2)]
+        );
+        assert_eq!(
+            Config::parse("[lint]\nno-such-rule = false"),
+            Err("rlox.toml line 2: unknown rule 'no-such-rule'".into())
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rlox::{
+    debug::globals,
+    lint::{lint as lint_source, Config, RULES},
+    vm::VM,
+};
+
+const USAGE: &str = "Usage: rlox lint [--rules] file.lox...";
+
+/// The closest `rlox.toml` in the file's directory or above it.
+fn find_config(path: &str) -> Option<PathBuf> {
+    let path = fs::canonicalize(path).ok()?;
+    path.ancestors()
+        .skip(1)
+        .map(|dir| dir.join("rlox.toml"))
+        .find(|config| config.is_file())
+}
+
+fn config_for(path: &str) -> Result<Config, String> {
+    match find_config(path) {
+        Some(config) => {
+            let text = fs::read_to_string(&config)
+                .map_err(|error| format!("{}: {}", config.display(), error))?;
+            Config::parse(&text).map_err(|error| format!("{}: {}", config.display(), error))
+        }
+        None => Ok(Config::default()),
+    }
+}
+
+/// `rlox lint`: reports likely mistakes in each file. Rules are configured by the nearest
+/// `rlox.toml`, and `--rules` lists them.
+pub fn lint(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|arg| arg == "--rules") {
+        for (rule, description) in RULES {
+            println!("{:20} {}", rule, description);
+        }
+        return Ok(());
+    }
+    if args.is_empty() || args.iter().any(|arg| arg.starts_with('-')) {
+        return Err(USAGE.into());
+    }
+    let vm = VM::new();
+    let natives: Vec<&str> = globals(&vm).into_iter().map(|(name, _)| name).collect();
+    let mut found = 0;
+    for path in args {
+        let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        let config = config_for(path)?;
+        match lint_source(&source, &natives, &config) {
+            Ok(lints) => {
+                for lint in &lints {
+                    println!("{}: {}", Path::new(path).display(), lint);
+                }
+                found += lints.len();
+            }
+            Err(errors) => {
+                for error in errors {
+                    println!("{}: {}", path, error);
+                }
+                return Err(format!("{} doesn't parse", path));
+            }
+        }
+    }
+    match found {
+        0 => Ok(()),
+        1 => Err("1 problem found".into()),
+        n => Err(format!("{} problems found", n)),
+    }
+}
+
+#[cfg(test)]
+mod test_linter {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn reads_the_nearest_config() {
+        let dir = temp_dir("lint-project");
+        let nested = dir.join("src");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.join("rlox.toml"), "[lint]\nshadowed-variable = false\n").unwrap();
+        let script = nested.join("main.lox");
+        fs::write(&script, "var clock = 1;\n").unwrap();
+        let path = script.to_string_lossy().to_string();
+        assert_eq!(lint(std::slice::from_ref(&path)), Ok(()));
+
+        fs::write(&script, "var clock = 1;\nprint 1 == nil;\n").unwrap();
+        assert_eq!(lint(&[path]), Err("1 problem found".into()));
+    }
+}
@@ -0,0 +1,226 @@
+use std::cmp::Ordering;
+
+use crate::{
+    common::{Obj, Value},
+    convert::{expected, FromLox},
+    gc::{Heap, ObjRef},
+    vm::{RuntimeError, VM},
+};
+
+/// A list argument of a native, by handle, so the native can change the list in place.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ListRef(pub ObjRef);
+
+impl ListRef {
+    pub fn items(self, heap: &Heap) -> &Vec<Value> {
+        match heap.get(self.0) {
+            Obj::List(items) => items,
+            _ => unreachable!("ListRef always refers to a list"),
+        }
+    }
+
+    /// The items, to change. Whoever changes how many there are calls `Heap::resized` after.
+    pub fn items_mut(self, heap: &mut Heap) -> &mut Vec<Value> {
+        match heap.get_mut(self.0) {
+            Obj::List(items) => items,
+            _ => unreachable!("ListRef always refers to a list"),
+        }
+    }
+}
+
+impl FromLox for ListRef {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+        match value.as_obj() {
+            Some(obj) if matches!(vm.heap.get(obj), Obj::List(_)) => Ok(ListRef(obj)),
+            _ => Err(expected("list", value, vm)),
+        }
+    }
+}
+
+/// The position `index` stands for in a list of `len` items. Lists are indexed from 0 and there
+/// are no negative indices. `between` also allows `len`, for positions between items like where
+/// to insert or where a slice ends.
+pub fn position(index: &Value, len: usize, between: bool) -> Result<usize, RuntimeError> {
+    let Some(n) = index.as_number() else {
+        return Err(RuntimeError::new("List index must be a number"));
+    };
+    if n.fract() != 0.0 {
+        return Err(RuntimeError::new(format!(
+            "List index must be a whole number, got {}",
+            n
+        )));
+    }
+    if n < 0.0 {
+        return Err(RuntimeError::new(format!("List index {} is negative", n)));
+    }
+    let end = if between { len + 1 } else { len };
+    if n >= end as f64 {
+        return Err(RuntimeError::new(format!(
+            "List index {} is out of bounds for a list of {} items",
+            n, len
+        )));
+    }
+    Ok(n as usize)
+}
+
+fn push(vm: &mut VM, (list, value): (ListRef, Value)) -> Result<(), RuntimeError> {
+    list.items_mut(&mut vm.heap).push(value);
+    vm.heap.resized(list.0);
+    Ok(())
+}
+
+fn pop(vm: &mut VM, (list,): (ListRef,)) -> Result<Value, RuntimeError> {
+    let item = list.items_mut(&mut vm.heap).pop();
+    vm.heap.resized(list.0);
+    item.ok_or_else(|| RuntimeError::new("Can't pop from an empty list"))
+}
+
+fn len(vm: &mut VM, (list,): (ListRef,)) -> Result<usize, RuntimeError> {
+    Ok(list.items(&vm.heap).len())
+}
+
+fn insert(vm: &mut VM, (list, index, value): (ListRef, Value, Value)) -> Result<(), RuntimeError> {
+    let index = position(&index, list.items(&vm.heap).len(), true)?;
+    list.items_mut(&mut vm.heap).insert(index, value);
+    vm.heap.resized(list.0);
+    Ok(())
+}
+
+fn remove(vm: &mut VM, (list, index): (ListRef, Value)) -> Result<Value, RuntimeError> {
+    let index = position(&index, list.items(&vm.heap).len(), false)?;
+    let item = list.items_mut(&mut vm.heap).remove(index);
+    vm.heap.resized(list.0);
+    Ok(item)
+}
+
+fn slice(
+    vm: &mut VM,
+    (list, start, end): (ListRef, Value, Value),
+) -> Result<Vec<Value>, RuntimeError> {
+    let items = list.items(&vm.heap);
+    let start = position(&start, items.len(), true)?;
+    let end = position(&end, items.len(), true)?;
+    if start > end {
+        return Err(RuntimeError::new(format!(
+            "Slice starts at {} but ends before that, at {}",
+            start, end
+        )));
+    }
+    Ok(items[start..end].to_vec())
+}
+
+fn contains(vm: &mut VM, (list, value): (ListRef, Value)) -> Result<bool, RuntimeError> {
+    Ok(list.items(&vm.heap).contains(&value))
+}
+
+// Only numbers and strings have an order
+fn sort(vm: &mut VM, (list,): (ListRef,)) -> Result<(), RuntimeError> {
+    let mut items = list.items(&vm.heap).clone();
+    let heap = &vm.heap;
+    let compare: fn(&Heap, &Value, &Value) -> Ordering =
+        if items.iter().all(|item| item.as_number().is_some()) {
+            |_, a, b| a.as_number().unwrap().total_cmp(&b.as_number().unwrap())
+        } else if items.iter().all(|item| heap.as_str(item).is_some()) {
+            |heap, a, b| heap.as_str(a).cmp(&heap.as_str(b))
+        } else {
+            return Err(RuntimeError::new(
+                "Can only sort a list of numbers or a list of strings",
+            ));
+        };
+    items.sort_by(|a, b| compare(heap, a, b));
+    *list.items_mut(&mut vm.heap) = items;
+    Ok(())
+}
+
+/// Gives lists their methods: `push`, `pop`, `len`, `insert`, `remove`, `slice`, `contains` and
+/// `sort`.
+pub fn define_methods(vm: &mut VM) {
+    vm.define_method("list", "push", push);
+    vm.define_method("list", "pop", pop);
+    vm.define_method("list", "len", len);
+    vm.define_method("list", "insert", insert);
+    vm.define_method("list", "remove", remove);
+    vm.define_method("list", "slice", slice);
+    vm.define_method("list", "contains", contains);
+    vm.define_method("list", "sort", sort);
+}
+
+#[cfg(test)]
+mod test_list {
+    use super::*;
+    use crate::{
+        compile::Source,
+        vm::{InterpretMode, InterpretResult},
+    };
+
+    // The error report, if the source fails
+    fn run(vm: &mut VM, source: &str) -> Option<String> {
+        let chunk = Source(source.into())
+            .compile("test", InterpretMode::Release, &mut vm.heap)
+            .unwrap();
+        match vm.interpret(chunk, InterpretMode::Release) {
+            InterpretResult::RuntimeError(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    fn printed(vm: &VM, name: &str) -> String {
+        vm.get_global::<Value>(name).unwrap().print_lox(&vm.heap)
+    }
+
+    #[test]
+    fn literals_indexing_and_methods() {
+        let mut vm = VM::new();
+        // Every allocation collects, so anything left unrooted is caught
+        vm.heap.stress = true;
+        let source = "var a = [3, 1, [\"x\"]];\na.push(5);\na[2] = a.pop();\na.insert(0, 9);\nvar removed = a.remove(1);\na.sort();\nvar s = a.slice(1, a.len());\nvar has = [a.contains(9), s.contains(3)];\nvar words = [\"b\", \"c\", \"a\"];\nwords.sort();";
+        assert_eq!(run(&mut vm, source), None);
+        assert_eq!(printed(&vm, "a"), "[1, 5, 9]");
+        assert_eq!(vm.get_global("removed"), Ok(3.0));
+        assert_eq!(printed(&vm, "s"), "[5, 9]");
+        assert_eq!(printed(&vm, "has"), "[true, false]");
+        assert_eq!(printed(&vm, "words"), "[a, b, c]");
+    }
+
+    #[test]
+    fn bad_indices_are_runtime_errors() {
+        let mut vm = VM::new();
+        let error = |vm: &mut VM, source: &str| run(vm, source).unwrap();
+        run(&mut vm, "var a = [1, 2];");
+        assert_eq!(
+            error(&mut vm, "print a[-1];"),
+            "Error at line 1, List index -1 is negative"
+        );
+        assert_eq!(
+            error(&mut vm, "a[2] = 3;"),
+            "Error at line 1, List index 2 is out of bounds for a list of 2 items"
+        );
+        assert_eq!(
+            error(&mut vm, "a.insert(1 / 2, 1);"),
+            "Error at line 1, List index must be a whole number, got 0.5"
+        );
+        assert_eq!(
+            error(&mut vm, "[].pop();"),
+            "Error at line 1, Can't pop from an empty list"
+        );
+        assert_eq!(
+            error(&mut vm, "[1, \"a\"].sort();"),
+            "Error at line 1, Can only sort a list of numbers or a list of strings"
+        );
+        assert_eq!(
+            error(&mut vm, "a.size();"),
+            "Error at line 1, Undefined property 'size' on list"
+        );
+        assert_eq!(
+            error(&mut vm, "print \"ab\"[0];"),
+            "Error at line 1, Can only index lists and maps"
+        );
+    }
+
+    #[test]
+    fn a_list_can_contain_itself() {
+        let mut vm = VM::new();
+        assert_eq!(run(&mut vm, "var a = [1];\na.push(a);"), None);
+        assert_eq!(printed(&vm, "a"), "[1, [...]]");
+    }
+}
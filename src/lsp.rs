@@ -0,0 +1,619 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use rlox::{
+    ast::{Decl, DeclKind},
+    compile::Source,
+    debug::globals,
+    diagnostic::{Diagnostic, Severity},
+    json::Json,
+    resolve::resolve,
+    tokens::{Token, TokenType, Tokenizer, KEYWORDS},
+    vm::VM,
+};
+
+use crate::rpc::{read_message, write_message};
+
+// Numbers the protocol gives to kinds of symbols and completions
+const SYMBOL_MODULE: i32 = 2;
+const SYMBOL_VARIABLE: i32 = 13;
+const COMPLETION_KEYWORD: i32 = 14;
+const SEVERITY_ERROR: i32 = 1;
+const SEVERITY_WARNING: i32 = 2;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+/// Something a file declares: a global with `var`, or a module with `import ... as`.
+#[derive(Debug, Clone, PartialEq)]
+struct Declaration {
+    keyword: &'static str,
+    name: String,
+    line: i32,
+    column: i32,
+}
+
+impl Declaration {
+    fn of(decl: &Decl) -> Option<Self> {
+        let (keyword, name) = match &decl.kind {
+            DeclKind::Var { name, .. } => ("var", name),
+            DeclKind::Import {
+                alias: Some(alias), ..
+            } => ("import", alias),
+            _ => return None,
+        };
+        Some(Declaration {
+            keyword,
+            name: name.name.clone(),
+            line: name.line,
+            column: name.column,
+        })
+    }
+
+    fn symbol_kind(&self) -> i32 {
+        match self.keyword {
+            "import" => SYMBOL_MODULE,
+            _ => SYMBOL_VARIABLE,
+        }
+    }
+}
+
+/// An open file, tokenized and parsed once per edit.
+struct Document {
+    text: String,
+    tokens: Vec<Token>,
+    declarations: Vec<Declaration>,
+    /// Parse errors, or the resolver's warnings once the file parses.
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    /// `previous` is the last version of the file. While the new one doesn't parse it keeps the
+    /// old declarations, so the symbols don't vanish halfway through typing a line.
+    fn new(text: String, natives: &[String], previous: Option<Document>) -> Self {
+        let source = Source(text);
+        let tokens = Tokenizer::new(&source).collect();
+        let (declarations, diagnostics) = match source.parse() {
+            Ok(program) => {
+                let natives: Vec<&str> = natives.iter().map(String::as_str).collect();
+                let warnings = resolve(&program, &natives)
+                    .diagnostics
+                    .into_iter()
+                    .filter(|diagnostic| !diagnostic.is_error())
+                    .collect();
+                (
+                    program.iter().filter_map(Declaration::of).collect(),
+                    warnings,
+                )
+            }
+            Err(errors) => (
+                previous
+                    .map(|document| document.declarations)
+                    .unwrap_or_default(),
+                errors,
+            ),
+        };
+        Document {
+            text: source.0,
+            tokens,
+            declarations,
+            diagnostics,
+        }
+    }
+
+    /// The identifier under the cursor, which may also sit right after its last character.
+    fn identifier_at(&self, line: i32, character: i32) -> Option<(&str, &Token)> {
+        self.tokens
+            .iter()
+            .find_map(|token| match &token.token_type {
+                TokenType::Identifier(name)
+                    if token.line == line
+                        && (token.column..=token.column + length(name)).contains(&character) =>
+                {
+                    Some((name.as_str(), token))
+                }
+                _ => None,
+            })
+    }
+
+    fn declaration(&self, name: &str) -> Option<&Declaration> {
+        self.declarations
+            .iter()
+            .find(|declaration| declaration.name == name)
+    }
+}
+
+// Positions count characters, where the protocol counts UTF-16 code units. They only disagree past
+// the Basic Multilingual Plane. The protocol also counts lines from 0, where rlox counts from 1, so
+// `position` and `cursor` translate.
+fn length(name: &str) -> i32 {
+    name.chars().count() as i32
+}
+
+fn position(line: i32, character: i32) -> Json {
+    Json::object([
+        ("line", Json::from(line - 1)),
+        ("character", Json::from(character)),
+    ])
+}
+
+fn range(line: i32, column: i32, length: i32) -> Json {
+    Json::object([
+        ("start", position(line, column)),
+        ("end", position(line, column + length)),
+    ])
+}
+
+fn text_document_uri(params: &Json) -> &str {
+    params
+        .get("textDocument")
+        .and_then(|document| document.get("uri"))
+        .and_then(Json::as_str)
+        .unwrap_or("")
+}
+
+fn cursor(params: &Json) -> (i32, i32) {
+    let field = |name| {
+        params
+            .get("position")
+            .and_then(|position| position.get(name))
+            .and_then(Json::as_f64)
+            .unwrap_or(0.0) as i32
+    };
+    (field("line") + 1, field("character"))
+}
+
+/// A language server for one editor, over `read_message` and `write_message`.
+struct Server {
+    out: Box<dyn Write>,
+    documents: HashMap<String, Document>,
+    natives: Vec<String>,
+}
+
+impl Server {
+    fn send(&mut self, fields: Vec<(&str, Json)>) -> io::Result<()> {
+        let version = ("jsonrpc", Json::from("2.0"));
+        write_message(
+            &mut self.out,
+            &Json::object([version].into_iter().chain(fields)),
+        )
+    }
+
+    fn respond(&mut self, id: &Json, result: Json) -> io::Result<()> {
+        self.send(vec![("id", id.clone()), ("result", result)])
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> io::Result<()> {
+        self.send(vec![("method", Json::from(method)), ("params", params)])
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = self
+            .documents
+            .get(uri)
+            .map_or(&[][..], |document| &document.diagnostics)
+            .iter()
+            .map(|diagnostic| {
+                let severity = match diagnostic.severity {
+                    Severity::Error => SEVERITY_ERROR,
+                    Severity::Warning => SEVERITY_WARNING,
+                };
+                Json::object([
+                    ("range", range(diagnostic.line, diagnostic.column, 1)),
+                    ("severity", Json::from(severity)),
+                    ("source", Json::from("rlox")),
+                    ("message", Json::from(diagnostic.message.as_str())),
+                ])
+            })
+            .collect();
+        self.notify(
+            "textDocument/publishDiagnostics",
+            Json::object([
+                ("uri", Json::from(uri)),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        )
+    }
+
+    fn capabilities() -> Json {
+        Json::object([(
+            "capabilities",
+            Json::object([
+                // Editors send the whole text on every change
+                ("textDocumentSync", Json::from(1)),
+                ("documentSymbolProvider", Json::from(true)),
+                ("definitionProvider", Json::from(true)),
+                ("hoverProvider", Json::from(true)),
+                ("completionProvider", Json::Object(vec![])),
+            ]),
+        )])
+    }
+
+    fn document_symbols(&self, uri: &str) -> Json {
+        let Some(document) = self.documents.get(uri) else {
+            return Json::Array(vec![]);
+        };
+        let symbols = document
+            .declarations
+            .iter()
+            .map(|declaration| {
+                let range = range(
+                    declaration.line,
+                    declaration.column,
+                    length(&declaration.name),
+                );
+                Json::object([
+                    ("name", Json::from(declaration.name.as_str())),
+                    ("detail", Json::from(declaration.keyword)),
+                    ("kind", Json::from(declaration.symbol_kind())),
+                    ("range", range.clone()),
+                    ("selectionRange", range),
+                ])
+            })
+            .collect();
+        Json::Array(symbols)
+    }
+
+    fn definition(&self, uri: &str, (line, character): (i32, i32)) -> Json {
+        let declaration = self.documents.get(uri).and_then(|document| {
+            let (name, _) = document.identifier_at(line, character)?;
+            document.declaration(name)
+        });
+        match declaration {
+            Some(declaration) => Json::object([
+                ("uri", Json::from(uri)),
+                (
+                    "range",
+                    range(
+                        declaration.line,
+                        declaration.column,
+                        length(&declaration.name),
+                    ),
+                ),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, uri: &str, (line, character): (i32, i32)) -> Json {
+        let Some(document) = self.documents.get(uri) else {
+            return Json::Null;
+        };
+        let Some((name, token)) = document.identifier_at(line, character) else {
+            return Json::Null;
+        };
+        let contents = match document.declaration(name) {
+            Some(declaration) => {
+                let text = document
+                    .text
+                    .lines()
+                    .nth(declaration.line as usize - 1)
+                    .unwrap_or_default();
+                format!("```lox\n{}\n```", text.trim())
+            }
+            None if self.natives.iter().any(|native| native == name) => {
+                format!("`{}` is a native function", name)
+            }
+            None => return Json::Null,
+        };
+        Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", Json::from("markdown")),
+                    ("value", Json::from(contents)),
+                ]),
+            ),
+            ("range", range(token.line, token.column, length(name))),
+        ])
+    }
+
+    fn completion() -> Json {
+        let items = KEYWORDS
+            .iter()
+            .map(|(keyword, _)| {
+                Json::object([
+                    ("label", Json::from(*keyword)),
+                    ("kind", Json::from(COMPLETION_KEYWORD)),
+                ])
+            })
+            .collect();
+        Json::Array(items)
+    }
+
+    /// Handles one request or notification. Returns false once the editor says to exit.
+    fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let id = message.get("id").unwrap_or(&Json::Null);
+        let uri = text_document_uri(params);
+        match method {
+            "initialize" => self.respond(id, Self::capabilities())?,
+            "shutdown" => self.respond(id, Json::Null)?,
+            "exit" => return Ok(false),
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or_default();
+                let document = Document::new(text.into(), &self.natives, None);
+                self.documents.insert(uri.into(), document);
+                self.publish_diagnostics(uri)?;
+            }
+            "textDocument/didChange" => {
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+                if let Some(text) = text {
+                    let previous = self.documents.remove(uri);
+                    let document = Document::new(text.into(), &self.natives, previous);
+                    self.documents.insert(uri.into(), document);
+                    self.publish_diagnostics(uri)?;
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri)?;
+            }
+            "textDocument/documentSymbol" => {
+                let symbols = self.document_symbols(uri);
+                self.respond(id, symbols)?;
+            }
+            "textDocument/definition" => {
+                let definition = self.definition(uri, cursor(params));
+                self.respond(id, definition)?;
+            }
+            "textDocument/hover" => {
+                let hover = self.hover(uri, cursor(params));
+                self.respond(id, hover)?;
+            }
+            "textDocument/completion" => self.respond(id, Self::completion())?,
+            // Requests need an answer, notifications like `initialized` don't
+            _ if *id != Json::Null => {
+                let error = Json::object([
+                    ("code", Json::from(METHOD_NOT_FOUND)),
+                    (
+                        "message",
+                        Json::from(format!("Unsupported method {}", method)),
+                    ),
+                ]);
+                self.send(vec![("id", id.clone()), ("error", error)])?;
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+}
+
+/// Serves one editor until it sends `exit` or closes `input`.
+pub fn serve(mut input: Box<dyn BufRead>, out: Box<dyn Write>) -> io::Result<()> {
+    let natives = globals(&VM::new())
+        .into_iter()
+        .map(|(name, _)| name.to_string())
+        .collect();
+    let mut server = Server {
+        out,
+        documents: HashMap::new(),
+        natives,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// `rlox lsp`: a Language Server Protocol server on stdin and stdout, for editors to start.
+pub fn lsp(args: &[String]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: rlox lsp".into());
+    }
+    serve(
+        Box::new(io::BufReader::new(io::stdin())),
+        Box::new(io::stdout()),
+    )
+    .map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod test_lsp {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::SharedBuffer;
+
+    const URI: &str = "file:///main.lox";
+
+    fn request(id: usize, method: &str, params: Json) -> Json {
+        Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("id", Json::from(id)),
+            ("method", Json::from(method)),
+            ("params", params),
+        ])
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from(method)),
+            ("params", params),
+        ])
+    }
+
+    fn at(line: i32, character: i32) -> Json {
+        Json::object([
+            ("textDocument", Json::object([("uri", Json::from(URI))])),
+            ("position", position(line, character)),
+        ])
+    }
+
+    /// Sends every message up front like a scripted editor, and returns what came back.
+    fn session(messages: Vec<Json>) -> Vec<Json> {
+        let mut input = vec![];
+        for message in messages {
+            write_message(&mut input, &message).unwrap();
+        }
+        let out = SharedBuffer::default();
+        serve(Box::new(Cursor::new(input)), Box::new(out.clone())).unwrap();
+        let mut output = Cursor::new(out.take());
+        let mut replies = vec![];
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    fn result(replies: &[Json], id: usize) -> &Json {
+        replies
+            .iter()
+            .find(|reply| reply.get("id") == Some(&Json::from(id)))
+            .and_then(|reply| reply.get("result"))
+            .unwrap()
+    }
+
+    fn open(text: &str) -> Json {
+        notification(
+            "textDocument/didOpen",
+            Json::object([(
+                "textDocument",
+                Json::object([
+                    ("uri", Json::from(URI)),
+                    ("languageId", Json::from("lox")),
+                    ("version", Json::from(1)),
+                    ("text", Json::from(text)),
+                ]),
+            )]),
+        )
+    }
+
+    fn change(text: &str) -> Json {
+        notification(
+            "textDocument/didChange",
+            Json::object([
+                ("textDocument", Json::object([("uri", Json::from(URI))])),
+                (
+                    "contentChanges",
+                    Json::Array(vec![Json::object([("text", Json::from(text))])]),
+                ),
+            ]),
+        )
+    }
+
+    fn symbols() -> Json {
+        Json::object([("textDocument", Json::object([("uri", Json::from(URI))]))])
+    }
+
+    #[test]
+    fn publishes_diagnostics_on_every_edit() {
+        let replies = session(vec![
+            request(1, "initialize", Json::Object(vec![])),
+            notification("initialized", Json::Object(vec![])),
+            open("var a = 1;\nprint a +;"),
+            change("print b;"),
+            request(2, "shutdown", Json::Null),
+            notification("exit", Json::Null),
+        ]);
+        let capabilities = result(&replies, 1).get("capabilities").unwrap();
+        assert_eq!(capabilities.get("hoverProvider"), Some(&Json::from(true)));
+        let published: Vec<_> = replies
+            .iter()
+            .filter_map(|reply| reply.get("params")?.get("diagnostics")?.as_array())
+            .collect();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0][0].get("range"), Some(&range(2, 9, 1)));
+        // The protocol counts lines from 0
+        let start = published[0][0]
+            .get("range")
+            .and_then(|range| range.get("start"));
+        assert_eq!(
+            start.and_then(|start| start.get("line")),
+            Some(&Json::from(1))
+        );
+        assert_eq!(
+            published[0][0].get("message"),
+            Some(&Json::from("Unexpected token Semicolon"))
+        );
+        assert_eq!(
+            published[1][0].get("severity"),
+            Some(&Json::from(SEVERITY_WARNING))
+        );
+        assert_eq!(
+            published[1][0].get("message"),
+            Some(&Json::from("'b' is never defined"))
+        );
+        assert_eq!(result(&replies, 2), &Json::Null);
+    }
+
+    #[test]
+    fn finds_symbols_definitions_and_hovers() {
+        let replies = session(vec![
+            open("var total = 0;\nimport \"util.lox\" as util;\ntotal = total + clock();\n"),
+            request(1, "textDocument/documentSymbol", symbols()),
+            request(2, "textDocument/definition", at(3, 10)),
+            request(3, "textDocument/hover", at(3, 5)),
+            request(4, "textDocument/hover", at(3, 17)),
+            request(5, "textDocument/completion", at(3, 0)),
+            request(6, "textDocument/formatting", at(1, 0)),
+        ]);
+        let symbols: Vec<_> = result(&replies, 1)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|symbol| (symbol.get("name").unwrap(), symbol.get("kind").unwrap()))
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                (&Json::from("total"), &Json::from(SYMBOL_VARIABLE)),
+                (&Json::from("util"), &Json::from(SYMBOL_MODULE)),
+            ]
+        );
+
+        assert_eq!(result(&replies, 2).get("range"), Some(&range(1, 4, 5)));
+        let hover = |id| {
+            result(&replies, id)
+                .get("contents")
+                .and_then(|contents| contents.get("value"))
+                .and_then(Json::as_str)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(hover(3), "```lox\nvar total = 0;\n```");
+        assert_eq!(hover(4), "`clock` is a native function");
+
+        let completions = result(&replies, 5).as_array().unwrap();
+        assert_eq!(completions.len(), KEYWORDS.len());
+        let unsupported = replies
+            .iter()
+            .find(|reply| reply.get("id") == Some(&Json::from(6)))
+            .unwrap();
+        assert!(unsupported.get("error").is_some());
+    }
+
+    #[test]
+    fn keeps_the_symbols_while_an_edit_does_not_parse() {
+        let names = |replies: &[Json], id| -> Vec<Json> {
+            result(replies, id)
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|symbol| symbol.get("name").unwrap().clone())
+                .collect()
+        };
+        let replies = session(vec![
+            open("var a = 1;"),
+            change("var a = 1;\nvar b ="),
+            request(1, "textDocument/documentSymbol", symbols()),
+            change("var a = 1;\nvar b = 2;"),
+            request(2, "textDocument/documentSymbol", symbols()),
+        ]);
+        assert_eq!(names(&replies, 1), [Json::from("a")]);
+        assert_eq!(names(&replies, 2), [Json::from("a"), Json::from("b")]);
+    }
+}
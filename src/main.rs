@@ -1,67 +1,151 @@
-use std::io::Write;
+use std::path::PathBuf;
 
-use vm::VM;
+use rlox::{
+    compile::{Session, Source},
+    profile::Profiler,
+    trace::{self, TraceFilter, Tracer},
+    vm::{self, VM},
+};
 
-use compile::Source;
+use repl::repl;
 
-mod common;
-mod compile;
-mod parse;
-mod tokens;
-mod vm;
+mod bench;
+mod checker;
+mod dap;
+mod debugger;
+mod fmt;
+mod line_editor;
+mod linter;
+mod lsp;
+mod repl;
+mod rpc;
+#[cfg(test)]
+mod test_util;
 
-fn repl(mode: vm::InterpretMode) {
-    let mut vm = VM::new();
-    loop {
-        print!("> ");
-        std::io::stdout().flush().unwrap();
-        let mut input = String::new();
-        match std::io::stdin().read_line(&mut input) {
-            Ok(n) => {
-                if n > 0 {
-                    if let Some(chunk) = Source(input).compile("repl", mode) {
-                        vm.interpret(chunk, mode);
-                    } else {
-                        println!("Failed to compile");
-                    }
-                } else {
-                    println!("Bye!");
-                    break;
-                }
-            }
-            Err(error) => {
-                println!("error: {}", error);
-                break;
+/// Returns false if the script failed with a runtime error. Modules it imports are also looked for
+/// in `include`.
+fn run_file(vm: &mut VM, path: &str, mode: vm::InterpretMode, include: &[PathBuf]) -> bool {
+    let source =
+        Source(std::fs::read_to_string(path).expect("Something went wrong reading the file"));
+    let mut session = Session::new(vm, include);
+    let warnings = {
+        let predefined: Vec<&str> = session.predefined.iter().map(String::as_str).collect();
+        source.warnings(&predefined)
+    };
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    match source.compile_in(path, mode, &mut vm.heap, &mut session) {
+        Some(chunk) => match vm.interpret(chunk, mode) {
+            vm::InterpretResult::RuntimeError(error) => {
+                eprintln!("{}", error);
+                false
             }
-        };
+            _ => true,
+        },
+        None => {
+            println!("Failed to compile");
+            true
+        }
     }
 }
 
-fn run_file(path: &str, mode: vm::InterpretMode) {
+/// Prints a file's syntax tree as S-expressions, one declaration per line, instead of running it.
+/// Returns false if it doesn't parse.
+fn dump_ast(path: &str) -> bool {
     let source =
         Source(std::fs::read_to_string(path).expect("Something went wrong reading the file"));
-    let mut vm = VM::new();
-    match source.compile(path, mode) {
-        Some(chunk) => {
-            if let vm::InterpretResult::RuntimeError = vm.interpret(chunk, mode) {
-                std::process::exit(70);
+    match source.parse() {
+        Ok(program) => {
+            for decl in program {
+                println!("{}", decl);
             }
+            true
         }
-        None => {
-            println!("Failed to compile");
+        Err(errors) => {
+            for error in errors {
+                println!("{}", error);
+            }
+            false
         }
     }
 }
 
+// The report goes to stderr to keep it apart from the script's own output.
+fn write_profile(profiler: &Profiler, folded_path: Option<&str>) {
+    eprint!("{}", profiler.report());
+    if let Some(path) = folded_path {
+        let written =
+            std::fs::File::create(path).and_then(|mut file| profiler.write_folded(&mut file));
+        if let Err(error) = written {
+            eprintln!("Couldn't write folded stacks to {}: {}", path, error);
+        }
+    }
+}
+
+type Subcommand = fn(&[String]) -> Result<(), String>;
+
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
+    let subcommand: Option<Subcommand> = match args.get(1).map(String::as_str) {
+        Some("bench") => Some(bench::bench),
+        Some("check") => Some(checker::check),
+        Some("dap") => Some(dap::dap),
+        Some("debug") => Some(debugger::debug),
+        Some("fmt") => Some(fmt::fmt),
+        Some("lint") => Some(linter::lint),
+        Some("lsp") => Some(lsp::lsp),
+        _ => None,
+    };
+    if let Some(subcommand) = subcommand {
+        if let Err(error) = subcommand(&args[2..]) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut file_ref: Option<&String> = None;
     let mut interpret_mode = vm::InterpretMode::Release;
+    let mut stress_gc = false;
+    let mut dump = false;
+    // `--profile` prints a report, `--profile=path` also writes folded stacks to path
+    let mut profile: Option<Option<&str>> = None;
+    let mut trace_path: Option<&str> = None;
+    let mut trace_filter = TraceFilter::default();
+    // Searched for imported modules before `LOX_PATH`, as given by `-I dir` or `-Idir`
+    let mut include: Vec<PathBuf> = vec![];
 
-    for arg in &args[1..] {
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
         match arg.as_str() {
+            "-I" => match rest.next() {
+                Some(dir) => include.push(dir.into()),
+                None => {
+                    eprintln!("-I needs a directory");
+                    std::process::exit(1);
+                }
+            },
+            _ if arg.starts_with("-I") => include.push(arg["-I".len()..].into()),
             "-d" | "--debug" => interpret_mode = vm::InterpretMode::Debug,
+            "--stress-gc" => stress_gc = true,
+            "--dump-ast" => dump = true,
+            "--profile" => profile = Some(None),
+            _ if arg.starts_with("--profile=") => profile = Some(Some(&arg["--profile=".len()..])),
+            _ if arg.starts_with("--trace=") => trace_path = Some(&arg["--trace=".len()..]),
+            _ if arg.starts_with("--trace-function=") => {
+                trace_filter.function = Some(arg["--trace-function=".len()..].into())
+            }
+            _ if arg.starts_with("--trace-lines=") => {
+                match trace::parse_line_range(&arg["--trace-lines=".len()..]) {
+                    Some(lines) => trace_filter.lines = Some(lines),
+                    None => {
+                        eprintln!("Expected --trace-lines=start-end, got {}", arg);
+                        std::process::exit(1);
+                    }
+                }
+            }
             _ => {
                 if file_ref.is_none() {
                     file_ref = Some(arg);
@@ -73,9 +157,50 @@ fn main() {
         }
     }
 
+    if dump {
+        let Some(path) = file_ref else {
+            eprintln!("--dump-ast needs a file to parse");
+            std::process::exit(1);
+        };
+        if !dump_ast(path) {
+            std::process::exit(65);
+        }
+        return;
+    }
+
+    let mut vm = VM::new();
+    vm.heap.stress = stress_gc;
+    if profile.is_some() {
+        vm.profiler = Some(Profiler::new());
+    }
+    if let Some(path) = trace_path {
+        match std::fs::File::create(path) {
+            Ok(file) => {
+                let out = Box::new(std::io::BufWriter::new(file));
+                vm.tracer = Some(Tracer::new(out, trace_filter));
+            }
+            Err(error) => {
+                eprintln!("Couldn't create trace file {}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+    }
+
     if let Some(path) = file_ref {
-        run_file(path.as_str(), interpret_mode);
+        let ok = run_file(&mut vm, path.as_str(), interpret_mode, &include);
+        if let (Some(profiler), Some(folded_path)) = (&vm.profiler, profile) {
+            write_profile(profiler, folded_path);
+        }
+        if let Some(error) = vm.tracer.as_ref().and_then(|tracer| tracer.error.as_ref()) {
+            eprintln!("Trace incomplete: {}", error);
+        }
+        if !ok {
+            std::process::exit(70);
+        }
+    } else if profile.is_some() {
+        eprintln!("--profile needs a file to run");
+        std::process::exit(1);
     } else {
-        repl(interpret_mode);
+        repl(vm, interpret_mode, &include);
     }
 }
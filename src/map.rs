@@ -0,0 +1,176 @@
+use crate::{
+    common::{Obj, Value, ValueKind},
+    convert::{expected, FromLox},
+    gc::{Heap, ObjRef},
+    table::Table,
+    vm::{RuntimeError, VM},
+};
+
+/// A map argument of a native, by handle, so the native can change the map in place.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapRef(pub ObjRef);
+
+impl MapRef {
+    pub fn table(self, heap: &Heap) -> &Table {
+        match heap.get(self.0) {
+            Obj::Map(table) => table,
+            _ => unreachable!("MapRef always refers to a map"),
+        }
+    }
+
+    /// The table, to change. Whoever changes how many entries there are calls `Heap::resized`
+    /// after.
+    pub fn table_mut(self, heap: &mut Heap) -> &mut Table {
+        match heap.get_mut(self.0) {
+            Obj::Map(table) => table,
+            _ => unreachable!("MapRef always refers to a map"),
+        }
+    }
+}
+
+impl FromLox for MapRef {
+    fn from_lox(value: &Value, vm: &VM) -> Result<Self, RuntimeError> {
+        match value.as_obj() {
+            Some(obj) if matches!(vm.heap.get(obj), Obj::Map(_)) => Ok(MapRef(obj)),
+            _ => Err(expected("map", value, vm)),
+        }
+    }
+}
+
+/// Checks `key` can be a map key. Only strings, numbers, booleans and nil can, the values `==`
+/// compares by what they hold rather than by identity, and NaN can't since it's never `==` to
+/// anything.
+pub fn check_key(key: &Value, heap: &Heap) -> Result<(), RuntimeError> {
+    match key.kind() {
+        ValueKind::Number(n) if n.is_nan() => Err(RuntimeError::new("Map key can't be NaN")),
+        ValueKind::Number(_) | ValueKind::Boolean(_) | ValueKind::Nil => Ok(()),
+        ValueKind::Obj(_) if heap.as_str(key).is_some() => Ok(()),
+        ValueKind::Obj(_) => Err(RuntimeError::new(format!(
+            "Map keys must be strings, numbers, booleans or nil, got {}",
+            key.type_name(heap)
+        ))),
+    }
+}
+
+/// The error for looking up a key the map doesn't have.
+pub fn missing(key: &Value, heap: &Heap) -> RuntimeError {
+    // Quoted, so the key "1" can be told apart from the key 1
+    let key = match heap.as_str(key) {
+        Some(s) => format!("{:?}", s),
+        None => key.print_lox(heap),
+    };
+    RuntimeError::new(format!("Map has no key {}", key))
+}
+
+fn keys(vm: &mut VM, (map,): (MapRef,)) -> Result<Vec<Value>, RuntimeError> {
+    Ok(map.table(&vm.heap).keys().copied().collect())
+}
+
+fn values(vm: &mut VM, (map,): (MapRef,)) -> Result<Vec<Value>, RuntimeError> {
+    Ok(map.table(&vm.heap).values().copied().collect())
+}
+
+fn has(vm: &mut VM, (map, key): (MapRef, Value)) -> Result<bool, RuntimeError> {
+    Ok(map.table(&vm.heap).contains_key(&key))
+}
+
+fn remove(vm: &mut VM, (map, key): (MapRef, Value)) -> Result<Value, RuntimeError> {
+    let value = map.table_mut(&mut vm.heap).remove(&key);
+    vm.heap.resized(map.0);
+    value.ok_or_else(|| missing(&key, &vm.heap))
+}
+
+fn len(vm: &mut VM, (map,): (MapRef,)) -> Result<usize, RuntimeError> {
+    Ok(map.table(&vm.heap).len())
+}
+
+/// Gives maps their methods: `keys`, `values`, `has`, `remove` and `len`.
+pub fn define_methods(vm: &mut VM) {
+    vm.define_method("map", "keys", keys);
+    vm.define_method("map", "values", values);
+    vm.define_method("map", "has", has);
+    vm.define_method("map", "remove", remove);
+    vm.define_method("map", "len", len);
+}
+
+#[cfg(test)]
+mod test_map {
+    use super::*;
+    use crate::{
+        compile::Source,
+        vm::{InterpretMode, InterpretResult},
+    };
+
+    // The error report, if the source fails
+    fn run(vm: &mut VM, source: &str) -> Option<String> {
+        let chunk = Source(source.into())
+            .compile("test", InterpretMode::Release, &mut vm.heap)
+            .unwrap();
+        match vm.interpret(chunk, InterpretMode::Release) {
+            InterpretResult::RuntimeError(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    fn printed(vm: &VM, name: &str) -> String {
+        vm.get_global::<Value>(name).unwrap().print_lox(&vm.heap)
+    }
+
+    #[test]
+    fn literals_indexing_and_methods() {
+        let mut vm = VM::new();
+        // Every allocation collects, so anything left unrooted is caught
+        vm.heap.stress = true;
+        let source = "var m = {\"b\": 1, \"a\": [2], 3: nil};\nm[\"c\"] = \"x\" + \"y\";\nm[\"b\"] = m[\"a\"];\nvar removed = m.remove(3);\nvar keys = m.keys();\nvar values = m.values();\nvar has = [m.has(\"a\"), m.has(3), m.has(\"b\" + \"\")];\nvar n = m.len();";
+        assert_eq!(run(&mut vm, source), None);
+        assert_eq!(printed(&vm, "m"), "{b: [2], a: [2], c: xy}");
+        assert_eq!(vm.get_global::<Value>("removed"), Ok(Value::NIL));
+        assert_eq!(printed(&vm, "keys"), "[b, a, c]");
+        assert_eq!(printed(&vm, "values"), "[[2], [2], xy]");
+        assert_eq!(printed(&vm, "has"), "[true, false, true]");
+        assert_eq!(vm.get_global("n"), Ok(3.0));
+    }
+
+    #[test]
+    fn keys_follow_equality() {
+        let mut vm = VM::new();
+        let source = "var m = {0: \"zero\", true: \"yes\", nil: \"none\"};\nvar a = [m[-0], m[1 == 1], m[nil]];\nm[0] = \"again\";";
+        assert_eq!(run(&mut vm, source), None);
+        assert_eq!(printed(&vm, "a"), "[zero, yes, none]");
+        assert_eq!(printed(&vm, "m"), "{0: again, true: yes, nil: none}");
+    }
+
+    #[test]
+    fn bad_keys_are_runtime_errors() {
+        let mut vm = VM::new();
+        let error = |vm: &mut VM, source: &str| run(vm, source).unwrap();
+        run(&mut vm, "var m = {\"a\": 1};");
+        assert_eq!(
+            error(&mut vm, "print m[\"b\"];"),
+            "Error at line 1, Map has no key \"b\""
+        );
+        assert_eq!(
+            error(&mut vm, "m.remove(1);"),
+            "Error at line 1, Map has no key 1"
+        );
+        assert_eq!(
+            error(&mut vm, "m[[]] = 1;"),
+            "Error at line 1, Map keys must be strings, numbers, booleans or nil, got list"
+        );
+        assert_eq!(
+            error(&mut vm, "var n = {0 / 0: 1};"),
+            "Error at line 1, Map key can't be NaN"
+        );
+        assert_eq!(
+            error(&mut vm, "print 1[0];"),
+            "Error at line 1, Can only index lists and maps"
+        );
+    }
+
+    #[test]
+    fn a_map_can_contain_itself() {
+        let mut vm = VM::new();
+        assert_eq!(run(&mut vm, "var m = {};\nm[\"self\"] = m;"), None);
+        assert_eq!(printed(&vm, "m"), "{self: {...}}");
+    }
+}
@@ -1,20 +1,52 @@
 use crate::{
-    common::{self, OpCode, Value},
-    tokens::{Token, TokenType, Tokenizer},
+    ast::{BinaryOp, Decl, DeclKind, Expr, ExprKind, Identifier, Literal, Stmt, UnaryOp},
+    diagnostic::Diagnostic,
+    tokens::{Span, Token, TokenType, Tokenizer},
 };
-use std::{iter::Peekable, rc::Rc};
+use std::iter::Peekable;
 
 pub struct Parser<'a> {
     tokens: Peekable<Tokenizer<'a>>,
+    repl: bool,
+    /// Line and column of the last token consumed.
+    position: (i32, i32),
+    /// Where the last token consumed ends, which is where the node being parsed ends too.
+    end: usize,
+    /// Syntax errors, each placed at the last token read before giving up.
+    pub errors: Vec<Diagnostic>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: Peekable<Tokenizer<'a>>) -> Self {
-        Parser { tokens }
+        Parser {
+            tokens,
+            repl: false,
+            position: (0, 0),
+            end: 0,
+            errors: vec![],
+        }
+    }
+
+    /// A parser for REPL input, where the trailing `;` of the last expression statement may be
+    /// omitted.
+    pub fn for_repl(tokens: Peekable<Tokenizer<'a>>) -> Self {
+        Parser {
+            repl: true,
+            ..Parser::new(tokens)
+        }
+    }
+
+    fn error<T>(&mut self, message: impl Into<String>) -> Option<T> {
+        let (line, column) = self.position;
+        self.errors.push(Diagnostic::error(message, line, column));
+        None
     }
 
     fn consume(&mut self) -> Option<Token> {
-        self.tokens.next()
+        let token = self.tokens.next()?;
+        self.position = (token.line, token.column);
+        self.end = token.span.end;
+        Some(token)
     }
 
     fn peek_precedence(&mut self) -> i32 {
@@ -25,11 +57,202 @@ impl<'a> Parser<'a> {
         self.tokens.peek().is_some()
     }
 
-    fn declaration(&mut self) -> Option<Expr> {
-        self.statement()
+    fn next_is(&mut self, token_type: TokenType) -> bool {
+        self.tokens
+            .peek()
+            .is_some_and(|t| t.token_type == token_type)
+    }
+
+    /// The span from `start` to the end of the last token consumed.
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: self.end,
+        }
+    }
+
+    // Called right after the opening paren
+    fn call(&mut self, callee: Expr, line: i32) -> Option<Expr> {
+        let mut arguments = vec![];
+        if self.next_is(TokenType::RightParen) {
+            self.consume();
+        } else {
+            loop {
+                arguments.push(self.expression(1)?);
+                match self.consume().map(|t| t.token_type) {
+                    Some(TokenType::Comma) => continue,
+                    Some(TokenType::RightParen) => break,
+                    _ => return self.error("Expected ) after arguments"),
+                }
+            }
+        }
+        Some(Expr {
+            span: self.span_from(callee.span.start),
+            line,
+            kind: ExprKind::Call {
+                callee: Box::new(callee),
+                arguments,
+            },
+        })
+    }
+
+    // Called right after the opening bracket
+    fn index(&mut self, object: Expr, line: i32) -> Option<Expr> {
+        let index = self.expression(0)?;
+        match self.consume() {
+            Some(Token {
+                token_type: TokenType::RightBracket,
+                ..
+            }) => Some(Expr {
+                span: self.span_from(object.span.start),
+                line,
+                kind: ExprKind::Index {
+                    object: Box::new(object),
+                    index: Box::new(index),
+                },
+            }),
+            _ => self.error("Expected ] after index"),
+        }
+    }
+
+    // Called right after the dot
+    fn get(&mut self, object: Expr, line: i32) -> Option<Expr> {
+        let name = self.identifier("Expected a name after .")?;
+        Some(Expr {
+            span: self.span_from(object.span.start),
+            line,
+            kind: ExprKind::Get {
+                object: Box::new(object),
+                name,
+            },
+        })
+    }
+
+    fn identifier(&mut self, message: &str) -> Option<Identifier> {
+        match self.consume() {
+            Some(Token {
+                token_type: TokenType::Identifier(name),
+                span,
+                line,
+                column,
+            }) => Some(Identifier {
+                name,
+                span,
+                line,
+                column,
+            }),
+            _ => self.error(message),
+        }
+    }
+
+    fn declaration(&mut self) -> Option<Decl> {
+        match self.tokens.peek() {
+            Some(Token {
+                token_type: TokenType::Var,
+                ..
+            }) => self.var_declaration(),
+            Some(Token {
+                token_type: TokenType::Import,
+                ..
+            }) => self.import_declaration(),
+            _ => self.statement(),
+        }
+    }
+
+    // `as` isn't a keyword, so it stays usable as a name everywhere else
+    fn import_declaration(&mut self) -> Option<Decl> {
+        let import = self.consume()?;
+        let path = match self.consume() {
+            Some(Token {
+                token_type: TokenType::Str(path),
+                ..
+            }) => path,
+            _ => return self.error("Expected a path after import"),
+        };
+        let alias = if self.next_is(TokenType::Identifier("as".into())) {
+            self.consume();
+            Some(self.identifier("Expected a name after as")?)
+        } else {
+            None
+        };
+        match self.consume() {
+            Some(Token {
+                token_type: TokenType::Semicolon,
+                line,
+                ..
+            }) => Some(Decl {
+                kind: DeclKind::Import { path, alias },
+                span: self.span_from(import.span.start),
+                line,
+            }),
+            _ => self.error("Expected ;"),
+        }
     }
 
-    fn print_statement(&mut self) -> Option<Expr> {
+    fn var_declaration(&mut self) -> Option<Decl> {
+        let var = self.consume()?;
+        let name = self.identifier("Expected variable name")?;
+        let annotation = if self.next_is(TokenType::Colon) {
+            self.consume();
+            Some(self.annotation()?)
+        } else {
+            None
+        };
+        let initializer = if self.next_is(TokenType::Equal) {
+            self.consume();
+            Some(self.expression(0)?)
+        } else {
+            None
+        };
+        match self.consume() {
+            Some(Token {
+                token_type: TokenType::Semicolon,
+                line,
+                ..
+            }) => Some(Decl {
+                kind: DeclKind::Var {
+                    name,
+                    annotation,
+                    initializer,
+                },
+                span: self.span_from(var.span.start),
+                line,
+            }),
+            _ => self.error("Expected ;"),
+        }
+    }
+
+    /// A type name, which is any identifier as far as parsing goes. `nil` is a keyword, but it's
+    /// also the name of its type.
+    fn annotation(&mut self) -> Option<Identifier> {
+        match self.consume() {
+            Some(Token {
+                token_type: TokenType::Identifier(name),
+                span,
+                line,
+                column,
+            }) => Some(Identifier {
+                name,
+                span,
+                line,
+                column,
+            }),
+            Some(Token {
+                token_type: TokenType::Nil,
+                span,
+                line,
+                column,
+            }) => Some(Identifier {
+                name: "nil".into(),
+                span,
+                line,
+                column,
+            }),
+            _ => self.error("Expected a type after :"),
+        }
+    }
+
+    fn print_statement(&mut self) -> Option<Decl> {
         let t = self.consume()?;
         if !matches!(
             t,
@@ -42,432 +265,323 @@ impl<'a> Parser<'a> {
                 "Expected print statement to be established by the previous level. This should never happen"
             );
         }
-        let mut expr = self.expression(0)?;
-        expr.push((OpCode::Print, self.tokens.peek()?.line));
-        let tok = self.consume()?;
-        match tok.token_type {
-            TokenType::Semicolon => Some(expr),
-            _ => {
-                println!("Expected ;");
-                None
-            }
+        let expr = self.expression(0)?;
+        match self.consume() {
+            Some(Token {
+                token_type: TokenType::Semicolon,
+                line,
+                ..
+            }) => Some(Decl {
+                kind: DeclKind::Stmt(Stmt::Print(expr)),
+                span: self.span_from(t.span.start),
+                line,
+            }),
+            _ => self.error("Expected ;"),
         }
     }
 
-    fn statement(&mut self) -> Option<Expr> {
+    fn statement(&mut self) -> Option<Decl> {
         match self.tokens.peek() {
             Some(Token {
                 token_type: TokenType::Print,
-                line: _,
+                ..
             }) => self.print_statement(),
-            Some(Token {
-                token_type: TokenType::Var,
-                line: _,
-            }) => todo!("Var statement"),
             _ => self.expression_statement(),
         }
     }
 
-    fn expression_statement(&mut self) -> Option<Expr> {
-        let mut expr = self.expression(0)?;
-        match self.consume() {
+    fn expression_statement(&mut self) -> Option<Decl> {
+        let expr = self.expression(0)?;
+        let line = match self.consume() {
             Some(Token {
                 token_type: TokenType::Semicolon,
                 line,
-            }) => {
-                expr.push((OpCode::Pop, line));
-                Some(expr)
-            }
-            _ => {
-                println!("Expected ;");
-                None
-            }
-        }
+                ..
+            }) => line,
+            None if self.repl => self.position.0,
+            _ => return self.error("Expected ;"),
+        };
+        Some(Decl {
+            span: self.span_from(expr.span.start),
+            kind: DeclKind::Stmt(Stmt::Expression(expr)),
+            line,
+        })
     }
 
-    pub fn parse(&mut self) -> Option<Expr> {
+    pub fn parse(&mut self) -> Option<Vec<Decl>> {
         let mut result = vec![];
         while self.not_eof() {
-            let mut expr = self.declaration()?;
-            result.append(&mut expr);
+            result.push(self.declaration()?);
         }
         Some(result)
     }
 
     pub fn expression(&mut self, precedence: i32) -> Option<Expr> {
-        let token = self.consume().or_else(|| {
-            println!("Unexpected end of input"); // TODO: rewrite with Result to avoid this println-s
-            None
-        })?;
+        let Some(token) = self.consume() else {
+            return self.error("Unexpected end of input");
+        };
         let mut left = prefix_parselets(token, self)?;
+        loop {
+            if self.next_is(TokenType::LeftParen) {
+                let line = self.consume()?.line;
+                left = self.call(left, line)?;
+            } else if self.next_is(TokenType::LeftBracket) {
+                let line = self.consume()?.line;
+                left = self.index(left, line)?;
+            } else if self.next_is(TokenType::Dot) {
+                let line = self.consume()?.line;
+                left = self.get(left, line)?;
+            } else {
+                break;
+            }
+        }
+        if self.next_is(TokenType::Equal) {
+            let equal = self.consume()?;
+            return match left.kind {
+                ExprKind::Variable(name) if precedence <= 1 => {
+                    let value = self.expression(precedence)?;
+                    Some(Expr {
+                        span: self.span_from(left.span.start),
+                        line: equal.line,
+                        kind: ExprKind::Assign {
+                            name,
+                            value: Box::new(value),
+                        },
+                    })
+                }
+                ExprKind::Index { object, index } if precedence <= 1 => {
+                    let value = self.expression(precedence)?;
+                    Some(Expr {
+                        span: self.span_from(left.span.start),
+                        line: equal.line,
+                        kind: ExprKind::SetIndex {
+                            object,
+                            index,
+                            value: Box::new(value),
+                        },
+                    })
+                }
+                _ => self.error("Invalid assignment target"),
+            };
+        }
         while precedence < self.peek_precedence() {
-            let token = self.consume().or_else(|| {
-                println!("Unexpected end of input");
-                None
-            })?;
-            let mut right = infix_parselets(token, self)?;
-            left.append(&mut right);
+            let Some(token) = self.consume() else {
+                return self.error("Unexpected end of input");
+            };
+            left = infix_parselets(left, token, self)?;
         }
         Some(left)
     }
 }
 
 fn prefix_parselets(tok: Token, parser: &mut Parser) -> Option<Expr> {
-    match tok.token_type {
-        TokenType::Number(n) => {
-            let expr = vec![(OpCode::Constant(Rc::new(Value::Number(n))), tok.line)];
-            Some(expr)
-        }
-        TokenType::Str(s) => {
-            let expr = vec![(
-                OpCode::Constant(Rc::new(Value::Obj(common::Obj::String(s)))),
-                tok.line,
-            )];
-            Some(expr)
-        }
-        TokenType::True => {
-            let expr = vec![(OpCode::Constant(Rc::new(Value::Boolean(true))), tok.line)];
-            Some(expr)
-        }
-        TokenType::False => {
-            let expr = vec![(OpCode::Constant(Rc::new(Value::Boolean(false))), tok.line)];
-            Some(expr)
-        }
-        TokenType::Nil => {
-            let expr = vec![(OpCode::Constant(Rc::new(Value::Nil)), tok.line)];
-            Some(expr)
-        }
-        TokenType::Bang => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Not, tok.line));
-            Some(expr)
+    let kind = match tok.token_type {
+        TokenType::Number(n) => ExprKind::Literal(Literal::Number(n)),
+        TokenType::Str(ref s) => ExprKind::Literal(Literal::Str(s.clone())),
+        TokenType::True => ExprKind::Literal(Literal::Bool(true)),
+        TokenType::False => ExprKind::Literal(Literal::Bool(false)),
+        TokenType::Nil => ExprKind::Literal(Literal::Nil),
+        TokenType::Identifier(ref name) => ExprKind::Variable(Identifier {
+            name: name.clone(),
+            span: tok.span,
+            line: tok.line,
+            column: tok.column,
+        }),
+        TokenType::Bang | TokenType::Plus | TokenType::Minus => {
+            let op = match tok.token_type {
+                TokenType::Bang => UnaryOp::Not,
+                TokenType::Plus => UnaryOp::Plus,
+                _ => UnaryOp::Negate,
+            };
+            let operand = parser.expression(tok.precedence())?;
+            ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            }
         }
-        TokenType::Plus => {
-            let expr = parser.expression(tok.precedence())?;
-            Some(expr)
+        TokenType::LeftBracket => {
+            let mut items = vec![];
+            if parser.next_is(TokenType::RightBracket) {
+                parser.consume();
+            } else {
+                loop {
+                    items.push(parser.expression(1)?);
+                    match parser.consume().map(|t| t.token_type) {
+                        Some(TokenType::Comma) => continue,
+                        Some(TokenType::RightBracket) => break,
+                        _ => return parser.error("Expected ] after list items"),
+                    }
+                }
+            }
+            ExprKind::List(items)
         }
-        TokenType::Minus => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Negate, tok.line));
-            Some(expr)
+        TokenType::LeftBrace => {
+            let mut entries = vec![];
+            if parser.next_is(TokenType::RightBrace) {
+                parser.consume();
+            } else {
+                loop {
+                    let key = parser.expression(1)?;
+                    if !parser.next_is(TokenType::Colon) {
+                        return parser.error("Expected : after map key");
+                    }
+                    parser.consume();
+                    entries.push((key, parser.expression(1)?));
+                    match parser.consume().map(|t| t.token_type) {
+                        Some(TokenType::Comma) => continue,
+                        Some(TokenType::RightBrace) => break,
+                        _ => return parser.error("Expected } after map entries"),
+                    }
+                }
+            }
+            ExprKind::Map(entries)
         }
         TokenType::LeftParen => {
             let expr = parser.expression(tok.precedence())?;
-            match parser.consume()?.token_type {
-                TokenType::RightParen => Some(expr),
-                _ => None,
+            match parser.consume() {
+                Some(Token {
+                    token_type: TokenType::RightParen,
+                    ..
+                }) => ExprKind::Grouping(Box::new(expr)),
+                _ => return parser.error("Expected )"),
             }
         }
-        _ => {
-            println!("Unexpected token: {:?}", tok);
-            None
-        }
-    }
+        _ => return parser.error(format!("Unexpected token {:?}", tok.token_type)),
+    };
+    Some(Expr {
+        kind,
+        span: parser.span_from(tok.span.start),
+        line: tok.line,
+    })
 }
 
-fn infix_parselets(tok: Token, parser: &mut Parser) -> Option<Expr> {
-    match tok.token_type {
-        TokenType::Plus => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Add, tok.line));
-            Some(expr)
-        }
-        TokenType::Minus => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Subtract, tok.line));
-            Some(expr)
-        }
-        TokenType::Star => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Multiply, tok.line));
-            Some(expr)
-        }
-        TokenType::Slash => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Divide, tok.line));
-            Some(expr)
-        }
-        TokenType::Greater => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Greater, tok.line));
-            Some(expr)
-        }
-        TokenType::Less => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Less, tok.line));
-            Some(expr)
-        }
-        TokenType::EqualEqual => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Equal, tok.line));
-            Some(expr)
-        }
-        TokenType::BangEqual => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Equal, tok.line));
-            expr.push((OpCode::Not, tok.line));
-            Some(expr)
-        }
-        TokenType::GreaterEqual => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Less, tok.line));
-            expr.push((OpCode::Not, tok.line));
-            Some(expr)
-        }
-        TokenType::LessEqual => {
-            let mut expr = parser.expression(tok.precedence())?;
-            expr.push((OpCode::Greater, tok.line));
-            expr.push((OpCode::Not, tok.line));
-            Some(expr)
-        }
-        _ => {
-            println!("Unexpected token: {:?}", tok);
-            None
-        }
-    }
+fn infix_parselets(left: Expr, tok: Token, parser: &mut Parser) -> Option<Expr> {
+    let op = match tok.token_type {
+        TokenType::Plus => BinaryOp::Add,
+        TokenType::Minus => BinaryOp::Subtract,
+        TokenType::Star => BinaryOp::Multiply,
+        TokenType::Slash => BinaryOp::Divide,
+        TokenType::Greater => BinaryOp::Greater,
+        TokenType::Less => BinaryOp::Less,
+        TokenType::EqualEqual => BinaryOp::Equal,
+        TokenType::BangEqual => BinaryOp::NotEqual,
+        TokenType::GreaterEqual => BinaryOp::GreaterEqual,
+        TokenType::LessEqual => BinaryOp::LessEqual,
+        _ => return parser.error(format!("Unexpected token {:?}", tok.token_type)),
+    };
+    let right = parser.expression(tok.precedence())?;
+    Some(Expr {
+        span: parser.span_from(left.span.start),
+        line: tok.line,
+        kind: ExprKind::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+    })
 }
 
-type Expr = Vec<(OpCode, i32)>;
-
 #[cfg(test)]
-mod test_expr {
+mod test_parse {
     use super::*;
     use crate::{compile::Source, tokens::Tokenizer};
 
-    #[test]
-    fn test_parse_number() {
-        let input = Source("42".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0).unwrap();
-        assert_eq!(
-            expr,
-            vec![(OpCode::Constant(Rc::new(Value::Number(42.0))), 0)]
-        );
-    }
-
-    #[test]
-    fn test_parse_greater() {
-        let input = Source(">".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
-        assert_eq!(expr, None);
+    fn dump(source: &str) -> Option<String> {
+        let input = Source(source.into());
+        let mut parser = Parser::new(Tokenizer::new(&input).peekable());
+        let program = parser.parse()?;
+        Some(
+            program
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
     }
 
     #[test]
-    fn test_x_gt_y() {
-        let input = Source("10 > 5".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
+    fn dumps_declarations_as_s_expressions() {
         assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(10.0))), 0),
-                (OpCode::Constant(Rc::new(Value::Number(5.0))), 0),
-                (OpCode::Greater, 0)
-            ])
+            dump("var a = 1; var b: string;\nprint -a + max(1, (2)) * 3;\na = b = !nil == \"x\";"),
+            Some(
+                "(var a 1)\n(var (: b string))\n(print (+ (- a) (* (call max 1 (group 2)) 3)))\n(expr (= a (= b (== (! nil) \"x\"))))"
+                    .into()
+            )
         );
     }
 
     #[test]
-    fn test_parse_less() {
-        let input = Source("<".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
-        assert_eq!(expr, None);
-    }
-
-    #[test]
-    fn test_x_lt_y() {
-        let input = Source("10 < 5".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
+    fn dumps_imports_members_lists_and_maps() {
         assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(10.0))), 0),
-                (OpCode::Constant(Rc::new(Value::Number(5.0))), 0),
-                (OpCode::Less, 0)
-            ])
+            dump("import \"lib/util.lox\";\nimport \"math.lox\" as m;\nprint m.max(m.pi, 1).x;"),
+            Some(
+                "(import \"lib/util.lox\")\n(import \"math.lox\" as m)\n(print (. (call (. m max) (. m pi) 1) x))"
+                    .into()
+            )
         );
-    }
-
-    #[test]
-    fn test_parse_greater_equal() {
-        let input = Source(">=".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
-        assert_eq!(expr, None);
-    }
-
-    #[test]
-    fn test_x_gte_y() {
-        let input = Source("10 >= 5".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
+        assert_eq!(dump("import util;"), None);
         assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(10.0))), 0),
-                (OpCode::Constant(Rc::new(Value::Number(5.0))), 0),
-                (OpCode::Less, 0),
-                (OpCode::Not, 0)
-            ])
+            dump("var a = [1, [], b[0]];\na[1] = a[-1][2] = a.len();"),
+            Some(
+                "(var a (list 1 (list) (index b 0)))\n(expr (= (index a 1) (= (index (index a (- 1)) 2) (call (. a len)))))"
+                    .into()
+            )
         );
-    }
-
-    #[test]
-    fn test_parse_less_equal() {
-        let input = Source("<=".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
-        assert_eq!(expr, None);
-    }
-
-    #[test]
-    fn test_x_lte_y() {
-        let input = Source("10 <= 5".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
         assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(10.0))), 0),
-                (OpCode::Constant(Rc::new(Value::Number(5.0))), 0),
-                (OpCode::Greater, 0),
-                (OpCode::Not, 0)
-            ])
+            dump("print {\"a\": [1], 2: {}}[\"a\"];"),
+            Some("(print (index (map (\"a\" (list 1)) (2 (map))) \"a\"))".into())
         );
+        assert_eq!(dump("[1, 2;"), None);
+        assert_eq!(dump("var m = {\"a\" 1};"), None);
+        assert_eq!(dump("import \"util.lox\" as;"), None);
     }
 
     #[test]
-    fn test_parse_equal_equal() {
-        let input = Source("==".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
-        assert_eq!(expr, None);
+    fn nodes_span_their_source() {
+        let source = "print 1;\nvar total = count +  max(2);";
+        let input = Source(source.into());
+        let mut parser = Parser::new(Tokenizer::new(&input).peekable());
+        let program = parser.parse().unwrap();
+        let text = |span: Span| &source[span.start..span.end];
+        assert_eq!(text(program[0].span), "print 1;");
+        assert_eq!(program[1].line, 2);
+        let DeclKind::Var {
+            name,
+            initializer: Some(initializer),
+            ..
+        } = &program[1].kind
+        else {
+            panic!("Expected a var declaration, got {:?}", program[1]);
+        };
+        assert_eq!(text(name.span), "total");
+        assert_eq!(text(initializer.span), "count +  max(2)");
+        let ExprKind::Binary { right, .. } = &initializer.kind else {
+            panic!("Expected a binary expression, got {:?}", initializer);
+        };
+        assert_eq!(text(right.span), "max(2)");
     }
 
     #[test]
-    fn test_x_eq_y() {
-        let input = Source("10 == 5".into());
+    fn invalid_assignment_target() {
+        let input = Source("1 = 2;".into());
         let tokenizer = Tokenizer::new(&input).peekable();
         let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
-        assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(10.0))), 0),
-                (OpCode::Constant(Rc::new(Value::Number(5.0))), 0),
-                (OpCode::Equal, 0)
-            ])
-        );
+        assert_eq!(parser.parse(), None);
     }
 
     #[test]
-    fn test_parse_bang_equal() {
-        let input = Source("!=".into());
+    fn errors_point_at_the_offending_token() {
+        let input = Source("var a = 1;\nprint a\n  + ;".into());
         let tokenizer = Tokenizer::new(&input).peekable();
         let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
-        assert_eq!(expr, None);
-    }
-
-    #[test]
-    fn test_x_neq_y() {
-        let input = Source("10 != 5".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0);
-        assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(10.0))), 0),
-                (OpCode::Constant(Rc::new(Value::Number(5.0))), 0),
-                (OpCode::Equal, 0),
-                (OpCode::Not, 0)
-            ])
-        );
-    }
-
-    #[test]
-    fn test_parse_string() {
-        let input = Source("\"hello world\"".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.expression(0).unwrap();
+        assert_eq!(parser.parse(), None);
         assert_eq!(
-            expr,
-            vec![(
-                OpCode::Constant(Rc::new(Value::Obj(common::Obj::String(
-                    "hello world".to_string()
-                )))),
-                0
-            )]
+            parser.errors,
+            vec![Diagnostic::error("Unexpected token Semicolon", 3, 4)]
         );
-    }
-}
-
-#[cfg(test)]
-mod test_parse {
-    use std::rc::Rc;
-
-    use super::*;
-    use crate::{compile::Source, tokens::Tokenizer};
-
-    #[test]
-    fn parse_print_statement() {
-        let input = Source("print 42;".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.parse();
-        assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(42.0))), 0),
-                (OpCode::Print, 0),
-            ])
-        );
-    }
-
-    #[test]
-    fn test_parse_number() {
-        let input = Source("42;".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.parse();
-        assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(42.0))), 0),
-                (OpCode::Pop, 0)
-            ])
-        );
-    }
-
-    #[test]
-    fn parse_print_with_paren() {
-        let input = Source("print (42 + 5);".into());
-        let tokenizer = Tokenizer::new(&input).peekable();
-        let mut parser = Parser::new(tokenizer);
-        let expr = parser.parse();
         assert_eq!(
-            expr,
-            Some(vec![
-                (OpCode::Constant(Rc::new(Value::Number(42.0))), 0),
-                (OpCode::Constant(Rc::new(Value::Number(5.0))), 0),
-                (OpCode::Add, 0),
-                (OpCode::Print, 0)
-            ])
+            parser.errors[0].to_string(),
+            "Error at line 3, Unexpected token Semicolon"
         );
     }
 }
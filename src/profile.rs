@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    common::{Obj, OpCode},
+    vm::VM,
+};
+
+const HOT_LINES: usize = 10;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct OpcodeStats {
+    count: u64,
+    time: Duration,
+}
+
+/// Collects where a script spends its instructions. The VM calls `enter` before every instruction
+/// it executes when profiling is on. An instruction's time runs until the next one starts, or until
+/// `finish`, so it includes the dispatch overhead of the loop itself.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    opcodes: HashMap<&'static str, OpcodeStats>,
+    /// Function name, then line, to instruction count.
+    lines: HashMap<String, HashMap<i32, u64>>,
+    functions: HashMap<String, u64>,
+    /// Call stacks in folded form (`outer;inner`) to instruction count.
+    stacks: HashMap<String, u64>,
+    running: Option<(&'static str, Instant)>,
+    total: u64,
+}
+
+fn count(map: &mut HashMap<String, u64>, key: &str) {
+    match map.get_mut(key) {
+        Some(count) => *count += 1,
+        None => {
+            map.insert(key.into(), 1);
+        }
+    }
+}
+
+// Calls to natives show up as their own frame on top of the caller.
+fn native_name(vm: &VM, arg_count: usize) -> Option<String> {
+    let slot = vm.stack.len().checked_sub(arg_count + 1)?;
+    match vm.heap.get(vm.stack[slot].as_obj()?) {
+        Obj::NativeFn(native) => Some(native.name.clone()),
+        Obj::BoundMethod(bound) => Some(bound.method.name.clone()),
+        _ => None,
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enter(&mut self, vm: &VM, instruction: &OpCode, line: i32) {
+        let now = Instant::now();
+        self.stop(now);
+        self.running = Some((instruction.name(), now));
+        self.total += 1;
+        self.opcodes.entry(instruction.name()).or_default().count += 1;
+
+        let function = vm.frames.last().map_or("", |frame| frame.chunk.name());
+        count(&mut self.functions, function);
+        if !self.lines.contains_key(function) {
+            self.lines.insert(function.into(), HashMap::new());
+        }
+        if let Some(lines) = self.lines.get_mut(function) {
+            *lines.entry(line).or_default() += 1;
+        }
+
+        let mut stack = vm
+            .frames
+            .iter()
+            .map(|frame| frame.chunk.name())
+            .collect::<Vec<_>>()
+            .join(";");
+        if let OpCode::Call(arg_count) = instruction {
+            if let Some(native) = native_name(vm, *arg_count) {
+                stack.push(';');
+                stack.push_str(&native);
+            }
+        }
+        count(&mut self.stacks, &stack);
+    }
+
+    /// Stops timing the last instruction. The VM calls this when it stops running.
+    pub fn finish(&mut self) {
+        self.stop(Instant::now());
+    }
+
+    fn stop(&mut self, now: Instant) {
+        if let Some((name, start)) = self.running.take() {
+            self.opcodes.entry(name).or_default().time += now - start;
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.total
+    }
+
+    pub fn opcode_count(&self, name: &str) -> u64 {
+        self.opcodes.get(name).map_or(0, |stats| stats.count)
+    }
+
+    pub fn line_count(&self, function: &str, line: i32) -> u64 {
+        self.lines
+            .get(function)
+            .and_then(|lines| lines.get(&line))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        count as f64 * 100.0 / self.total.max(1) as f64
+    }
+
+    /// Opcodes sorted by total time, the hottest lines, and instructions per function.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let total_time: Duration = self.opcodes.values().map(|stats| stats.time).sum();
+        let _ = writeln!(
+            report,
+            "=== Profile: {} instructions in {:.3}ms ===",
+            self.total,
+            total_time.as_secs_f64() * 1000.0
+        );
+
+        let _ = writeln!(
+            report,
+            "{:<14} {:>10} {:>7} {:>12} {:>9}",
+            "opcode", "count", "%", "time", "ns/op"
+        );
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|(a_name, a), (b_name, b)| b.time.cmp(&a.time).then(a_name.cmp(b_name)));
+        for (name, stats) in opcodes {
+            let _ = writeln!(
+                report,
+                "{:<14} {:>10} {:>6.1}% {:>10.3}ms {:>9.1}",
+                name,
+                stats.count,
+                self.percent(stats.count),
+                stats.time.as_secs_f64() * 1000.0,
+                stats.time.as_nanos() as f64 / stats.count.max(1) as f64
+            );
+        }
+
+        let _ = writeln!(report, "--- Hot lines ---");
+        let mut lines: Vec<_> = self
+            .lines
+            .iter()
+            .flat_map(|(function, lines)| {
+                lines
+                    .iter()
+                    .map(move |(line, count)| (function, *line, *count))
+            })
+            .collect();
+        lines.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(b.0)).then(a.1.cmp(&b.1)));
+        for (function, line, count) in lines.into_iter().take(HOT_LINES) {
+            let _ = writeln!(
+                report,
+                "{:<30} {:>10} {:>6.1}%",
+                format!("{}:{}", function, line),
+                count,
+                self.percent(count)
+            );
+        }
+
+        let _ = writeln!(report, "--- Functions ---");
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (function, count) in functions {
+            let _ = writeln!(
+                report,
+                "{:<30} {:>10} {:>6.1}%",
+                function,
+                count,
+                self.percent(*count)
+            );
+        }
+        report
+    }
+
+    /// Writes instruction counts per call stack in the folded format `flamegraph.pl` and inferno
+    /// read: one `outer;inner count` line per stack.
+    pub fn write_folded(&self, out: &mut impl io::Write) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(out, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_profile {
+    use super::*;
+    use crate::{
+        compile::Source,
+        vm::{InterpretMode, VM},
+    };
+
+    fn profile(source: &str) -> Profiler {
+        let mut vm = VM::new();
+        vm.output = Box::new(io::sink());
+        vm.profiler = Some(Profiler::new());
+        let chunk = Source(source.into())
+            .compile("test.lox", InterpretMode::Release, &mut vm.heap)
+            .unwrap();
+        vm.interpret(chunk, InterpretMode::Release);
+        vm.profiler.take().unwrap()
+    }
+
+    #[test]
+    fn counts_opcodes_and_lines() {
+        let profiler = profile("var a = 1;\na = a + 1;\na = a + 1;\nprint a;");
+        assert_eq!(profiler.instructions(), 15);
+        assert_eq!(profiler.opcode_count("Add"), 2);
+        assert_eq!(profiler.opcode_count("GetGlobal"), 3);
+        assert_eq!(profiler.line_count("test.lox", 2), 5);
+        // Including the final Return
+        assert_eq!(profiler.line_count("test.lox", 4), 3);
+        let report = profiler.report();
+        assert!(report.starts_with("=== Profile: 15 instructions"));
+        assert!(report.contains("test.lox:2"));
+    }
+
+    #[test]
+    fn natives_get_their_own_frame() {
+        let profiler = profile("clock(); clock();");
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "test.lox 5\ntest.lox;clock 2\n"
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use std::{io, path::PathBuf};
+
+use rlox::{
+    common::{Chunk, Disassembler},
+    compile::{Session, Source},
+    debug::globals,
+    parse::Parser,
+    tokens::{TokenType, Tokenizer, KEYWORDS},
+    vm::{InterpretMode, InterpretResult, VM},
+};
+
+use crate::line_editor::LineEditor;
+
+#[derive(Debug, PartialEq)]
+enum MetaCommand {
+    Dis(String),
+    Stack,
+    Globals,
+    Debug(bool),
+    Load(String),
+    Reset,
+    Help,
+}
+
+const HELP: &str = "\
+:dis <expr>      show the bytecode of an expression
+:stack           dump the VM stack
+:globals         list defined globals and their values
+:debug on|off    toggle debug mode
+:load <file>     run a file in the current session
+:reset           start a fresh session
+:help            show this message";
+
+impl MetaCommand {
+    fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+        match (command, argument) {
+            (":dis", "") => Err("Usage: :dis <expr>".into()),
+            (":dis", expr) => Ok(MetaCommand::Dis(expr.into())),
+            (":stack", "") => Ok(MetaCommand::Stack),
+            (":globals", "") => Ok(MetaCommand::Globals),
+            (":debug", "on") => Ok(MetaCommand::Debug(true)),
+            (":debug", "off") => Ok(MetaCommand::Debug(false)),
+            (":debug", _) => Err("Usage: :debug on|off".into()),
+            (":load", "") => Err("Usage: :load <file>".into()),
+            (":load", path) => Ok(MetaCommand::Load(path.into())),
+            (":reset", "") => Ok(MetaCommand::Reset),
+            (":help", "") => Ok(MetaCommand::Help),
+            _ => Err(format!("Unknown command {}, try :help", line)),
+        }
+    }
+
+    fn run(self, vm: &mut VM, mode: &mut InterpretMode, session: &mut Session) {
+        match self {
+            MetaCommand::Dis(expr) => {
+                // Only shown, so what it declares doesn't stay in scope
+                let chunk = Source(expr).compile_repl(
+                    "dis",
+                    InterpretMode::Release,
+                    &mut vm.heap,
+                    &mut session.clone(),
+                );
+                if let Some(chunk) = chunk {
+                    chunk.disassemble(&vm.heap);
+                }
+            }
+            MetaCommand::Stack => vm.stack.disassemble(&vm.heap),
+            MetaCommand::Globals => {
+                for (name, value) in globals(vm) {
+                    println!("{} = {}", name, value.print_lox(&vm.heap));
+                }
+            }
+            MetaCommand::Debug(on) => {
+                *mode = if on {
+                    InterpretMode::Debug
+                } else {
+                    InterpretMode::Release
+                };
+            }
+            MetaCommand::Load(path) => match std::fs::read_to_string(&path) {
+                Ok(source) => {
+                    match Source(source).compile_in(&path, *mode, &mut vm.heap, session) {
+                        Some(chunk) => interpret(vm, chunk, *mode, session),
+                        None => println!("Failed to compile"),
+                    }
+                }
+                Err(error) => println!("Can't read {}: {}", path, error),
+            },
+            MetaCommand::Reset => {
+                let stress = vm.heap.stress;
+                *vm = VM::new();
+                vm.heap.stress = stress;
+                *session = Session::new(vm, &session.include);
+            }
+            MetaCommand::Help => println!("{}", HELP),
+        }
+    }
+}
+
+/// Modules imported in the session are also looked for in `include`.
+pub fn repl(mut vm: VM, mut mode: InterpretMode, include: &[PathBuf]) {
+    let mut session = Session::new(&vm, include);
+    let mut editor = LineEditor::new();
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "> " } else { "... " };
+        match editor.read_line(prompt, &completions(&vm)) {
+            Ok(None) => {
+                println!("Bye!");
+                break;
+            }
+            Ok(Some(line)) if input.is_empty() && line.trim_start().starts_with(':') => {
+                match MetaCommand::parse(&line) {
+                    Ok(command) => command.run(&mut vm, &mut mode, &mut session),
+                    Err(message) => println!("{}", message),
+                }
+            }
+            Ok(Some(line)) => {
+                // An empty continuation line gives up on completing the input and shows the error
+                let give_up = !input.is_empty() && line.trim().is_empty();
+                input.push_str(&line);
+                input.push('\n');
+                if input.trim().is_empty() || (!give_up && is_incomplete(&input)) {
+                    continue;
+                }
+                let source = Source(std::mem::take(&mut input));
+                if let Some(chunk) = source.compile_repl("repl", mode, &mut vm.heap, &mut session) {
+                    interpret(&mut vm, chunk, mode, &session);
+                } else {
+                    println!("Failed to compile");
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => input.clear(),
+            Err(error) => {
+                println!("error: {}", error);
+                break;
+            }
+        };
+    }
+}
+
+// Runtime errors are reported and the session goes on. Any module the session compiled may run
+// later, so they all stay alive while the chunk runs.
+fn interpret(vm: &mut VM, chunk: Chunk, mode: InterpretMode, session: &Session) {
+    vm.roots = session.constants();
+    if let InterpretResult::RuntimeError(error) = vm.interpret(chunk, mode) {
+        eprintln!("{}", error);
+    }
+}
+
+/// Words tab can complete: keywords and the globals defined so far.
+fn completions(vm: &VM) -> Vec<String> {
+    KEYWORDS
+        .iter()
+        .map(|(keyword, _)| keyword.to_string())
+        .chain(globals(vm).into_iter().map(|(name, _)| name.to_string()))
+        .collect()
+}
+
+/// Input is incomplete when it has unclosed parens, brackets or braces, ends inside a string, or doesn't parse
+/// yet and isn't terminated by `;` or `}`.
+fn is_incomplete(input: &str) -> bool {
+    let source = Source(input.into());
+    let mut depth = 0;
+    let mut last = None;
+    for token in Tokenizer::new(&source) {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => depth -= 1,
+            TokenType::UnterminatedStr => return true,
+            _ => {}
+        }
+        last = Some(token.token_type);
+    }
+    if depth > 0 {
+        return true;
+    }
+    if depth < 0 || matches!(last, Some(TokenType::Semicolon | TokenType::RightBrace)) {
+        return false;
+    }
+    Parser::for_repl(Tokenizer::new(&source).peekable())
+        .parse()
+        .is_none()
+}
+
+#[cfg(test)]
+mod test_meta_commands {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn parses_commands_with_arguments() {
+        assert_eq!(
+            MetaCommand::parse(":dis 1 + 2"),
+            Ok(MetaCommand::Dis("1 + 2".into()))
+        );
+        assert_eq!(
+            MetaCommand::parse(":load  test.lox \n"),
+            Ok(MetaCommand::Load("test.lox".into()))
+        );
+        assert_eq!(
+            MetaCommand::parse(":debug on"),
+            Ok(MetaCommand::Debug(true))
+        );
+        assert_eq!(
+            MetaCommand::parse(":debug off"),
+            Ok(MetaCommand::Debug(false))
+        );
+    }
+
+    #[test]
+    fn parses_commands_without_arguments() {
+        assert_eq!(MetaCommand::parse(":stack"), Ok(MetaCommand::Stack));
+        assert_eq!(MetaCommand::parse(":globals"), Ok(MetaCommand::Globals));
+        assert_eq!(MetaCommand::parse(":reset"), Ok(MetaCommand::Reset));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(MetaCommand::parse(":debug maybe").is_err());
+        assert!(MetaCommand::parse(":dis").is_err());
+        assert!(MetaCommand::parse(":stack 1").is_err());
+        assert!(MetaCommand::parse(":frobnicate").is_err());
+    }
+
+    #[test]
+    fn debug_toggles_the_mode() {
+        let mut vm = VM::new();
+        let mut mode = InterpretMode::Release;
+        MetaCommand::Debug(true).run(&mut vm, &mut mode, &mut Session::default());
+        assert!(mode == InterpretMode::Debug);
+    }
+
+    #[test]
+    fn reset_clears_globals() {
+        let mut vm = VM::new();
+        let mut mode = InterpretMode::Release;
+        vm.set_global("a", 1.0);
+        MetaCommand::Reset.run(&mut vm, &mut mode, &mut Session::default());
+        assert!(vm.get_global::<f64>("a").is_err());
+    }
+
+    #[test]
+    fn imports_carry_over_between_lines() {
+        let dir = temp_dir("repl-imports");
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/m.lox"), "var pi = 3;\n").unwrap();
+        let main = dir.join("main.lox");
+        std::fs::write(&main, "import \"m.lox\";\nvar loaded = pi;\n").unwrap();
+        let mut vm = VM::new();
+        let mut mode = InterpretMode::Release;
+        let mut session = Session::new(&vm, &[dir.join("lib")]);
+        for line in [
+            "import \"m.lox\";",
+            "import \"m.lox\" as m;",
+            "var x = pi + m.pi;",
+        ] {
+            let chunk = Source(line.into()).compile_repl("repl", mode, &mut vm.heap, &mut session);
+            interpret(&mut vm, chunk.unwrap(), mode, &session);
+        }
+        assert_eq!(vm.get_global("x"), Ok(6.0));
+        // Files run with :load look in the include directories too
+        let load = MetaCommand::Load(main.to_string_lossy().into());
+        load.run(&mut vm, &mut mode, &mut session);
+        assert_eq!(vm.get_global("loaded"), Ok(3.0));
+        // The module's own global is stored under its path, which isn't offered
+        let completions = completions(&vm);
+        assert!(completions.contains(&"loaded".to_string()));
+        assert!(!completions.iter().any(|name| name.contains("::")));
+    }
+
+    #[test]
+    fn modules_from_lines_that_failed_survive_collections() {
+        let dir = temp_dir("repl-failed-import");
+        std::fs::write(dir.join("m.lox"), "var s = \"hello\";\n").unwrap();
+        let mut vm = VM::new();
+        vm.heap.stress = true;
+        let mut session = Session::new(&vm, &[dir]);
+        for line in [
+            // The module is compiled, then the line fails to resolve and never runs
+            "import \"m.lox\" as m; print m.nope;",
+            "var x = \"a\" + \"b\";",
+            "import \"m.lox\";",
+            "var t = s;",
+        ] {
+            let chunk = Source(line.into()).compile_repl(
+                "repl",
+                InterpretMode::Release,
+                &mut vm.heap,
+                &mut session,
+            );
+            if let Some(chunk) = chunk {
+                interpret(&mut vm, chunk, InterpretMode::Release, &session);
+            }
+        }
+        assert_eq!(vm.get_global("t"), Ok(String::from("hello")));
+    }
+}
+
+#[cfg(test)]
+mod test_incomplete {
+    use super::*;
+
+    #[test]
+    fn complete_statements() {
+        assert!(!is_incomplete("print 1;"));
+        assert!(!is_incomplete("1 + 2"));
+        assert!(!is_incomplete("var a = 1;\n"));
+    }
+
+    #[test]
+    fn unbalanced_parens() {
+        assert!(is_incomplete("print (1 +"));
+        assert!(is_incomplete("clock(\n"));
+        assert!(!is_incomplete("print 1);"));
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        assert!(is_incomplete("var a = [1,\n"));
+        assert!(is_incomplete("var a = [1, [2];"));
+        assert!(!is_incomplete("print 1];"));
+    }
+
+    #[test]
+    fn unbalanced_braces() {
+        assert!(is_incomplete("{"));
+    }
+
+    #[test]
+    fn unterminated_string() {
+        assert!(is_incomplete("print \"hello\n"));
+        assert!(!is_incomplete("print \"hello\nworld\";"));
+    }
+
+    #[test]
+    fn missing_semicolon() {
+        assert!(is_incomplete("var a = 1"));
+        assert!(is_incomplete("print 1\n"));
+        assert!(is_incomplete("1 +"));
+    }
+
+    #[test]
+    fn broken_statement_is_complete() {
+        assert!(!is_incomplete("var = 1;"));
+    }
+}
@@ -0,0 +1,525 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{Decl, DeclKind, Expr, ExprKind, Identifier, Stmt},
+    common::Chunk,
+    diagnostic::Diagnostic,
+    tokens::Span,
+};
+
+/// Where a name lives at runtime. Without blocks or functions every name is a global, so this is
+/// where locals and upvalues will go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    /// The global's name at runtime. The globals a module declares are prefixed with its path, so
+    /// they don't clash with the globals of other files.
+    Global(String),
+}
+
+/// A compiled module, as the files importing it see it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub chunk: Rc<Chunk>,
+    /// Every global the module declares, with its name at runtime.
+    pub exports: HashMap<String, String>,
+}
+
+/// What a file has declared and imported, the names in scope besides the predefined ones. The REPL
+/// keeps one across lines, so what a line declares or imports is still there on the next.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scope {
+    /// Globals declared anywhere in the file. Whether they're declared before they're used is up
+    /// to the order things run in, which isn't known until there's control flow.
+    declared: HashSet<String>,
+    /// Names brought into scope by plain imports, with their names at runtime.
+    imported: HashMap<String, String>,
+    /// Names bound by `import ... as`. `None` for a module that wasn't loaded.
+    aliases: HashMap<String, Option<Module>>,
+    /// Set when the file imports a module that wasn't loaded, which could define any name.
+    open: bool,
+}
+
+impl Scope {
+    fn contains(&self, name: &str) -> bool {
+        self.declared.contains(name)
+            || self.imported.contains_key(name)
+            || self.aliases.contains_key(name)
+    }
+}
+
+pub struct Resolution {
+    /// The binding of every name in the program, keyed by the span of the identifier.
+    pub bindings: HashMap<Span, Binding>,
+    /// The chunk each import runs, keyed by the span of the import.
+    pub imports: HashMap<Span, Rc<Chunk>>,
+    /// The globals the program declares, with their names at runtime.
+    pub exports: HashMap<String, String>,
+    /// In the order they appear in the source. Errors are names that can't be resolved at all,
+    /// like a member a module doesn't have. Warnings are names that most likely won't work the way
+    /// they're written: globals defined nowhere, or used before their declaration runs.
+    pub diagnostics: Vec<Diagnostic>,
+    /// What's in scope after the program, for the REPL to carry over to the next line.
+    pub scope: Scope,
+}
+
+/// Binds names to where they live and reports the ones that can't work. Warnings that need blocks,
+/// functions or classes, like unused or redeclared locals, `return` at the top level or `this`
+/// outside a method, wait for the language to have them. Redeclaring a global is fine, the REPL
+/// and `:load` do it all the time.
+#[derive(Default)]
+pub struct Resolver {
+    bindings: HashMap<Span, Binding>,
+    imports: HashMap<Span, Rc<Chunk>>,
+    /// Globals that exist before the file runs, like natives.
+    predefined: HashSet<String>,
+    scope: Scope,
+    /// The globals declared before the file, by earlier lines of the REPL.
+    earlier: HashSet<String>,
+    /// The globals the file has declared so far.
+    defined: HashSet<String>,
+    /// The line of the first declaration of every global the file declares.
+    declared_at: HashMap<String, i32>,
+    /// The global whose initializer is being resolved.
+    initializing: Option<String>,
+    used: Vec<Identifier>,
+    /// What the globals the file declares are prefixed with. `None` for the file being run, whose
+    /// globals keep their names and share the namespace of the host.
+    prefix: Option<String>,
+    /// The modules the file imports, by the path they're imported as.
+    modules: HashMap<String, Module>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    pub fn new<'a>(predefined: impl IntoIterator<Item = &'a str>) -> Self {
+        Resolver {
+            predefined: predefined.into_iter().map(String::from).collect(),
+            ..Resolver::default()
+        }
+    }
+
+    /// A resolver for a file whose globals are prefixed with `prefix`, whose imports are
+    /// `modules`, and that starts out with `scope` in scope. Imports that aren't in `modules` may
+    /// define anything.
+    pub fn for_module(
+        prefix: Option<&str>,
+        modules: HashMap<String, Module>,
+        predefined: &HashSet<String>,
+        scope: Scope,
+    ) -> Self {
+        Resolver {
+            predefined: predefined.clone(),
+            earlier: scope.declared.clone(),
+            scope,
+            prefix: prefix.map(String::from),
+            modules,
+            ..Resolver::default()
+        }
+    }
+
+    fn error(&mut self, name: &Identifier, message: String) {
+        let error = Diagnostic::error(message, name.line, name.column);
+        self.diagnostics.push(error);
+    }
+
+    fn warning(&mut self, name: &Identifier, message: String) {
+        let warning = Diagnostic::warning(message, name.line, name.column);
+        self.diagnostics.push(warning);
+    }
+
+    /// Whether `name` already has a value when the declaration being resolved runs, as far as can
+    /// be told without control flow.
+    fn exists(&self, name: &str) -> bool {
+        self.scope.open
+            || self.defined.contains(name)
+            || self.earlier.contains(name)
+            || self.scope.imported.contains_key(name)
+            || self.predefined.contains(name)
+    }
+
+    /// Looks at what the program declares and imports before anything is bound, since a name
+    /// declared further down still refers to the file's own global.
+    pub fn declare(&mut self, program: &[Decl]) {
+        for decl in program {
+            match &decl.kind {
+                DeclKind::Var { name, .. } => {
+                    self.scope.declared.insert(name.name.clone());
+                    self.declared_at
+                        .entry(name.name.clone())
+                        .or_insert(name.line);
+                }
+                DeclKind::Import { path, alias } => {
+                    let module = self.modules.get(path).cloned();
+                    if module.is_none() {
+                        self.scope.open = true;
+                    }
+                    match (alias, module) {
+                        (Some(alias), module) => {
+                            self.scope.aliases.insert(alias.name.clone(), module);
+                        }
+                        (None, Some(module)) => self.scope.imported.extend(module.exports),
+                        (None, None) => {}
+                    }
+                }
+                DeclKind::Stmt(_) => {}
+            }
+        }
+    }
+
+    /// The name a global has at runtime. Names the file neither declares nor imports are the
+    /// host's, like natives.
+    fn runtime_name(&self, name: &str) -> String {
+        match (&self.prefix, self.scope.imported.get(name)) {
+            (Some(prefix), _) if self.scope.declared.contains(name) => {
+                format!("{}::{}", prefix, name)
+            }
+            (_, Some(imported)) if !self.scope.declared.contains(name) => imported.clone(),
+            _ => name.to_string(),
+        }
+    }
+
+    fn bind(&mut self, name: &Identifier) {
+        let binding = Binding::Global(self.runtime_name(&name.name));
+        self.bindings.insert(name.span, binding);
+    }
+
+    fn use_global(&mut self, name: &Identifier) {
+        if self.scope.aliases.contains_key(&name.name) && !self.scope.declared.contains(&name.name)
+        {
+            let message = format!("'{}' is a module, use one of its members", name.name);
+            self.error(name, message);
+        }
+        // Without control flow, a global the file declares further down is certain not to exist yet
+        if let Some(line) = self.declared_at.get(&name.name).copied() {
+            if !self.exists(&name.name) {
+                let message = if self.initializing.as_ref() == Some(&name.name) {
+                    format!("'{}' is read in its own initializer", name.name)
+                } else {
+                    format!(
+                        "'{}' is used before its declaration on line {}",
+                        name.name, line
+                    )
+                };
+                self.warning(name, message);
+            }
+        }
+        self.bind(name);
+        self.used.push(name.clone());
+    }
+
+    pub fn declaration(&mut self, decl: &Decl) {
+        match &decl.kind {
+            DeclKind::Var {
+                name, initializer, ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.initializing = Some(name.name.clone());
+                    self.expression(initializer);
+                    self.initializing = None;
+                }
+                self.bind(name);
+                self.scope.declared.insert(name.name.clone());
+                self.defined.insert(name.name.clone());
+            }
+            DeclKind::Import { path, .. } => {
+                if let Some(module) = self.modules.get(path) {
+                    self.imports.insert(decl.span, Rc::clone(&module.chunk));
+                }
+            }
+            DeclKind::Stmt(Stmt::Print(expr) | Stmt::Expression(expr)) => self.expression(expr),
+        }
+    }
+
+    pub fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::Variable(name) => self.use_global(name),
+            ExprKind::Assign { name, value } => {
+                self.expression(value);
+                self.use_global(name);
+            }
+            ExprKind::Unary { operand, .. } => self.expression(operand),
+            ExprKind::Binary { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            ExprKind::Get { object, name } => self.member(object, name),
+        }
+    }
+
+    fn member(&mut self, object: &Expr, name: &Identifier) {
+        let alias = match &object.kind {
+            ExprKind::Variable(alias) if !self.scope.declared.contains(&alias.name) => self
+                .scope
+                .aliases
+                .get(&alias.name)
+                .map(|module| (alias, module)),
+            _ => None,
+        };
+        // Anything else is a method, looked up when the code runs
+        let Some((alias, module)) = alias else {
+            return self.expression(object);
+        };
+        // A module that wasn't loaded can't say what it exports
+        let Some(module) = module else {
+            return;
+        };
+        match module.exports.get(&name.name) {
+            Some(global) => {
+                let binding = Binding::Global(global.clone());
+                self.bindings.insert(name.span, binding);
+            }
+            None => {
+                let message = format!("Module '{}' has no '{}'", alias.name, name.name);
+                self.error(name, message);
+            }
+        }
+    }
+
+    /// Names that are defined nowhere only get a warning in the file being run, since the host
+    /// can still define them before it runs. A module can't see the host's globals besides the
+    /// predefined ones, so there they're an error.
+    pub fn finish(mut self) -> Resolution {
+        let undefined: Vec<_> = self
+            .used
+            .iter()
+            .filter(|name| {
+                !self.scope.open
+                    && !self.scope.contains(&name.name)
+                    && !self.predefined.contains(&name.name)
+            })
+            .cloned()
+            .collect();
+        for name in undefined {
+            if self.prefix.is_some() {
+                let message = format!("'{}' isn't declared or imported by this module", name.name);
+                self.error(&name, message);
+            } else {
+                let message = format!("'{}' is never defined", name.name);
+                self.warning(&name, message);
+            }
+        }
+        self.diagnostics
+            .sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        let exports = self
+            .scope
+            .declared
+            .iter()
+            .map(|name| (name.clone(), self.runtime_name(name)))
+            .collect();
+        Resolution {
+            bindings: self.bindings,
+            imports: self.imports,
+            exports,
+            diagnostics: self.diagnostics,
+            scope: self.scope,
+        }
+    }
+}
+
+/// Works out where each name in `program` lives, for the code generator, and warns about names
+/// that are used in ways that can't work. `predefined` are the globals that exist before the program runs.
+/// Whatever the program imports is unknown, so names it uses may come from there.
+pub fn resolve(program: &[Decl], predefined: &[&str]) -> Resolution {
+    let mut resolver = Resolver::new(predefined.iter().copied());
+    resolve_with(&mut resolver, program);
+    resolver.finish()
+}
+
+/// Same as `resolve`, for a file whose imports are already compiled into `modules`. The globals
+/// it declares are prefixed with `prefix`, unless it's the file being run. `scope` is what's
+/// already in scope, which is nothing unless it's a line typed into the REPL.
+pub fn resolve_module(
+    program: &[Decl],
+    prefix: Option<&str>,
+    modules: HashMap<String, Module>,
+    predefined: &HashSet<String>,
+    scope: Scope,
+) -> Resolution {
+    let mut resolver = Resolver::for_module(prefix, modules, predefined, scope);
+    resolve_with(&mut resolver, program);
+    resolver.finish()
+}
+
+fn resolve_with(resolver: &mut Resolver, program: &[Decl]) {
+    resolver.declare(program);
+    for decl in program {
+        resolver.declaration(decl);
+    }
+}
+
+#[cfg(test)]
+mod test_resolve {
+    use super::*;
+    use crate::{compile::Source, parse::Parser, tokens::Tokenizer};
+
+    fn parsed(source: &str) -> Vec<Decl> {
+        let input = Source(source.into());
+        Parser::new(Tokenizer::new(&input).peekable())
+            .parse()
+            .unwrap()
+    }
+
+    fn resolved(source: &str, predefined: &[&str]) -> Resolution {
+        resolve(&parsed(source), predefined)
+    }
+
+    #[test]
+    fn binds_every_name() {
+        let source = "var a = b; a = clock();";
+        let mut names: Vec<_> = resolved(source, &[])
+            .bindings
+            .into_iter()
+            .map(|(span, binding)| (&source[span.start..span.end], binding))
+            .collect();
+        names.sort_by_key(|(name, _)| *name);
+        assert_eq!(
+            names,
+            vec![
+                ("a", Binding::Global("a".into())),
+                ("a", Binding::Global("a".into())),
+                ("b", Binding::Global("b".into())),
+                ("clock", Binding::Global("clock".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_globals_defined_nowhere() {
+        let source = "print later;\nvar later = clock();\nmissing = 1;\nprint later + missing;";
+        assert_eq!(
+            resolved(source, &["clock"]).diagnostics,
+            vec![
+                Diagnostic::warning("'later' is used before its declaration on line 2", 1, 6),
+                Diagnostic::warning("'missing' is never defined", 3, 0),
+                Diagnostic::warning("'missing' is never defined", 4, 14),
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_globals_used_before_their_declaration() {
+        let source =
+            "var a = a + 1;\nvar b = 1;\nvar b = b + 1;\nvar clock = clock;\nc = 1;\nvar c = c;";
+        let warnings: Vec<_> = resolved(source, &["clock"])
+            .diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "Warning at line 1, 'a' is read in its own initializer",
+                "Warning at line 5, 'c' is used before its declaration on line 6",
+                "Warning at line 6, 'c' is read in its own initializer",
+            ]
+        );
+    }
+
+    #[test]
+    fn redeclaring_what_earlier_repl_lines_declared_is_fine() {
+        let predefined = HashSet::new();
+        let first = resolve_module(
+            &parsed("var a = 1;"),
+            None,
+            HashMap::new(),
+            &predefined,
+            Scope::default(),
+        );
+        let second = resolve_module(
+            &parsed("var a = a + 1;"),
+            None,
+            HashMap::new(),
+            &predefined,
+            first.scope,
+        );
+        assert_eq!(second.diagnostics, vec![]);
+    }
+
+    #[test]
+    fn prefixes_module_globals_and_binds_imports() {
+        let exports = HashMap::from([("pi".to_string(), "/lib/math.lox::pi".to_string())]);
+        let module = Module {
+            chunk: Rc::new(Chunk::new("/lib/math.lox")),
+            exports,
+        };
+        let modules = HashMap::from([("math.lox".to_string(), module)]);
+        let source = "import \"math.lox\";\nimport \"math.lox\" as m;\nvar r = pi * m.pi;\nprint m.e + m + clock();\nprint r.len();";
+        let predefined = HashSet::from(["clock".to_string()]);
+        let resolution = resolve_module(
+            &parsed(source),
+            Some("/app/circle.lox"),
+            modules,
+            &predefined,
+            Scope::default(),
+        );
+        let mut names: Vec<_> = resolution
+            .bindings
+            .into_iter()
+            .map(|(span, Binding::Global(global))| (&source[span.start..span.end], global))
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                ("clock", "clock".into()),
+                ("m", "m".into()),
+                ("pi", "/lib/math.lox::pi".into()),
+                ("pi", "/lib/math.lox::pi".into()),
+                ("r", "/app/circle.lox::r".into()),
+                ("r", "/app/circle.lox::r".into()),
+            ]
+        );
+        assert_eq!(resolution.imports.len(), 2);
+        assert_eq!(
+            resolution.exports,
+            HashMap::from([("r".to_string(), "/app/circle.lox::r".to_string())])
+        );
+        let errors: Vec<_> = resolution
+            .diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Error at line 4, Module 'm' has no 'e'",
+                "Error at line 4, 'm' is a module, use one of its members",
+            ]
+        );
+    }
+}
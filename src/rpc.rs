@@ -0,0 +1,66 @@
+use std::io::{self, BufRead, Write};
+
+use rlox::json::Json;
+
+fn invalid_data(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Reads one message framed by a `Content-Length` header, or `None` once the input ends. The debug
+/// adapter and the language server both frame their messages this way.
+pub fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        match header.trim().split_once(':') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("Content-Length") => {
+                length = Some(value.trim().parse::<usize>().map_err(invalid_data)?);
+            }
+            // The blank line between the headers and the body
+            None if header.trim().is_empty() && length.is_some() => break,
+            _ => {}
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(invalid_data)?;
+    Json::parse(&body).map(Some).map_err(invalid_data)
+}
+
+pub fn write_message(out: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod test_rpc {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn frames_messages_with_content_length() {
+        let mut input = Cursor::new(
+            "Content-Length: 9\r\n\r\n{\"a\":[1]}content-length: 4\r\n\r\nnull".as_bytes(),
+        );
+        let first = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(first.get("a"), Some(&Json::from(vec![1usize])));
+        assert_eq!(read_message(&mut input).unwrap(), Some(Json::Null));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn messages_survive_a_round_trip() {
+        let message = Json::object([("id", Json::from(1)), ("method", Json::from("exit"))]);
+        let mut out = vec![];
+        write_message(&mut out, &message).unwrap();
+        write_message(&mut out, &Json::Null).unwrap();
+        let mut input = Cursor::new(out);
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+        assert_eq!(read_message(&mut input).unwrap(), Some(Json::Null));
+    }
+}
@@ -0,0 +1,256 @@
+use std::mem;
+
+use crate::common::{Value, ValueKind};
+
+const EMPTY: u32 = u32::MAX;
+const TOMBSTONE: u32 = u32::MAX - 1;
+const MIN_CAPACITY: usize = 8;
+
+/// A hash table keyed by Lox values that remembers the order keys were first inserted in. It holds
+/// the globals and backs maps.
+///
+/// Keys are equal when Lox `==` says they are: numbers by value, so `0` and `-0` are the same key,
+/// and objects by handle, which for strings means by contents since they're interned. NaN isn't
+/// equal to itself, so a NaN key could never be found again; maps refuse it.
+///
+/// Entries are kept in a dense vector, in order, and the open-addressed index only holds positions
+/// into it, probed linearly. Removing an entry leaves a hole in the vector and a tombstone in the
+/// index, and both are cleaned up the next time the table is rebuilt.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Table {
+    entries: Vec<Option<Entry>>,
+    /// Positions in `entries`, `EMPTY` or `TOMBSTONE`. Its length is a power of two, or 0.
+    index: Vec<u32>,
+    len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    hash: u32,
+    key: Value,
+    value: Value,
+}
+
+/// Where a key is in the index, or where it would go.
+enum Slot {
+    Occupied { slot: usize, entry: usize },
+    Vacant(usize),
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        match self.find(key, hash(key)) {
+            Slot::Occupied { entry, .. } => self.entries[entry].as_ref().map(|entry| &entry.value),
+            Slot::Vacant(_) => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &Value) -> Option<&mut Value> {
+        match self.find(key, hash(key)) {
+            Slot::Occupied { entry, .. } => {
+                self.entries[entry].as_mut().map(|entry| &mut entry.value)
+            }
+            Slot::Vacant(_) => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Sets `key` to `value` and returns what it was before. A key that's already there keeps its
+    /// place in the order.
+    pub fn insert(&mut self, key: Value, value: Value) -> Option<Value> {
+        // The index always keeps at least a quarter of its slots empty, so probing ends
+        if (self.entries.len() + 1) * 4 > self.index.len() * 3 {
+            self.rebuild();
+        }
+        let hash = hash(&key);
+        match self.find(&key, hash) {
+            Slot::Occupied { entry, .. } => self.entries[entry]
+                .as_mut()
+                .map(|entry| mem::replace(&mut entry.value, value)),
+            Slot::Vacant(slot) => {
+                self.index[slot] = self.entries.len() as u32;
+                self.entries.push(Some(Entry { hash, key, value }));
+                self.len += 1;
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        match self.find(key, hash(key)) {
+            Slot::Occupied { slot, entry } => {
+                self.index[slot] = TOMBSTONE;
+                self.len -= 1;
+                self.entries[entry].take().map(|entry| entry.value)
+            }
+            Slot::Vacant(_) => None,
+        }
+    }
+
+    /// The entries in the order their keys were first inserted.
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|entry| (&entry.key, &entry.value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Approximate number of bytes the table holds on to besides itself.
+    pub fn allocated_bytes(&self) -> usize {
+        self.entries.capacity() * mem::size_of::<Option<Entry>>()
+            + self.index.capacity() * mem::size_of::<u32>()
+    }
+
+    fn find(&self, key: &Value, hash: u32) -> Slot {
+        if self.index.is_empty() {
+            return Slot::Vacant(0);
+        }
+        let mask = self.index.len() - 1;
+        let mut slot = hash as usize & mask;
+        let mut tombstone = None;
+        loop {
+            match self.index[slot] {
+                EMPTY => return Slot::Vacant(tombstone.unwrap_or(slot)),
+                TOMBSTONE => {
+                    tombstone.get_or_insert(slot);
+                }
+                entry => {
+                    let found = self.entries[entry as usize]
+                        .as_ref()
+                        .is_some_and(|entry| entry.hash == hash && entry.key == *key);
+                    if found {
+                        return Slot::Occupied {
+                            slot,
+                            entry: entry as usize,
+                        };
+                    }
+                }
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    // Drops the holes removals left and sizes the index for twice the entries that are left
+    fn rebuild(&mut self) {
+        let capacity = ((self.len + 1) * 2).next_power_of_two().max(MIN_CAPACITY);
+        self.entries.retain(Option::is_some);
+        self.index = vec![EMPTY; capacity];
+        let mask = capacity - 1;
+        for (position, entry) in self.entries.iter().flatten().enumerate() {
+            let mut slot = entry.hash as usize & mask;
+            while self.index[slot] != EMPTY {
+                slot = (slot + 1) & mask;
+            }
+            self.index[slot] = position as u32;
+        }
+    }
+}
+
+// FNV-1a over a tag for the kind and the bits of the value. The `0.0` pattern matches `-0` as well,
+// so both zeros hash the same, since `-0 == 0`.
+fn hash(key: &Value) -> u32 {
+    let (tag, bits): (u8, u64) = match key.kind() {
+        ValueKind::Number(0.0) => (0, 0),
+        ValueKind::Number(n) => (0, n.to_bits()),
+        ValueKind::Boolean(b) => (1, b as u64),
+        ValueKind::Nil => (2, 0),
+        ValueKind::Obj(obj) => (3, obj.index() as u64),
+    };
+    let mut hash: u32 = 2166136261;
+    for byte in [tag].into_iter().chain(bits.to_le_bytes()) {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test_table {
+    use super::*;
+    use crate::gc::Heap;
+
+    fn number(n: f64) -> Value {
+        Value::number(n)
+    }
+
+    #[test]
+    fn keeps_insertion_order() {
+        let mut table = Table::new();
+        for n in [3.0, 1.0, 2.0] {
+            table.insert(number(n), number(n * 10.0));
+        }
+        table.insert(number(1.0), number(0.0));
+        let entries: Vec<_> = table.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(
+            entries,
+            [
+                (number(3.0), number(30.0)),
+                (number(1.0), number(0.0)),
+                (number(2.0), number(20.0))
+            ]
+        );
+        assert_eq!(table.remove(&number(3.0)), Some(number(30.0)));
+        table.insert(number(3.0), number(30.0));
+        let keys: Vec<_> = table.keys().copied().collect();
+        assert_eq!(keys, [number(1.0), number(2.0), number(3.0)]);
+    }
+
+    #[test]
+    fn keys_are_equal_when_lox_says_so() {
+        let mut heap = Heap::new();
+        let mut table = Table::new();
+        table.insert(number(0.0), Value::boolean(true));
+        table.insert(Value::obj(heap.intern("a")), Value::NIL);
+        table.insert(Value::NIL, number(1.0));
+        table.insert(Value::boolean(false), number(2.0));
+        assert_eq!(table.get(&number(-0.0)), Some(&Value::boolean(true)));
+        assert!(table.contains_key(&Value::obj(heap.intern("a"))));
+        assert_eq!(table.get(&Value::NIL), Some(&number(1.0)));
+        assert_eq!(table.get(&Value::boolean(false)), Some(&number(2.0)));
+        // Kinds are never equal to each other
+        assert_eq!(table.get(&Value::boolean(true)), None);
+        assert_eq!(table.len(), 4);
+    }
+
+    #[test]
+    fn grows_and_survives_removals() {
+        let mut table = Table::new();
+        for n in 0..1000 {
+            table.insert(number(n as f64), number(n as f64));
+        }
+        for n in (0..1000).step_by(2) {
+            assert_eq!(table.remove(&number(n as f64)), Some(number(n as f64)));
+        }
+        assert_eq!(table.remove(&number(0.0)), None);
+        for n in 1000..1500 {
+            table.insert(number(n as f64), number(n as f64));
+        }
+        assert_eq!(table.len(), 1000);
+        assert!((0..1000).all(|n| table.contains_key(&number(n as f64)) == (n % 2 == 1)));
+        assert!((1000..1500).all(|n| table.get(&number(n as f64)) == Some(&number(n as f64))));
+        assert_eq!(table.keys().next(), Some(&number(1.0)));
+    }
+}
@@ -0,0 +1,55 @@
+//! Helpers for tests. Both the library and the `rlox` binary include this file, and each uses only
+//! some of it.
+#![allow(dead_code)]
+
+use std::{
+    cell::RefCell,
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    process,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Output a test can read back after handing a clone to the VM or a server.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// Everything written since the last call.
+    pub fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+
+    pub fn take_string(&self) -> String {
+        String::from_utf8(self.take()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A new directory for one test. The process id and a counter keep tests that run at the same
+/// time, in this process or another, out of each other's way.
+pub fn temp_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let unique = NEXT.fetch_add(1, Ordering::Relaxed);
+    let dir = env::temp_dir().join(format!("rlox-{}-{}-{}", name, process::id(), unique));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes `source` to `name.lox` in a directory of its own, and returns the file's path.
+pub fn script(name: &str, source: &str) -> PathBuf {
+    let path = temp_dir(name).join(format!("{}.lox", name));
+    fs::write(&path, source).unwrap();
+    path
+}
@@ -2,14 +2,17 @@ use std::{iter::Peekable, str::Chars};
 
 use crate::compile::Source;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -37,6 +40,7 @@ pub enum TokenType {
     For,
     Fun,
     If,
+    Import,
     Nil,
     Or,
     Print,
@@ -47,13 +51,60 @@ pub enum TokenType {
     Var,
     While,
 
+    UnterminatedStr,
     Error,
 }
 
+pub const KEYWORDS: &[(&str, TokenType)] = &[
+    ("and", TokenType::And),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("import", TokenType::Import),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("true", TokenType::True),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
+/// Byte offsets of a piece of source, `start..end`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The line and column the span starts at in `source`, counted like tokens do.
+    pub fn position(self, source: &str) -> (i32, i32) {
+        let before = &source[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count();
+        (line as i32, column as i32)
+    }
+}
+
 #[derive(Debug)]
 pub struct Token {
     pub token_type: TokenType,
     pub line: i32,
+    /// Counted in characters from the start of the line, from 0. Lines count from 1, the way
+    /// editors and every diagnostic show them. Both are where the token starts.
+    pub column: i32,
+    pub span: Span,
 }
 
 impl Token {
@@ -72,11 +123,7 @@ impl Token {
             TokenType::Nil | TokenType::True | TokenType::False => 2,
             TokenType::Identifier(_) => 2,
             TokenType::LeftParen | TokenType::RightParen => 1, // Parentheses to control precedence explicitly.
-            TokenType::Semicolon => 0,
-            _ => {
-                println!("Unhandled token type: {:?}", self.token_type);
-                todo!("Handle the rest of the token types in Token::precedence()")
-            }
+            _ => 0, // Everything else can't continue an expression.
         }
     }
 }
@@ -84,28 +131,48 @@ impl Token {
 pub struct Tokenizer<'a> {
     chars: Peekable<Chars<'a>>,
     line: i32,
+    column: i32,
+    offset: usize,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(input: &'a Source) -> Self {
         Tokenizer {
             chars: input.0.chars().peekable(),
-            line: 0,
+            line: 1,
+            column: 0,
+            offset: 0,
         }
     }
+
+    fn advance(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
 }
 
 impl<'a> Iterator for Tokenizer<'a> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_char = self.chars.next()?;
+        let (line, column, start) = (self.line, self.column, self.offset);
+        let next_char = self.advance()?;
         let token_type = match next_char {
             '(' => TokenType::LeftParen,
             ')' => TokenType::RightParen,
             '{' => TokenType::LeftBrace,
             '}' => TokenType::RightBrace,
+            '[' => TokenType::LeftBracket,
+            ']' => TokenType::RightBracket,
             ',' => TokenType::Comma,
+            ':' => TokenType::Colon,
             '.' => TokenType::Dot,
             '-' => TokenType::Minus,
             '+' => TokenType::Plus,
@@ -113,7 +180,7 @@ impl<'a> Iterator for Tokenizer<'a> {
             '*' => TokenType::Star,
             '!' => {
                 if self.chars.peek() == Some(&'=') {
-                    self.chars.next();
+                    self.advance();
                     TokenType::BangEqual
                 } else {
                     TokenType::Bang
@@ -121,7 +188,7 @@ impl<'a> Iterator for Tokenizer<'a> {
             }
             '=' => {
                 if self.chars.peek() == Some(&'=') {
-                    self.chars.next();
+                    self.advance();
                     TokenType::EqualEqual
                 } else {
                     TokenType::Equal
@@ -129,7 +196,7 @@ impl<'a> Iterator for Tokenizer<'a> {
             }
             '<' => {
                 if self.chars.peek() == Some(&'=') {
-                    self.chars.next();
+                    self.advance();
                     TokenType::LessEqual
                 } else {
                     TokenType::Less
@@ -137,7 +204,7 @@ impl<'a> Iterator for Tokenizer<'a> {
             }
             '>' => {
                 if self.chars.peek() == Some(&'=') {
-                    self.chars.next();
+                    self.advance();
                     TokenType::GreaterEqual
                 } else {
                     TokenType::Greater
@@ -145,10 +212,9 @@ impl<'a> Iterator for Tokenizer<'a> {
             }
             '/' => {
                 if let Some('/') = self.chars.peek() {
-                    while let Some('\n') = self.chars.peek() {
-                        self.chars.next();
+                    while self.chars.peek().is_some_and(|&ch| ch != '\n') {
+                        self.advance();
                     }
-                    self.line += 1;
                     return self.next();
                 } else {
                     TokenType::Slash
@@ -157,22 +223,22 @@ impl<'a> Iterator for Tokenizer<'a> {
             ' ' => return self.next(),
             '\r' => return self.next(),
             '\t' => return self.next(),
-            '\n' => {
-                self.line += 1;
-                return self.next();
-            }
+            '\n' => return self.next(),
             '"' => {
                 let mut string = String::new();
-                while let Some(&ch) = self.chars.peek() {
+                let mut terminated = false;
+                while let Some(ch) = self.advance() {
                     if ch == '"' {
-                        self.chars.next();
+                        terminated = true;
                         break;
-                    } else {
-                        string.push(ch);
-                        self.chars.next();
                     }
+                    string.push(ch);
+                }
+                if terminated {
+                    TokenType::Str(string)
+                } else {
+                    TokenType::UnterminatedStr
                 }
-                TokenType::Str(string)
             }
             _ if next_char.is_ascii_digit() => {
                 let mut number = String::new();
@@ -180,7 +246,7 @@ impl<'a> Iterator for Tokenizer<'a> {
                 while let Some(&ch) = self.chars.peek() {
                     if ch.is_ascii_digit() {
                         number.push(ch);
-                        self.chars.next();
+                        self.advance();
                     } else {
                         break;
                     }
@@ -193,36 +259,26 @@ impl<'a> Iterator for Tokenizer<'a> {
                 while let Some(&ch) = self.chars.peek() {
                     if ch.is_alphanumeric() {
                         identifier.push(ch);
-                        self.chars.next();
+                        self.advance();
                     } else {
                         break;
                     }
                 }
-                match identifier.as_str() {
-                    "and" => TokenType::And,
-                    "class" => TokenType::Class,
-                    "else" => TokenType::Else,
-                    "false" => TokenType::False,
-                    "for" => TokenType::For,
-                    "fun" => TokenType::Fun,
-                    "if" => TokenType::If,
-                    "nil" => TokenType::Nil,
-                    "or" => TokenType::Or,
-                    "print" => TokenType::Print,
-                    "return" => TokenType::Return,
-                    "super" => TokenType::Super,
-                    "this" => TokenType::This,
-                    "true" => TokenType::True,
-                    "var" => TokenType::Var,
-                    "while" => TokenType::While,
-                    _ => TokenType::Identifier(identifier),
+                match KEYWORDS.iter().find(|(keyword, _)| *keyword == identifier) {
+                    Some((_, token_type)) => token_type.clone(),
+                    None => TokenType::Identifier(identifier),
                 }
             }
             _ => TokenType::Error,
         };
         Some(Token {
             token_type,
-            line: self.line,
+            line,
+            column,
+            span: Span {
+                start,
+                end: self.offset,
+            },
         })
     }
 }
@@ -243,6 +299,13 @@ mod tokenizer_tests {
         assert!(matches!(token.token_type, TokenType::RightParen));
     }
 
+    #[test]
+    fn test_brackets() {
+        let source = Source("[]".into());
+        let kinds: Vec<_> = Tokenizer::new(&source).map(|t| t.token_type).collect();
+        assert_eq!(kinds, vec![TokenType::LeftBracket, TokenType::RightBracket]);
+    }
+
     #[test]
     fn test_left_brace() {
         let source = Source("{".into());
@@ -264,6 +327,13 @@ mod tokenizer_tests {
         assert!(matches!(token.token_type, TokenType::Comma));
     }
 
+    #[test]
+    fn test_colon() {
+        let source = Source(":".into());
+        let token = Tokenizer::new(&source).next().unwrap();
+        assert!(matches!(token.token_type, TokenType::Colon));
+    }
+
     #[test]
     fn test_dot() {
         let source = Source(".".into());
@@ -480,6 +550,13 @@ mod tokenizer_tests {
         assert!(matches!(token.token_type, TokenType::True));
     }
 
+    #[test]
+    fn test_import() {
+        let source = Source("import".into());
+        let token = Tokenizer::new(&source).next().unwrap();
+        assert!(matches!(token.token_type, TokenType::Import));
+    }
+
     #[test]
     fn test_var() {
         let source = Source("var".into());
@@ -501,6 +578,51 @@ mod tokenizer_tests {
         assert!(matches!(token.token_type, TokenType::Error));
     }
 
+    #[test]
+    fn test_unterminated_string() {
+        let source = Source("\"string".into());
+        let token = Tokenizer::new(&source).next().unwrap();
+        assert!(matches!(token.token_type, TokenType::UnterminatedStr));
+    }
+
+    #[test]
+    fn tokens_know_their_column() {
+        let source = Source("var x = \"a\nb\";\n  print x;".into());
+        let positions: Vec<_> = Tokenizer::new(&source)
+            .map(|token| (token.line, token.column))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (1, 0),
+                (1, 4),
+                (1, 6),
+                (1, 8),
+                (2, 2),
+                (3, 2),
+                (3, 8),
+                (3, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_cover_the_token_text() {
+        let source = Source("print \"é\"; // done".into());
+        let texts: Vec<_> = Tokenizer::new(&source)
+            .map(|token| &source.0[token.span.start..token.span.end])
+            .collect();
+        assert_eq!(texts, vec!["print", "\"é\"", ";"]);
+    }
+
+    #[test]
+    fn test_comment_is_skipped_until_end_of_line() {
+        let source = Source("// print (\n+".into());
+        let token = Tokenizer::new(&source).next().unwrap();
+        assert_eq!(token.token_type, TokenType::Plus);
+        assert_eq!(token.line, 2);
+    }
+
     #[test]
     fn test_multiple_tokens() {
         let source = Source("while (true) { print \"Hello, World!\"; }".into());
@@ -542,11 +664,15 @@ mod token_precedence_tests {
     fn bang_has_higher_precedence_than_star() {
         let bang_token = Token {
             token_type: TokenType::Bang,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         let star_token = Token {
             token_type: TokenType::Star,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         assert!(bang_token.precedence() > star_token.precedence());
     }
@@ -555,11 +681,15 @@ mod token_precedence_tests {
     fn star_has_higher_precedence_than_plus() {
         let star_token = Token {
             token_type: TokenType::Star,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         let plus_token = Token {
             token_type: TokenType::Plus,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         assert!(star_token.precedence() > plus_token.precedence());
     }
@@ -568,11 +698,15 @@ mod token_precedence_tests {
     fn plus_has_higher_precedence_than_greater() {
         let plus_token = Token {
             token_type: TokenType::Plus,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         let greater_token = Token {
             token_type: TokenType::Greater,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         assert!(plus_token.precedence() > greater_token.precedence());
     }
@@ -581,11 +715,15 @@ mod token_precedence_tests {
     fn greater_has_higher_precedence_than_equal_equal() {
         let greater_token = Token {
             token_type: TokenType::Greater,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         let equal_equal_token = Token {
             token_type: TokenType::EqualEqual,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         assert!(greater_token.precedence() > equal_equal_token.precedence());
     }
@@ -594,11 +732,15 @@ mod token_precedence_tests {
     fn equal_equal_has_higher_precedence_than_number() {
         let equal_equal_token = Token {
             token_type: TokenType::EqualEqual,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         let number_token = Token {
             token_type: TokenType::Number(0.0),
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         assert!(equal_equal_token.precedence() > number_token.precedence());
     }
@@ -607,11 +749,15 @@ mod token_precedence_tests {
     fn number_has_same_precedence_as_identifier() {
         let number_token = Token {
             token_type: TokenType::Number(0.0),
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         let identifier_token = Token {
             token_type: TokenType::Identifier("a".to_string()),
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         assert_eq!(number_token.precedence(), identifier_token.precedence());
     }
@@ -620,11 +766,15 @@ mod token_precedence_tests {
     fn identifier_has_higher_precedence_than_left_paren() {
         let identifier_token = Token {
             token_type: TokenType::Identifier("a".to_string()),
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         let left_paren_token = Token {
             token_type: TokenType::LeftParen,
-            line: 0,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         assert!(identifier_token.precedence() > left_paren_token.precedence());
     }
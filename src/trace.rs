@@ -0,0 +1,148 @@
+use std::{
+    io::{self, Write},
+    ops::RangeInclusive,
+};
+
+use crate::{common::OpCode, json::Json, vm::VM};
+
+/// Which instructions end up in the trace. Everything passes by default.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TraceFilter {
+    /// Only instructions executed in the function with this name.
+    pub function: Option<String>,
+    /// Only instructions compiled from these lines.
+    pub lines: Option<RangeInclusive<i32>>,
+}
+
+impl TraceFilter {
+    fn matches(&self, function: &str, line: i32) -> bool {
+        self.function.as_ref().is_none_or(|name| name == function)
+            && self
+                .lines
+                .as_ref()
+                .is_none_or(|lines| lines.contains(&line))
+    }
+}
+
+/// Parses a line range written as `10-20`, or `10` for a single line.
+pub fn parse_line_range(range: &str) -> Option<RangeInclusive<i32>> {
+    match range.split_once('-') {
+        Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+        None => {
+            let line = range.trim().parse().ok()?;
+            Some(line..=line)
+        }
+    }
+}
+
+/// Writes one JSON object per executed instruction, as JSON Lines:
+///
+/// `{"ip":3,"opcode":"Add","line":1,"function":"main.lox","depth":1,"stack":["Number(1)","Number(2)"]}`
+///
+/// The stack is the one the instruction is about to operate on. The first write error stops the
+/// trace and is kept in `error`, since failing to trace shouldn't fail the script.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    filter: TraceFilter,
+    pub error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, filter: TraceFilter) -> Self {
+        Tracer {
+            out,
+            filter,
+            error: None,
+        }
+    }
+
+    pub fn record(&mut self, vm: &VM, ip: usize, instruction: &OpCode, line: i32) {
+        let function = vm.frames.last().map_or("", |frame| frame.chunk.name());
+        if self.error.is_some() || !self.filter.matches(function, line) {
+            return;
+        }
+        let stack = vm
+            .stack
+            .iter()
+            .map(|value| Json::from(value.describe(&vm.heap)))
+            .collect();
+        let record = Json::object([
+            ("ip", Json::from(ip)),
+            ("opcode", Json::from(instruction.name())),
+            ("line", Json::from(line)),
+            ("function", Json::from(function)),
+            ("depth", Json::from(vm.frames.len())),
+            ("stack", Json::Array(stack)),
+        ]);
+        if let Err(error) = writeln!(self.out, "{}", record) {
+            self.error = Some(error);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.error.is_none() {
+            if let Err(error) = self.out.flush() {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_trace {
+    use super::*;
+    use crate::{compile::Source, test_util::SharedBuffer, vm::InterpretMode};
+
+    fn trace(source: &str, filter: TraceFilter) -> Vec<String> {
+        let buffer = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.output = Box::new(io::sink());
+        vm.tracer = Some(Tracer::new(Box::new(buffer.clone()), filter));
+        let chunk = Source(source.into())
+            .compile("test.lox", InterpretMode::Release, &mut vm.heap)
+            .unwrap();
+        vm.interpret(chunk, InterpretMode::Release);
+        let output = buffer.take_string();
+        output.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn records_every_instruction() {
+        let lines = trace("print 1 + \"a\";", TraceFilter::default());
+        // Add fails, so neither Print nor Return run
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[2],
+            r#"{"ip":2,"opcode":"Add","line":1,"function":"test.lox","depth":1,"stack":["Number(1)","String(\"a\")"]}"#
+        );
+    }
+
+    #[test]
+    fn filters_by_line_and_function() {
+        let source = "var a = 1;\na = a + 1;\nprint a;";
+        let lines = trace(
+            source,
+            TraceFilter {
+                lines: parse_line_range("2"),
+                ..TraceFilter::default()
+            },
+        );
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| line.contains(r#""line":2"#)));
+        let lines = trace(
+            source,
+            TraceFilter {
+                function: Some("other.lox".into()),
+                ..TraceFilter::default()
+            },
+        );
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn parses_line_ranges() {
+        assert_eq!(parse_line_range("3-7"), Some(3..=7));
+        assert_eq!(parse_line_range("4"), Some(4..=4));
+        assert_eq!(parse_line_range("a-b"), None);
+    }
+}
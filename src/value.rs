@@ -0,0 +1,232 @@
+use std::fmt;
+
+use crate::gc::ObjRef;
+
+/// What a `Value` holds, for matching on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+    Number(f64),
+    Boolean(bool),
+    Nil,
+    Obj(ObjRef),
+}
+
+/// A Lox value: numbers, booleans and nil are stored inline, objects as handles into the heap.
+///
+/// By default this is a tagged enum. With the `nan-boxing` feature it is packed into a single
+/// `u64` instead, the way clox does it. Both representations have the same API, so nothing outside
+/// this module knows which one it's using.
+#[derive(Clone, Copy)]
+pub struct Value(repr::Repr);
+
+impl Value {
+    pub const NIL: Value = Value(repr::NIL);
+
+    pub fn number(n: f64) -> Value {
+        Value(repr::number(n))
+    }
+
+    pub fn boolean(b: bool) -> Value {
+        Value(repr::boolean(b))
+    }
+
+    pub fn obj(obj: ObjRef) -> Value {
+        Value(repr::obj(obj))
+    }
+
+    pub fn kind(&self) -> ValueKind {
+        repr::kind(self.0)
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self.kind() {
+            ValueKind::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.kind() {
+            ValueKind::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_obj(&self) -> Option<ObjRef> {
+        match self.kind() {
+            ValueKind::Obj(obj) => Some(obj),
+            _ => None,
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.kind() == ValueKind::Nil
+    }
+}
+
+// Goes through `kind` so that NaN is unequal to itself in both representations.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind()
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.kind())
+    }
+}
+
+impl From<ValueKind> for Value {
+    fn from(kind: ValueKind) -> Self {
+        match kind {
+            ValueKind::Number(n) => Value::number(n),
+            ValueKind::Boolean(b) => Value::boolean(b),
+            ValueKind::Nil => Value::NIL,
+            ValueKind::Obj(obj) => Value::obj(obj),
+        }
+    }
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+mod repr {
+    use super::ValueKind;
+    use crate::gc::ObjRef;
+
+    pub type Repr = ValueKind;
+
+    pub const NIL: Repr = ValueKind::Nil;
+
+    pub fn number(n: f64) -> Repr {
+        ValueKind::Number(n)
+    }
+
+    pub fn boolean(b: bool) -> Repr {
+        ValueKind::Boolean(b)
+    }
+
+    pub fn obj(obj: ObjRef) -> Repr {
+        ValueKind::Obj(obj)
+    }
+
+    pub fn kind(repr: Repr) -> ValueKind {
+        repr
+    }
+}
+
+// Any double whose quiet NaN bits are all set isn't a number, and the bits below them tell the
+// other kinds apart: objects have the sign bit set and carry their heap index, nil and the booleans
+// use small tags. Arithmetic only ever produces the canonical NaN, which doesn't have all of them
+// set, and `number` canonicalizes any other NaN it's handed.
+#[cfg(feature = "nan-boxing")]
+mod repr {
+    use super::ValueKind;
+    use crate::gc::ObjRef;
+
+    pub type Repr = u64;
+
+    const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+    const QNAN: u64 = 0x7ffc_0000_0000_0000;
+
+    const TAG_NIL: u64 = 1;
+    const TAG_FALSE: u64 = 2;
+    const TAG_TRUE: u64 = 3;
+
+    pub const NIL: Repr = QNAN | TAG_NIL;
+    const FALSE: Repr = QNAN | TAG_FALSE;
+    const TRUE: Repr = QNAN | TAG_TRUE;
+
+    pub fn number(n: f64) -> Repr {
+        if n.is_nan() {
+            f64::NAN.to_bits()
+        } else {
+            n.to_bits()
+        }
+    }
+
+    pub fn boolean(b: bool) -> Repr {
+        if b {
+            TRUE
+        } else {
+            FALSE
+        }
+    }
+
+    pub fn obj(obj: ObjRef) -> Repr {
+        SIGN_BIT | QNAN | obj.index() as u64
+    }
+
+    pub fn kind(repr: Repr) -> ValueKind {
+        if repr & QNAN != QNAN {
+            ValueKind::Number(f64::from_bits(repr))
+        } else if repr & (SIGN_BIT | QNAN) == SIGN_BIT | QNAN {
+            ValueKind::Obj(ObjRef::from_index(repr as u32))
+        } else {
+            match repr {
+                NIL => ValueKind::Nil,
+                FALSE => ValueKind::Boolean(false),
+                TRUE => ValueKind::Boolean(true),
+                _ => unreachable!("Invalid value bits {:#x}", repr),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_value {
+    use super::*;
+    use crate::gc::Heap;
+
+    #[test]
+    fn round_trips_every_kind() {
+        let mut heap = Heap::new();
+        let obj = heap.intern("lox");
+        for kind in [
+            ValueKind::Number(0.0),
+            ValueKind::Number(-0.0),
+            ValueKind::Number(1.5),
+            ValueKind::Number(f64::INFINITY),
+            ValueKind::Number(f64::NEG_INFINITY),
+            ValueKind::Number(f64::MAX),
+            ValueKind::Boolean(true),
+            ValueKind::Boolean(false),
+            ValueKind::Nil,
+            ValueKind::Obj(obj),
+        ] {
+            assert_eq!(Value::from(kind).kind(), kind);
+        }
+    }
+
+    #[test]
+    fn nan_is_a_number_unequal_to_itself() {
+        let nan = Value::number(f64::NAN);
+        assert!(nan.as_number().is_some_and(f64::is_nan));
+        assert_ne!(nan, nan);
+        // A NaN whose payload looks like a boxed value is still a number
+        for bits in [
+            0x7fff_ffff_ffff_ffff,
+            0xffff_ffff_ffff_ffff,
+            0x7ffc_0000_0000_0001,
+        ] {
+            let odd_nan = Value::number(f64::from_bits(bits));
+            assert!(odd_nan.as_number().is_some_and(f64::is_nan));
+        }
+    }
+
+    #[test]
+    fn kinds_are_distinct() {
+        assert_ne!(Value::NIL, Value::boolean(false));
+        assert_ne!(Value::boolean(false), Value::number(0.0));
+        assert_ne!(Value::boolean(true), Value::boolean(false));
+        assert!(Value::NIL.is_nil());
+        assert_eq!(Value::boolean(true).as_bool(), Some(true));
+        assert_eq!(Value::number(2.0).as_bool(), None);
+    }
+
+    #[test]
+    fn is_small() {
+        assert!(std::mem::size_of::<Value>() <= 16);
+        #[cfg(feature = "nan-boxing")]
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+    }
+}
@@ -1,7 +1,24 @@
-use std::rc::Rc;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::common::{Chunk, Disassembler, Obj, OpCode, Value};
-// use std::collections::HashMap;
+use crate::{
+    common::{
+        BoundMethod, Chunk, Disassembler, NativeFn, NativeFnPtr, Obj, OpCode, Value, ValueKind,
+    },
+    convert::{FromLox, FromLoxArgs, IntoLox},
+    debug::{DebugHook, Resume},
+    gc::{Heap, ObjRef},
+    list::{self, ListRef},
+    map::{self, MapRef},
+    profile::Profiler,
+    table::Table,
+    trace::Tracer,
+};
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum InterpretMode {
@@ -10,14 +27,74 @@ pub enum InterpretMode {
 }
 
 pub struct VM {
-    pub stack: Vec<Rc<Value>>,
-    // pub global_env: HashMap<String, Value>,
+    pub stack: Vec<Value>,
+    /// Keyed by interned name.
+    pub globals: Table,
+    pub heap: Heap,
+    pub frames: Vec<CallFrame>,
+    /// Roots for the constants of the chunks being interpreted, modules they import included.
+    /// Gathered once when a chunk starts rather than at every collection.
+    constants: Vec<Value>,
+    /// Values the host needs to outlive every run, like the constants of the modules a REPL
+    /// session compiled and may run later.
+    pub roots: Vec<Value>,
+    /// The names of the modules that already ran, so importing one again does nothing.
+    pub modules: HashSet<String>,
+    /// Native methods, by the type of value they're called on and then by name.
+    pub methods: HashMap<&'static str, HashMap<String, Rc<NativeFn>>>,
+    /// Where `print` writes to.
+    pub output: Box<dyn Write>,
+    /// Number of instructions executed since the VM was created.
+    pub instructions: u64,
+    /// Sees every instruction before it runs, when set.
+    pub profiler: Option<Profiler>,
+    /// Same, but writes a record of it instead.
+    pub tracer: Option<Tracer>,
+    /// Can pause the script before any instruction, see `debug::Stepper`.
+    pub debugger: Option<Box<dyn DebugHook>>,
 }
 
-impl Disassembler for Vec<Rc<Value>> {
-    fn disassemble(&self) {
+/// A chunk being executed.
+pub struct CallFrame {
+    pub chunk: Rc<Chunk>,
+    /// The instruction being executed. Only kept up to date while a debugger is attached, the
+    /// dispatch loop works on a local copy. Frames below the top one are at the instruction they
+    /// resume from.
+    pub ip: usize,
+}
+
+impl CallFrame {
+    /// The line of the instruction at `ip`.
+    pub fn line(&self) -> i32 {
+        self.chunk.code.get(self.ip).map_or(0, |(_, line)| *line)
+    }
+}
+
+/// An error raised while executing a chunk, either by an instruction or by a native function.
+/// The VM attaches the line number when it reports the error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        RuntimeError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Disassembler for Vec<Value> {
+    fn disassemble(&self, heap: &Heap) {
         for slot in self.iter() {
-            print!("[ {} ]", slot);
+            print!("[ {} ]", slot.describe(heap));
         }
         println!();
     }
@@ -25,84 +102,401 @@ impl Disassembler for Vec<Rc<Value>> {
 
 macro_rules! val_constr {
     ($a: expr, $b: expr, <) => {
-        Value::Boolean($a < $b)
+        Value::boolean($a < $b)
     };
     ($a: expr, $b: expr, >) => {
-        Value::Boolean($a > $b)
+        Value::boolean($a > $b)
     };
     ($a: expr, $b: expr, ==) => {
-        Value::Boolean($a == $b)
+        Value::boolean($a == $b)
     };
     ($a: expr, $b: expr, $op:tt) => {
-        Value::Number($a $op $b)
+        Value::number($a $op $b)
     };
 }
 
+// The operands are already popped, so both strings are copied before allocating the result in
+// case the allocation triggers a collection.
 macro_rules! concat_strings {
-    ($a:expr, $b:expr) => {
-        Value::Obj(Obj::String(format!("{}{}", $b, $a)))
-    };
+    ($vm:ident, $a:expr, $b:expr) => {{
+        let string = format!("{}{}", $b, $a);
+        Value::obj($vm.intern(&string))
+    }};
 }
 
 macro_rules! binary_op {
     ($vm:ident, $op:tt, $line:expr) => {
         match ($vm.stack.pop(), $vm.stack.pop()) {
-            (Some(a), Some(b)) => match (&*a, &*b) {
-                (Value::Number(a), Value::Number(b)) => {
-                    $vm.stack.push(Rc::new(val_constr!(b, a, $op)));
+            (Some(a), Some(b)) => match (a.kind(), b.kind()) {
+                (ValueKind::Number(a), ValueKind::Number(b)) => {
+                    $vm.stack.push(val_constr!(b, a, $op));
                 }
-                (Value::Obj(Obj::String(a)), Value::Obj(Obj::String(b))) => {
-                    $vm.stack.push(Rc::new(concat_strings!(a, b)));
+                _ if $vm.heap.as_str(&a).is_some() && $vm.heap.as_str(&b).is_some() => {
+                    let (a, b) = ($vm.heap.as_str(&a).unwrap(), $vm.heap.as_str(&b).unwrap());
+                    let result = concat_strings!($vm, a, b);
+                    $vm.stack.push(result);
                 }
                 _ => {
-                    eprintln!("Error at line {}, Operands are incompatible", $line);
-                    return InterpretResult::RuntimeError;
+                    return $vm
+                        .runtime_error(*$line, RuntimeError::new("Operands are incompatible"));
                 }
             },
             _ => {
-                eprintln!("Error at line {}, Operands are incompatible", $line);
-                return InterpretResult::RuntimeError;
+                return $vm.runtime_error(*$line, RuntimeError::new("Operands are incompatible"));
             }
         }
     };
 }
 
+// The native behind `define_fn` and `define_method`, which converts the arguments and the result.
+fn typed_native<Args, R, F>(name: &str, arity: usize, function: F) -> NativeFn
+where
+    Args: FromLoxArgs,
+    R: IntoLox,
+    F: Fn(&mut VM, Args) -> Result<R, RuntimeError> + 'static,
+{
+    NativeFn {
+        name: name.into(),
+        arity,
+        function: Box::new(move |vm, args| {
+            let args = Args::from_lox_args(args, vm)?;
+            let result = function(vm, args)?;
+            Ok(result.into_lox(vm))
+        }),
+    }
+}
+
+fn clock_native(_vm: &mut VM, _args: ()) -> Result<f64, RuntimeError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| RuntimeError::new("System clock is set before the Unix epoch"))?;
+    Ok(now.as_secs_f64())
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl VM {
     pub fn new() -> Self {
-        VM {
+        let mut vm = VM {
             stack: vec![],
-            // global_env: HashMap::new(),
+            globals: Table::new(),
+            heap: Heap::new(),
+            frames: vec![],
+            constants: vec![],
+            roots: vec![],
+            modules: HashSet::new(),
+            methods: HashMap::new(),
+            output: Box::new(io::stdout()),
+            instructions: 0,
+            profiler: None,
+            tracer: None,
+            debugger: None,
+        };
+        vm.define_fn("clock", clock_native);
+        list::define_methods(&mut vm);
+        map::define_methods(&mut vm);
+        vm
+    }
+
+    /// Exposes a host function to Lox scripts as a global called `name`.
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFnPtr) {
+        let native = NativeFn {
+            name: name.into(),
+            arity,
+            function: Box::new(function),
+        };
+        let native = self.alloc(Obj::NativeFn(Rc::new(native)));
+        self.define_global(name, Value::obj(native));
+    }
+
+    /// Same as `define_native`, but arguments and the result are converted from and into Lox values,
+    /// so `function` can take e.g. `(f64, String)` and return `bool`.
+    pub fn define_fn<Args, R, F>(&mut self, name: &str, function: F)
+    where
+        Args: FromLoxArgs,
+        R: IntoLox,
+        F: Fn(&mut VM, Args) -> Result<R, RuntimeError> + 'static,
+    {
+        let native = typed_native(name, Args::ARITY, function);
+        let native = self.alloc(Obj::NativeFn(Rc::new(native)));
+        self.define_global(name, Value::obj(native));
+    }
+
+    /// Same as `define_fn`, but for a method of the values whose `Value::type_name` is
+    /// `type_name`. `function` gets the value the method is called on as its first argument.
+    pub fn define_method<Args, R, F>(&mut self, type_name: &'static str, name: &str, function: F)
+    where
+        Args: FromLoxArgs,
+        R: IntoLox,
+        F: Fn(&mut VM, Args) -> Result<R, RuntimeError> + 'static,
+    {
+        let arity = Args::ARITY
+            .checked_sub(1)
+            .expect("a method takes its receiver as the first argument");
+        let native = typed_native(name, arity, function);
+        self.methods
+            .entry(type_name)
+            .or_default()
+            .insert(name.into(), Rc::new(native));
+    }
+
+    pub fn get_global<T: FromLox>(&self, name: &str) -> Result<T, RuntimeError> {
+        let global = self
+            .heap
+            .find_string(name)
+            .and_then(|name| self.globals.get(&Value::obj(name)));
+        match global {
+            Some(value) => T::from_lox(value, self),
+            None => Err(RuntimeError::new(format!("Undefined variable '{}'", name))),
+        }
+    }
+
+    pub fn set_global<T: IntoLox>(&mut self, name: &str, value: T) {
+        let value = value.into_lox(self);
+        self.define_global(name, value);
+    }
+
+    // Interning the name may collect, so `value` sits on the stack until it's reachable from
+    // the globals.
+    fn define_global(&mut self, name: &str, value: Value) {
+        self.stack.push(value);
+        let name = self.intern(name);
+        self.stack.pop();
+        self.globals.insert(Value::obj(name), value);
+    }
+
+    /// The interned string holding `s`. Only allocates, and so only collects, if it's a new string.
+    pub fn intern(&mut self, s: &str) -> ObjRef {
+        if let Some(obj) = self.heap.find_string(s) {
+            return obj;
+        }
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.intern(s)
+    }
+
+    /// Allocates `obj` on the heap, collecting garbage first if the heap has grown enough.
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(obj)
+    }
+
+    /// Frees every object that isn't reachable from the stack, the globals, the constants of the
+    /// chunks being executed or the host's `roots`, and returns how many objects were freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        let mut roots: Vec<Value> = self.stack.clone();
+        roots.extend(self.globals.keys().copied());
+        roots.extend(self.globals.values().copied());
+        roots.extend_from_slice(&self.constants);
+        roots.extend_from_slice(&self.roots);
+        self.heap.collect(roots)
+    }
+
+    /// Calls `callee` with `args` and returns its result. This is the same path the `Call`
+    /// instruction takes, so it works from host code and from inside a native that was itself
+    /// called from Lox. Until the language has functions, the only callable values are natives and
+    /// native methods bound to a value, so this never re-enters the interpreter loop.
+    pub fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let base = self.stack.len();
+        self.stack.push(*callee);
+        self.stack.extend_from_slice(args);
+        let result = self.call_on_stack(args.len());
+        self.stack.truncate(base);
+        result
+    }
+
+    // The callee and its arguments are the top `arg_count + 1` stack slots. They stay on the stack
+    // for the duration of the call, so the collector still sees them.
+    fn call_on_stack(&mut self, arg_count: usize) -> Result<Value, RuntimeError> {
+        let Some(callee_slot) = self.stack.len().checked_sub(arg_count + 1) else {
+            return Err(RuntimeError::new("Nothing to call"));
+        };
+        // A bound method gets the value it was looked up on before the arguments
+        let (native, receiver) = match self.stack[callee_slot].kind() {
+            ValueKind::Obj(obj) => match self.heap.get(obj) {
+                Obj::NativeFn(native) => (Some(Rc::clone(native)), None),
+                Obj::BoundMethod(bound) => (Some(Rc::clone(&bound.method)), Some(bound.receiver)),
+                _ => (None, None),
+            },
+            _ => (None, None),
+        };
+        let Some(native) = native else {
+            return Err(RuntimeError::new(
+                "Can only call native functions and methods",
+            ));
+        };
+        if arg_count != native.arity {
+            return Err(RuntimeError::new(format!(
+                "Expected {} arguments but got {}",
+                native.arity, arg_count
+            )));
+        }
+        let mut args: Vec<Value> = receiver.into_iter().collect();
+        args.extend_from_slice(&self.stack[callee_slot + 1..]);
+        (native.function)(self, &args)
+    }
+
+    fn get_index(&self, object: Value, index: Value) -> Result<Value, RuntimeError> {
+        if let Ok(list) = ListRef::from_lox(&object, self) {
+            let items = list.items(&self.heap);
+            return Ok(items[list::position(&index, items.len(), false)?]);
+        }
+        let map = MapRef::from_lox(&object, self)
+            .map_err(|_| RuntimeError::new("Can only index lists and maps"))?;
+        match map.table(&self.heap).get(&index) {
+            Some(value) => Ok(*value),
+            None => Err(map::missing(&index, &self.heap)),
+        }
+    }
+
+    // Setting a key a map doesn't have yet adds it
+    fn set_index(&mut self, object: Value, index: Value, value: Value) -> Result<(), RuntimeError> {
+        if let Ok(list) = ListRef::from_lox(&object, self) {
+            let index = list::position(&index, list.items(&self.heap).len(), false)?;
+            list.items_mut(&mut self.heap)[index] = value;
+            return Ok(());
+        }
+        let map = MapRef::from_lox(&object, self)
+            .map_err(|_| RuntimeError::new("Can only index lists and maps"))?;
+        map::check_key(&index, &self.heap)?;
+        map.table_mut(&mut self.heap).insert(index, value);
+        self.heap.resized(map.0);
+        Ok(())
+    }
+
+    // The object stays on the stack while the bound method is allocated, so it can't be collected.
+    fn get_property(&mut self, name: ObjRef) -> Result<(), RuntimeError> {
+        let Some(&object) = self.stack.last() else {
+            return Err(RuntimeError::new("Nothing to get a property of"));
+        };
+        let name = self.heap.string(name).unwrap_or_default();
+        let type_name = object.type_name(&self.heap);
+        let method = self
+            .methods
+            .get(type_name)
+            .and_then(|methods| methods.get(name));
+        let Some(method) = method.cloned() else {
+            return Err(RuntimeError::new(format!(
+                "Undefined property '{}' on {}",
+                name, type_name
+            )));
+        };
+        let bound = self.alloc(Obj::BoundMethod(BoundMethod {
+            receiver: object,
+            method,
+        }));
+        self.stack.pop();
+        self.stack.push(Value::obj(bound));
+        Ok(())
+    }
+
+    fn undefined_variable(&self, name: ObjRef) -> RuntimeError {
+        let name = self.heap.string(name).unwrap_or_default();
+        // The globals of modules are prefixed with their path
+        let name = name.rsplit_once("::").map_or(name, |(_, name)| name);
+        RuntimeError::new(format!("Undefined variable '{}'", name))
+    }
+
+    fn runtime_error(&mut self, line: i32, error: RuntimeError) -> InterpretResult {
+        let mut report = format!("Error at line {}, {}", line, error);
+        // Lines in a module are counted from the top of its own file
+        if let Some(frame) = self.frames.last() {
+            if self.modules.contains(frame.chunk.name()) {
+                report = format!("{}: {}", frame.chunk.name(), report);
+            }
         }
+        self.stack.clear();
+        InterpretResult::RuntimeError(report)
     }
 
     pub fn interpret(&mut self, chunk: Chunk, mode: InterpretMode) -> InterpretResult {
-        // self.global_env
-        //     .insert("Let's try it".into(), Value::Boolean(false)); // Temporarily
+        let base = self.frames.len();
+        let constants = self.constants.len();
+        self.constants.extend(chunk.constants());
+        self.frames.push(CallFrame {
+            chunk: Rc::new(chunk),
+            ip: 0,
+        });
+        let result = self.run(mode);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.finish();
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush();
+        }
+        // More than one if a runtime error happened inside a module
+        self.frames.truncate(base);
+        self.constants.truncate(constants);
+        result
+    }
+
+    fn run(&mut self, mode: InterpretMode) -> InterpretResult {
+        let base = self.frames.len();
+        let mut chunk = match self.frames.last() {
+            Some(frame) => Rc::clone(&frame.chunk),
+            None => return InterpretResult::Ok,
+        };
         let mut ip = 0;
         if mode == InterpretMode::Debug {
             println!("Disassembling...");
-            chunk.disassemble();
+            chunk.disassemble(&self.heap);
             println!("Interpreting...");
         }
         loop {
             let (instruction, line) = &chunk.code[ip];
+            self.instructions += 1;
+            if let Some(mut profiler) = self.profiler.take() {
+                profiler.enter(self, instruction, *line);
+                self.profiler = Some(profiler);
+            }
+            if let Some(mut tracer) = self.tracer.take() {
+                tracer.record(self, ip, instruction, *line);
+                self.tracer = Some(tracer);
+            }
+            if let Some(mut debugger) = self.debugger.take() {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.ip = ip;
+                }
+                let resume = debugger.before_instruction(self, *line);
+                self.debugger = Some(debugger);
+                if resume == Resume::Quit {
+                    self.stack.clear();
+                    return InterpretResult::Stopped;
+                }
+            }
             if mode == InterpretMode::Debug {
                 print!("// ");
-                instruction.disassemble();
+                instruction.disassemble(&self.heap);
             }
+            // Where to carry on from when an instruction switches to another chunk
+            let mut jump: Option<(Rc<Chunk>, usize)> = None;
             use OpCode::*;
             match instruction {
+                // A module ran to its end, so its importer carries on after the import
+                Return if self.frames.len() > base => {
+                    self.frames.pop();
+                    if let Some(frame) = self.frames.last() {
+                        jump = Some((Rc::clone(&frame.chunk), frame.ip));
+                    }
+                }
                 Return => {
                     self.stack.pop();
                     return InterpretResult::Ok;
                 }
-                Constant(value) => self.stack.push(Rc::clone(value)),
-                Negate => match self.stack.pop() {
-                    Some(val) => match *val {
-                        Value::Number(n) => self.stack.push(Rc::new(Value::Number(-n))),
-                        _ => return InterpretResult::RuntimeError,
-                    },
-                    _ => return InterpretResult::RuntimeError,
+                Constant(value) => self.stack.push(*value),
+                Negate => match self.stack.pop().map(|value| value.kind()) {
+                    Some(ValueKind::Number(n)) => self.stack.push(Value::number(-n)),
+                    _ => {
+                        return self
+                            .runtime_error(*line, RuntimeError::new("Operand must be a number"))
+                    }
                 },
                 Add => {
                     binary_op!(self, +, line);
@@ -117,40 +511,47 @@ impl VM {
                     binary_op!(self, /, line);
                 }
                 Not => match self.stack.pop() {
-                    Some(val) => match *val {
-                        Value::Nil => self.stack.push(Rc::new(Value::Boolean(true))),
-                        Value::Number(x) => self.stack.push(Rc::new(Value::Boolean(x != 0.0))),
-                        Value::Boolean(value) => self.stack.push(Rc::new(Value::Boolean(!value))),
-                        Value::Obj(_) => {
-                            eprintln!("Error at line {}. Operand must be a boolean", line);
-                            return InterpretResult::RuntimeError;
+                    Some(val) => match val.kind() {
+                        ValueKind::Nil => self.stack.push(Value::boolean(true)),
+                        ValueKind::Number(x) => self.stack.push(Value::boolean(x != 0.0)),
+                        ValueKind::Boolean(value) => self.stack.push(Value::boolean(!value)),
+                        ValueKind::Obj(_) => {
+                            return self.runtime_error(
+                                *line,
+                                RuntimeError::new("Operand must be a boolean"),
+                            )
                         }
                     },
                     _ => {
-                        eprintln!("Error at line {}. Operand must be a boolean", line);
-                        return InterpretResult::RuntimeError;
+                        return self
+                            .runtime_error(*line, RuntimeError::new("Operand must be a boolean"))
                     }
                 },
                 Equal => match (self.stack.pop(), self.stack.pop()) {
-                    (Some(a), Some(b)) => match (&*a, &*b) {
-                        (Value::Number(a), Value::Number(b)) => {
-                            self.stack.push(Rc::new(Value::Boolean(a == b)))
+                    (Some(a), Some(b)) => match (a.kind(), b.kind()) {
+                        (ValueKind::Number(a), ValueKind::Number(b)) => {
+                            self.stack.push(Value::boolean(a == b))
                         }
-                        (Value::Boolean(a), Value::Boolean(b)) => {
-                            self.stack.push(Rc::new(Value::Boolean(a == b)))
+                        (ValueKind::Boolean(a), ValueKind::Boolean(b)) => {
+                            self.stack.push(Value::boolean(a == b))
                         }
-                        (Value::Nil, Value::Nil) => self.stack.push(Rc::new(Value::Boolean(true))),
-                        (Value::Obj(a), Value::Obj(b)) => {
-                            self.stack.push(Rc::new(Value::Boolean(a == b)));
+                        (ValueKind::Nil, ValueKind::Nil) => self.stack.push(Value::boolean(true)),
+                        // Strings are interned, so every object compares by identity
+                        (ValueKind::Obj(a), ValueKind::Obj(b)) => {
+                            self.stack.push(Value::boolean(a == b))
                         }
                         _ => {
-                            eprintln!("Error at line {}, Operands must be of the same type", line);
-                            return InterpretResult::RuntimeError;
+                            return self.runtime_error(
+                                *line,
+                                RuntimeError::new("Operands must be of the same type"),
+                            )
                         }
                     },
                     _ => {
-                        eprintln!("Error at line {}, Operands must be of the same type", line);
-                        return InterpretResult::RuntimeError;
+                        return self.runtime_error(
+                            *line,
+                            RuntimeError::new("Operands must be of the same type"),
+                        )
                     }
                 },
                 Greater => {
@@ -160,7 +561,15 @@ impl VM {
                     binary_op!(self, <, line);
                 }
                 Print => match self.stack.pop() {
-                    Some(val) => println!("{}", val.print_lox()),
+                    Some(val) => {
+                        let text = val.print_lox(&self.heap);
+                        if let Err(error) = writeln!(self.output, "{}", text) {
+                            return self.runtime_error(
+                                *line,
+                                RuntimeError::new(format!("Failed to print: {}", error)),
+                            );
+                        }
+                    }
                     None => eprintln!(
                         "Error at line {}, nothing to print, the stack is empty",
                         line
@@ -169,9 +578,96 @@ impl VM {
                 Pop => {
                     self.stack.pop();
                 }
+                GetGlobal(name) => match self.globals.get(&Value::obj(*name)) {
+                    Some(value) => self.stack.push(*value),
+                    None => return self.runtime_error(*line, self.undefined_variable(*name)),
+                },
+                DefineGlobal(name) => match self.stack.pop() {
+                    Some(value) => {
+                        self.globals.insert(Value::obj(*name), value);
+                    }
+                    None => {
+                        return self.runtime_error(*line, RuntimeError::new("Nothing to assign"))
+                    }
+                },
+                SetGlobal(name) => {
+                    match (self.stack.last(), self.globals.get_mut(&Value::obj(*name))) {
+                        (Some(value), Some(global)) => *global = *value,
+                        _ => return self.runtime_error(*line, self.undefined_variable(*name)),
+                    }
+                }
+                Call(arg_count) => match self.call_on_stack(*arg_count) {
+                    Ok(result) => {
+                        self.stack.truncate(self.stack.len() - arg_count - 1);
+                        self.stack.push(result);
+                    }
+                    Err(error) => return self.runtime_error(*line, error),
+                },
+                BuildList(count) => {
+                    // Still on the stack while the list is allocated
+                    let items = self.stack[self.stack.len() - count..].to_vec();
+                    let list = self.alloc(Obj::List(items));
+                    self.stack.truncate(self.stack.len() - count);
+                    self.stack.push(Value::obj(list));
+                }
+                BuildMap(count) => {
+                    let entries = &self.stack[self.stack.len() - count * 2..];
+                    let mut table = Table::new();
+                    for pair in entries.chunks(2) {
+                        if let Err(error) = map::check_key(&pair[0], &self.heap) {
+                            return self.runtime_error(*line, error);
+                        }
+                        table.insert(pair[0], pair[1]);
+                    }
+                    // The entries are still on the stack while the map is allocated
+                    let map = self.alloc(Obj::Map(table));
+                    self.stack.truncate(self.stack.len() - count * 2);
+                    self.stack.push(Value::obj(map));
+                }
+                GetIndex => match (self.stack.pop(), self.stack.pop()) {
+                    (Some(index), Some(object)) => match self.get_index(object, index) {
+                        Ok(value) => self.stack.push(value),
+                        Err(error) => return self.runtime_error(*line, error),
+                    },
+                    _ => return self.runtime_error(*line, RuntimeError::new("Nothing to index")),
+                },
+                SetIndex => match (self.stack.pop(), self.stack.pop(), self.stack.pop()) {
+                    (Some(value), Some(index), Some(object)) => {
+                        if let Err(error) = self.set_index(object, index, value) {
+                            return self.runtime_error(*line, error);
+                        }
+                        self.stack.push(value);
+                    }
+                    _ => return self.runtime_error(*line, RuntimeError::new("Nothing to index")),
+                },
+                GetProperty(name) => {
+                    if let Err(error) = self.get_property(*name) {
+                        return self.runtime_error(*line, error);
+                    }
+                }
+                Import(module) => {
+                    if self.modules.insert(module.name().to_string()) {
+                        if let Some(frame) = self.frames.last_mut() {
+                            frame.ip = ip + 1;
+                        }
+                        if mode == InterpretMode::Debug {
+                            module.disassemble(&self.heap);
+                        }
+                        self.frames.push(CallFrame {
+                            chunk: Rc::clone(module),
+                            ip: 0,
+                        });
+                        jump = Some((Rc::clone(module), 0));
+                    }
+                }
             }
             if mode == InterpretMode::Debug {
-                self.stack.disassemble();
+                self.stack.disassemble(&self.heap);
+            }
+            if let Some((next, next_ip)) = jump {
+                chunk = next;
+                ip = next_ip;
+                continue;
             }
             ip += 1;
         }
@@ -180,5 +676,283 @@ impl VM {
 
 pub enum InterpretResult {
     Ok,
-    RuntimeError,
+    /// The script failed. Holds the report, which is left to the caller to show.
+    RuntimeError(String),
+    /// A debugger stopped the script before it finished.
+    Stopped,
+}
+
+#[cfg(test)]
+mod test_natives {
+    use super::*;
+    use crate::compile::Source;
+
+    fn run(vm: &mut VM, source: &str) -> InterpretResult {
+        let chunk = Source(source.into())
+            .compile("test", InterpretMode::Release, &mut vm.heap)
+            .unwrap();
+        vm.interpret(chunk, InterpretMode::Release)
+    }
+
+    fn remember(vm: &mut VM, (value,): (Value,)) -> Result<(), RuntimeError> {
+        vm.set_global("remembered", value);
+        Ok(())
+    }
+
+    fn fail(_vm: &mut VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+        Err(RuntimeError::new("native failure"))
+    }
+
+    #[test]
+    fn native_receives_arguments() {
+        let mut vm = VM::new();
+        vm.define_fn("remember", remember);
+        assert!(matches!(
+            run(&mut vm, "remember(1 + 2);"),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global("remembered"), Ok(Value::number(3.0)));
+    }
+
+    #[test]
+    fn clock_returns_a_number() {
+        let mut vm = VM::new();
+        vm.define_fn("remember", remember);
+        assert!(matches!(
+            run(&mut vm, "remember(clock());"),
+            InterpretResult::Ok
+        ));
+        assert!(vm.get_global::<f64>("remembered").is_ok());
+    }
+
+    #[test]
+    fn wrong_arity_is_a_runtime_error() {
+        let mut vm = VM::new();
+        assert!(matches!(
+            run(&mut vm, "clock(1);"),
+            InterpretResult::RuntimeError(_)
+        ));
+    }
+
+    #[test]
+    fn typed_native_converts_arguments_and_result() {
+        let mut vm = VM::new();
+        vm.define_fn("repeat", |_, (s, n): (String, usize)| Ok(s.repeat(n)));
+        vm.define_fn("remember", remember);
+        assert!(matches!(
+            run(&mut vm, "remember(repeat(\"ab\", 3));"),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global::<String>("remembered"), Ok("ababab".into()));
+    }
+
+    #[test]
+    fn typed_native_rejects_wrong_types() {
+        let mut vm = VM::new();
+        vm.define_fn("repeat", |_, (s, n): (String, usize)| Ok(s.repeat(n)));
+        assert!(matches!(
+            run(&mut vm, "repeat(3, \"ab\");"),
+            InterpretResult::RuntimeError(_)
+        ));
+    }
+
+    #[test]
+    fn host_can_call_a_global() {
+        let mut vm = VM::new();
+        vm.define_fn("twice", |_, (n,): (f64,)| Ok(n * 2.0));
+        let twice = vm.get_global("twice").unwrap();
+        assert_eq!(
+            vm.call(&twice, &[Value::number(21.0)]),
+            Ok(Value::number(42.0))
+        );
+        assert_eq!(
+            vm.call(&Value::NIL, &[]),
+            Err(RuntimeError::new(
+                "Can only call native functions and methods"
+            ))
+        );
+    }
+
+    #[test]
+    fn native_can_call_back_into_the_vm() {
+        let mut vm = VM::new();
+        vm.define_fn("twice", |_, (n,): (f64,)| Ok(n * 2.0));
+        vm.define_fn("apply", |vm, (f, arg): (Value, Value)| vm.call(&f, &[arg]));
+        vm.define_fn("remember", remember);
+        assert!(matches!(
+            run(&mut vm, "remember(1 + apply(twice, 20));"),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global::<f64>("remembered"), Ok(41.0));
+        assert!(matches!(
+            run(&mut vm, "apply(clock, 1);"),
+            InterpretResult::RuntimeError(_)
+        ));
+    }
+
+    #[test]
+    fn native_errors_are_runtime_errors() {
+        let mut vm = VM::new();
+        vm.define_native("fail", 0, fail);
+        match run(&mut vm, "fail();") {
+            InterpretResult::RuntimeError(report) => {
+                assert_eq!(report, "Error at line 1, native failure")
+            }
+            _ => panic!("expected a runtime error"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "a method takes its receiver as the first argument")]
+    fn methods_need_a_receiver() {
+        VM::new().define_method("list", "nothing", |_, ()| Ok(()));
+    }
+
+    #[test]
+    fn globals_survive_between_chunks() {
+        let mut vm = VM::new();
+        assert!(matches!(run(&mut vm, "var a = 1;"), InterpretResult::Ok));
+        assert!(matches!(run(&mut vm, "a = a + 1;"), InterpretResult::Ok));
+        assert_eq!(vm.get_global::<f64>("a"), Ok(2.0));
+    }
+
+    #[test]
+    fn assigning_an_undefined_global_is_a_runtime_error() {
+        let mut vm = VM::new();
+        assert!(matches!(
+            run(&mut vm, "a = 1;"),
+            InterpretResult::RuntimeError(_)
+        ));
+    }
+
+    #[test]
+    fn runtime_error_resets_the_stack() {
+        let mut vm = VM::new();
+        assert!(matches!(
+            run(&mut vm, "1 + (2 + -nil);"),
+            InterpretResult::RuntimeError(_)
+        ));
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn calling_a_number_is_a_runtime_error() {
+        let mut vm = VM::new();
+        assert!(matches!(
+            run(&mut vm, "1();"),
+            InterpretResult::RuntimeError(_)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test_gc {
+    use super::*;
+    use crate::compile::Source;
+
+    fn run(vm: &mut VM, source: &str) -> InterpretResult {
+        let chunk = Source(source.into())
+            .compile("test", InterpretMode::Release, &mut vm.heap)
+            .unwrap();
+        vm.interpret(chunk, InterpretMode::Release)
+    }
+
+    #[test]
+    fn stress_gc_keeps_reachable_strings() {
+        let mut vm = VM::new();
+        vm.heap.stress = true;
+        assert!(matches!(
+            run(&mut vm, "var a = \"x\" + \"y\"; var b = a + \"z\" + a;"),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global::<String>("b"), Ok("xyzxy".into()));
+    }
+
+    #[test]
+    fn stress_gc_keeps_native_arguments() {
+        let mut vm = VM::new();
+        vm.heap.stress = true;
+        vm.define_fn("join", |vm, (a, b): (Value, Value)| {
+            // Allocating here would sweep `a` and `b` if they weren't rooted
+            let separator = vm.intern(", ");
+            let a = vm.heap.as_str(&a).unwrap_or_default();
+            let b = vm.heap.as_str(&b).unwrap_or_default();
+            let separator = vm.heap.string(separator).unwrap_or_default();
+            Ok(format!("{}{}{}", a, separator, b))
+        });
+        assert!(matches!(
+            run(&mut vm, "var j = join(\"a\" + \"b\", \"c\" + \"d\");"),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global::<String>("j"), Ok("ab, cd".into()));
+    }
+
+    #[test]
+    fn constants_survive_collections_while_their_chunk_runs() {
+        let mut vm = VM::new();
+        vm.define_fn("collect", |vm, ()| Ok(vm.collect_garbage() as f64));
+        assert!(matches!(
+            run(&mut vm, "collect();\nvar s = \"constant\";"),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global::<String>("s"), Ok("constant".into()));
+        assert!(vm.constants.is_empty());
+    }
+
+    #[test]
+    fn unreachable_strings_are_freed() {
+        let mut vm = VM::new();
+        let baseline = vm.heap.objects();
+        run(
+            &mut vm,
+            "\"a\" + \"b\"; \"c\" + \"d\"; var kept = \"e\" + \"f\";",
+        );
+        vm.collect_garbage();
+        // The name `kept` and its value
+        assert_eq!(vm.heap.objects(), baseline + 2);
+        assert_eq!(vm.get_global::<String>("kept"), Ok("ef".into()));
+    }
+
+    #[test]
+    fn arithmetic_does_not_allocate() {
+        let mut vm = VM::new();
+        run(&mut vm, "var x = 1;");
+        let allocated = vm.heap.bytes_allocated();
+        assert!(matches!(
+            run(&mut vm, "x = -(x + 2) * 3 / 4 - 5; x = x > 1 == !(x < 2);"),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.heap.bytes_allocated(), allocated);
+    }
+
+    #[test]
+    fn equal_strings_are_the_same_object() {
+        let mut vm = VM::new();
+        assert!(matches!(
+            run(
+                &mut vm,
+                "var a = \"ab\"; var b = \"a\" + \"b\"; var same = a == b;"
+            ),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global("same"), Ok(true));
+        assert_eq!(vm.get_global::<Value>("a"), vm.get_global::<Value>("b"));
+    }
+
+    #[test]
+    fn stress_gc_keeps_global_names() {
+        let mut vm = VM::new();
+        vm.heap.stress = true;
+        assert!(matches!(
+            run(&mut vm, "var answer = 42;"),
+            InterpretResult::Ok
+        ));
+        // Only the globals table refers to the name now that the chunk is done
+        vm.collect_garbage();
+        assert!(matches!(
+            run(&mut vm, "answer = answer + 1;"),
+            InterpretResult::Ok
+        ));
+        assert_eq!(vm.get_global("answer"), Ok(43.0));
+    }
 }